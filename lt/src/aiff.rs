@@ -0,0 +1,356 @@
+//! Interface to AIFF and AIFF-C file format reading.
+//!
+//! An AIFF file is a "FORM" chunk holding a form type, "AIFF" or "AIFC", and a list of
+//! chunks. They're like RIFF chunks, but the sizes are big endian. The format is in the
+//! COMM chunk, whose sample rate is an 80 bit extended float. AIFF-C adds a compression
+//! type, though most files are uncompressed: big endian ("NONE"), little endian ("sowt")
+//! or float ("fl32", "fl64") samples.
+//! The metadata is in the NAME, AUTH, ANNO and "(c) " text chunks, and in an ID3v2 tag
+//! in an "ID3 " chunk.
+//!
+//! References:
+//!
+//! [https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/AIFF/AIFF.html](https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/AIFF/AIFF.html)
+use crate::file::{Decoder, FileFormat};
+use crate::mp3;
+use crate::riff::{self, Chunk};
+use crate::track;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// AIFF and AIFF-C file format reader.
+///
+/// Implements the `Decoder` trait, so has `get_track`.
+#[derive(Default, Debug)]
+pub struct Aiff {
+    /// True for AIFF-C.
+    pub compressed: bool,
+}
+
+/// Id of the chunk that holds the whole file.
+pub const FORM: &str = "FORM";
+/// Form type of AIFF files.
+pub const AIFF: &str = "AIFF";
+/// Form type of AIFF-C files.
+pub const AIFC: &str = "AIFC";
+/// Id of the format chunk.
+pub const COMM: &str = "COMM";
+/// Id of the chunk with the sample data.
+pub const SSND: &str = "SSND";
+/// Id of the chunk with an ID3v2 tag. Some writers use "id3 ".
+pub const ID3: &str = "ID3 ";
+// Size of a chunk's id and size.
+const CHUNK_HEADER_SIZE: u64 = 8;
+// Size of the COMM chunk of AIFF files, AIFF-C adds the compression.
+const COMM_SIZE: usize = 18;
+
+/// Names of the text chunks.
+pub const TEXT_CHUNKS: [(&str, &str); 4] = [
+    ("NAME", "Name"),
+    ("AUTH", "Author"),
+    ("ANNO", "Annotation"),
+    ("(c) ", "Copyright"),
+];
+
+/// Read the first 12 bytes of the buffer and return an `Aiff` struct
+/// if this buffer has the right identifier,
+/// return None otherwise.
+pub fn identify(b: &[u8]) -> Option<FileFormat> {
+    if b.len() < 12 || &b[0..4] != FORM.as_bytes() {
+        return None;
+    }
+    match &b[8..12] {
+        t if t == AIFF.as_bytes() => Some(FileFormat::AIFF(Aiff { compressed: false })),
+        t if t == AIFC.as_bytes() => Some(FileFormat::AIFF(Aiff { compressed: true })),
+        _ => None,
+    }
+}
+
+/// The fields of a COMM chunk.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comm {
+    pub channels: u16,
+    /// Samples of each channel.
+    pub sample_frames: u32,
+    pub sample_size: u16,
+    pub sample_rate: f64,
+    /// The AIFF-C compression type, "NONE" for AIFF.
+    pub compression: String,
+    /// The AIFF-C name of the compression, e.g. "not compressed".
+    pub compression_name: String,
+}
+
+impl Comm {
+    /// Parse the data of a COMM chunk. The compression is only there for AIFF-C.
+    pub fn parse(b: &[u8], compressed: bool) -> Result<Comm, Box<dyn Error>> {
+        if b.len() < COMM_SIZE || (compressed && b.len() < COMM_SIZE + 4) {
+            return Err(format!("COMM chunk is too short: {} bytes.", b.len()).into());
+        }
+        let mut c = Comm {
+            channels: u16::from_be_bytes([b[0], b[1]]),
+            sample_frames: u32::from_be_bytes([b[2], b[3], b[4], b[5]]),
+            sample_size: u16::from_be_bytes([b[6], b[7]]),
+            sample_rate: extended(&b[8..18]),
+            compression: "NONE".to_string(),
+            compression_name: String::new(),
+        };
+        if compressed {
+            c.compression = String::from_utf8_lossy(&b[18..22]).to_string();
+            // A Pascal string: a length then the text.
+            if let Some(&n) = b.get(22) {
+                let end = (23 + n as usize).min(b.len());
+                c.compression_name = String::from_utf8_lossy(&b[23..end]).to_string();
+            }
+        }
+        Ok(c)
+    }
+
+    /// Integer or float samples, or compressed for any other compression type,
+    /// e.g. "ulaw", "alaw" or "ima4".
+    pub fn sample_format(&self) -> track::SampleFormat {
+        match self.compression.as_str() {
+            "NONE" | "twos" | "sowt" | "in24" | "in32" | "raw " => track::SampleFormat::Int,
+            "fl32" | "FL32" | "fl64" | "FL64" => track::SampleFormat::Float,
+            _ => track::SampleFormat::Compressed,
+        }
+    }
+}
+
+/// The value of an 80 bit IEEE 754 extended precision float, as used for AIFF sample rates.
+pub fn extended(b: &[u8]) -> f64 {
+    let exponent = i32::from(u16::from_be_bytes([b[0], b[1]]) & 0x7fff);
+    let mut m = [0; 8];
+    m.copy_from_slice(&b[2..10]);
+    let mantissa = u64::from_be_bytes(m);
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    // The mantissa has an explicit integer bit, so it's mantissa / 2^63.
+    let v = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if b[0] & 0x80 != 0 {
+        -v
+    } else {
+        v
+    }
+}
+
+/// Read the chunks of an AIFF file.
+pub fn read_chunks(mut r: impl Read + Seek) -> Result<Vec<Chunk>, Box<dyn Error>> {
+    let mut header = [0; 12];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header)?;
+    if &header[0..4] != FORM.as_bytes() {
+        return Err("Not an AIFF file.".into());
+    }
+    let len = r.seek(SeekFrom::End(0))?;
+    let mut chunks = Vec::new();
+    let mut offset = header.len() as u64;
+    let mut b = [0; CHUNK_HEADER_SIZE as usize];
+    while offset + CHUNK_HEADER_SIZE <= len {
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut b)?;
+        let c = Chunk {
+            id: String::from_utf8_lossy(&b[0..4]).to_string(),
+            offset,
+            size: u64::from(u32::from_be_bytes([b[4], b[5], b[6], b[7]])),
+        };
+        // Chunks are padded to an even size.
+        offset = c.data_offset() + c.size + (c.size & 1);
+        chunks.push(c);
+    }
+    Ok(chunks)
+}
+
+/// Name of a text chunk id, or the id if it isn't one.
+pub fn text_name(id: &str) -> &str {
+    TEXT_CHUNKS
+        .iter()
+        .find(|(i, _)| *i == id)
+        .map_or(id, |(_, n)| n)
+}
+
+const FORMAT_NAME: &str = "aiff";
+
+impl Decoder for Aiff {
+    /// Return the name of this format; "aiff".
+    fn name(&self) -> &str {
+        FORMAT_NAME
+    }
+
+    /// Create a track with as much information as you have from the file.
+    /// The title and artist come from the ID3 chunk, or the NAME and AUTH chunks
+    /// if it doesn't have them.
+    fn get_track(
+        &mut self,
+        mut r: impl Read + Seek,
+    ) -> Result<Option<track::Track>, Box<dyn Error>> {
+        let chunks = read_chunks(&mut r)?;
+        let comm = match chunks.iter().find(|c| c.id == COMM) {
+            Some(c) => Comm::parse(&riff::read_data(&mut r, c)?, self.compressed)?,
+            None => return Err("No COMM chunk.".into()),
+        };
+        let mut tk = track::Track {
+            file_format: Some(FORMAT_NAME.to_string()),
+            ..Default::default()
+        };
+        tk.format = Some(track::CodecFormat::PCM(track::PCMFormat {
+            sample_rate: comm.sample_rate.round() as u32,
+            channels: comm.channels as u8,
+            bits_per_sample: comm.sample_size,
+            total_samples: u64::from(comm.sample_frames),
+            sample_format: comm.sample_format(),
+            ..Default::default()
+        }));
+
+        let mut md = track::AiffMetadata {
+            compression: if self.compressed {
+                Some((comm.compression.clone(), comm.compression_name.clone()))
+            } else {
+                None
+            },
+            ..Default::default()
+        };
+        for c in &chunks {
+            let id = c.id.as_str();
+            if TEXT_CHUNKS.iter().any(|(t, _)| *t == id) {
+                let b = riff::read_data(&mut r, c)?;
+                let text = String::from_utf8_lossy(&b)
+                    .trim_end_matches('\0')
+                    .to_string();
+                md.text.push((id.to_string(), text));
+            } else if id == ID3 || id == "id3 " {
+                // A bad tag doesn't make the audio unreadable, so the track is kept without it.
+                let b = riff::read_data(&mut r, c)?;
+                match id3::Tag::read_from(&b[..]) {
+                    Ok(tag) => md.id3 = Some(mp3::read_tag(&mut tk, &tag)),
+                    Err(e) => eprintln!("Skipping the bad ID3 chunk at offset {}: {}", c.offset, e),
+                }
+            }
+        }
+        if tk.title.is_none() {
+            tk.title = md.text("NAME").map(|s| s.to_string());
+        }
+        if tk.artist.is_none() {
+            tk.artist = md.text("AUTH").map(|s| s.to_string());
+        }
+        md.chunks = chunks;
+        tk.metadata = Some(track::FormatMetadata::Aiff(md));
+        Ok(Some(tk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut b = id.as_bytes().to_vec();
+        b.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        b.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            b.push(0);
+        }
+        b
+    }
+
+    // 44100 and 48000 as 80 bit extended floats.
+    const RATE_44100: [u8; 10] = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+    const RATE_48000: [u8; 10] = [0x40, 0x0e, 0xbb, 0x80, 0, 0, 0, 0, 0, 0];
+
+    #[test]
+    fn test_extended() {
+        assert_eq!(extended(&RATE_44100), 44_100.0);
+        assert_eq!(extended(&RATE_48000), 48_000.0);
+        assert_eq!(extended(&[0; 10]), 0.0);
+        let mut b = RATE_48000;
+        b[0] |= 0x80;
+        assert_eq!(extended(&b), -48_000.0);
+    }
+
+    #[test]
+    fn test_aiff() {
+        let comm = [&[0, 2, 0, 0, 0x10, 0, 0, 24][..], &RATE_44100].concat();
+        let body = [
+            &b"AIFF"[..],
+            &chunk(COMM, &comm),
+            &chunk("NAME", b"Take 3"),
+            &chunk("ANNO", b"Room mics\0"),
+            &chunk(SSND, &[0; 9]),
+        ]
+        .concat();
+        let file = [&b"FORM"[..], &(body.len() as u32).to_be_bytes(), &body].concat();
+
+        let mut a = match identify(&file) {
+            Some(FileFormat::AIFF(a)) => a,
+            _ => panic!("not an AIFF file"),
+        };
+        assert!(!a.compressed);
+        let ids: Vec<String> = read_chunks(Cursor::new(&file))
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, vec![COMM, "NAME", "ANNO", SSND]);
+
+        let tk = a.get_track(Cursor::new(&file)).unwrap().unwrap();
+        assert_eq!(tk.title.as_deref(), Some("Take 3"));
+        match &tk.format {
+            Some(track::CodecFormat::PCM(f)) => {
+                assert_eq!(
+                    (f.sample_rate, f.channels, f.bits_per_sample),
+                    (44_100, 2, 24)
+                );
+                assert_eq!(f.total_samples, 4096);
+            }
+            _ => panic!("not PCM"),
+        }
+        match &tk.metadata {
+            Some(track::FormatMetadata::Aiff(md)) => {
+                assert_eq!(md.text("ANNO"), Some("Room mics"));
+                assert_eq!(md.compression, None);
+            }
+            _ => panic!("no AIFF metadata"),
+        }
+        assert!(identify(b"FORM\0\0\0\0ILBM").is_none());
+    }
+
+    #[test]
+    fn test_aifc_comm() {
+        let comm = [
+            &[0, 1, 0, 0, 0, 10, 0, 32][..],
+            &RATE_48000,
+            b"fl32",
+            b"\x0c32-bit float\0",
+        ]
+        .concat();
+        let c = Comm::parse(&comm, true).unwrap();
+        assert_eq!(c.sample_rate, 48_000.0);
+        assert_eq!(c.compression, "fl32");
+        assert_eq!(c.compression_name, "32-bit float");
+        assert_eq!(c.sample_format(), track::SampleFormat::Float);
+
+        // Compressed files are still tracks.
+        let ulaw = [&comm[..18], b"ulaw", b"\x08uLaw 2:1"].concat();
+        assert_eq!(
+            Comm::parse(&ulaw, true).unwrap().sample_format(),
+            track::SampleFormat::Compressed
+        );
+        let body = [&b"AIFC"[..], &chunk(COMM, &ulaw), &chunk(SSND, &[0; 8])].concat();
+        let file = [&b"FORM"[..], &(body.len() as u32).to_be_bytes(), &body].concat();
+        let tk = Aiff { compressed: true }
+            .get_track(Cursor::new(&file))
+            .unwrap()
+            .unwrap();
+        match &tk.metadata {
+            Some(track::FormatMetadata::Aiff(md)) => assert_eq!(
+                md.compression,
+                Some(("ulaw".to_string(), "uLaw 2:1".to_string()))
+            ),
+            _ => panic!("no AIFF metadata"),
+        }
+
+        assert!(Comm::parse(&comm[..18], true).is_err());
+        assert_eq!(Comm::parse(&comm[..18], false).unwrap().compression, "NONE");
+    }
+}
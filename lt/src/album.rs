@@ -20,6 +20,18 @@ pub struct Album {
     pub disk_total: Option<u32>,
 }
 
+impl Album {
+    /// Tracks that need gapless information to run into each other without a gap,
+    /// see `Track::needs_gapless`, but don't have it.
+    /// Only matters if the album is a continuous set, like a live show or a mix.
+    pub fn missing_gapless(&self) -> Vec<&track::Track> {
+        self.tracks
+            .iter()
+            .filter(|t| t.needs_gapless() && t.gapless().is_none())
+            .collect()
+    }
+}
+
 // TODO(jdr): Learn to use lifetimes and get rid of these tk.* clones?
 // Assume that there is only one album in a directory.
 /// Capture all the tracks in a directory and treat them as a group.
@@ -75,3 +87,48 @@ pub fn album_from_tracks(tks: Vec<track::Track>) -> Album {
     }
     album
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn mp3(title: &str, gapless: Option<track::Gapless>) -> track::Track {
+        track::Track {
+            title: Some(title.to_string()),
+            format: Some(track::CodecFormat::MPEG3(track::MPEG3Format {
+                bitrate: 320,
+                sample_rate: 44_100,
+                version: track::MPVersion::MPEG1,
+                layer: track::MP3Layer::Layer3,
+                duration: Duration::from_secs(60),
+                frames: None,
+                vbr: false,
+                gapless,
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_missing_gapless() {
+        let g = track::Gapless {
+            delay: 576,
+            padding: 1000,
+            valid_samples: 44_100 * 59,
+            source: track::GaplessSource::Lame,
+        };
+        let flac = track::Track {
+            format: Some(track::CodecFormat::PCM(Default::default())),
+            ..Default::default()
+        };
+        let album = album_from_tracks(vec![mp3("One", Some(g)), mp3("Two", None), flac]);
+        let missing = album.missing_gapless();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].title.as_deref(), Some("Two"));
+        assert_eq!(
+            album.tracks[0].playable_duration(),
+            Some(Duration::from_secs(59))
+        );
+    }
+}
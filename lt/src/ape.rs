@@ -0,0 +1,201 @@
+//! APEv2 tags, found at the end of MP3 files (before any ID3v1 tag) and in
+//! Monkey's Audio, WavPack and Musepack files.
+//!
+//! A tag is a list of items, each a key and a UTF-8, binary or link value,
+//! followed by a 32 byte footer giving the size of the tag and the number of items.
+//! There may be a copy of the footer before the items as a header.
+//!
+//! Reference: [https://wiki.hydrogenaud.io/index.php?title=APEv2_specification](https://wiki.hydrogenaud.io/index.php?title=APEv2_specification)
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Starts the header and footer.
+pub const PREAMBLE: &[u8] = b"APETAGEX";
+/// Size of the header and footer.
+pub const FOOTER_SIZE: u64 = 32;
+// Footer flag for a header before the items.
+const HAS_HEADER: u32 = 0x8000_0000;
+// Item flags for the type of value.
+const ITEM_TYPE: u32 = 0x06;
+const ITEM_BINARY: u32 = 0x02;
+const ITEM_LINK: u32 = 0x04;
+
+/// The value of an item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApeValue {
+    /// UTF-8 text, there may be more than one value.
+    Text(Vec<String>),
+    Binary(Vec<u8>),
+    /// A URL, or a file name, of where the value is.
+    Link(String),
+}
+
+impl fmt::Display for ApeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApeValue::Text(v) => f.write_str(&v.join("; ")),
+            ApeValue::Binary(b) => write!(f, "{} bytes", b.len()),
+            ApeValue::Link(l) => write!(f, "Link: {}", l),
+        }
+    }
+}
+
+/// An item in a tag, keys are ASCII and compared without regard to case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApeItem {
+    pub key: String,
+    pub value: ApeValue,
+}
+
+/// What's in an APEv2 (or APEv1) tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApeTag {
+    /// 2000 for APEv2, 1000 for APEv1.
+    pub version: u32,
+    /// File offset of the tag, its header if it has one, otherwise its first item.
+    pub offset: u64,
+    pub items: Vec<ApeItem>,
+}
+
+impl ApeTag {
+    /// The first text value of the item with key, e.g. "Title" or "Track".
+    pub fn text(&self, key: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .filter(|i| i.key.eq_ignore_ascii_case(key))
+            .find_map(|i| match &i.value {
+                ApeValue::Text(v) => v.first().map(|s| s.as_str()),
+                _ => None,
+            })
+    }
+}
+
+/// Read the tag whose footer ends at end, the end of the file or
+/// the start of an ID3v1 tag. Returns None if there isn't one.
+pub fn read(mut r: impl Read + Seek, end: u64) -> Result<Option<ApeTag>, Box<dyn Error>> {
+    if end < FOOTER_SIZE {
+        return Ok(None);
+    }
+    let mut footer = [0; FOOTER_SIZE as usize];
+    r.seek(SeekFrom::Start(end - FOOTER_SIZE))?;
+    r.read_exact(&mut footer)?;
+    if !footer.starts_with(PREAMBLE) {
+        return Ok(None);
+    }
+    let version = le_u32(&footer[8..12]);
+    // The size includes the footer, but not the header.
+    let size = le_u32(&footer[12..16]) as u64;
+    let count = le_u32(&footer[16..20]);
+    let flags = le_u32(&footer[20..24]);
+    if size < FOOTER_SIZE || size > end {
+        return Err(format!("APE tag size {} doesn't fit before offset {}", size, end).into());
+    }
+
+    let start = end - size;
+    let mut items = vec![0; (size - FOOTER_SIZE) as usize];
+    r.seek(SeekFrom::Start(start))?;
+    r.read_exact(&mut items)?;
+    let items = parse_items(&items, count)
+        .ok_or_else(|| format!("APE tag items at offset {} are cut short", start))?;
+    let header = if flags & HAS_HEADER != 0 {
+        FOOTER_SIZE
+    } else {
+        0
+    };
+    Ok(Some(ApeTag {
+        version,
+        offset: start.saturating_sub(header),
+        items,
+    }))
+}
+
+/// Parse count items from b, each a value size, flags, a key ending in 0 and the value.
+pub fn parse_items(mut b: &[u8], count: u32) -> Option<Vec<ApeItem>> {
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let size = le_u32(b.get(..4)?) as usize;
+        let flags = le_u32(b.get(4..8)?);
+        b = &b[8..];
+        let key_len = b.iter().position(|&c| c == 0)?;
+        let key = String::from_utf8_lossy(&b[..key_len]).to_string();
+        b = &b[key_len + 1..];
+        let value = b.get(..size)?;
+        b = &b[size..];
+        items.push(ApeItem {
+            key,
+            value: match flags & ITEM_TYPE {
+                ITEM_BINARY => ApeValue::Binary(value.to_vec()),
+                ITEM_LINK => ApeValue::Link(String::from_utf8_lossy(value).to_string()),
+                _ => ApeValue::Text(
+                    String::from_utf8_lossy(value)
+                        .split('\0')
+                        .map(|s| s.to_string())
+                        .collect(),
+                ),
+            },
+        });
+    }
+    Some(items)
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn item(key: &str, flags: u32, value: &[u8]) -> Vec<u8> {
+        let mut b = (value.len() as u32).to_le_bytes().to_vec();
+        b.extend_from_slice(&flags.to_le_bytes());
+        b.extend_from_slice(key.as_bytes());
+        b.push(0);
+        b.extend_from_slice(value);
+        b
+    }
+
+    fn footer(size: usize, count: u32, flags: u32) -> Vec<u8> {
+        let mut b = PREAMBLE.to_vec();
+        for v in &[2000, size as u32 + FOOTER_SIZE as u32, count, flags] {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        b.resize(FOOTER_SIZE as usize, 0);
+        b
+    }
+
+    #[test]
+    fn test_ape() {
+        let items = [
+            item("Title", 0, b"A Title"),
+            item("Artist", 0, b"One\0Two"),
+            item("Cover Art (Front)", ITEM_BINARY, &[1, 2, 3]),
+        ]
+        .concat();
+        let mut file = vec![0xFF; 100];
+        file.extend_from_slice(&footer(items.len(), 3, HAS_HEADER));
+        file.extend_from_slice(&items);
+        file.extend_from_slice(&footer(items.len(), 3, HAS_HEADER));
+        let end = file.len() as u64;
+
+        let tag = read(Cursor::new(&file), end).unwrap().unwrap();
+        assert_eq!(tag.version, 2000);
+        assert_eq!(tag.offset, 100);
+        assert_eq!(tag.items.len(), 3);
+        assert_eq!(tag.text("TITLE"), Some("A Title"));
+        assert_eq!(
+            tag.items[1].value,
+            ApeValue::Text(vec!["One".to_string(), "Two".to_string()])
+        );
+        assert_eq!(tag.items[2].value.to_string(), "3 bytes");
+        assert_eq!(tag.text("Cover Art (Front)"), None);
+
+        // Nothing there, and a tag that claims more items than it has.
+        assert_eq!(read(Cursor::new(&file), 100).unwrap(), None);
+        let mut bad = items.clone();
+        bad.extend_from_slice(&footer(items.len(), 4, 0));
+        assert!(read(Cursor::new(&bad), bad.len() as u64).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! Embedded artwork (cover art etc.) found in the tags of audio files.
+//!
+//! FLAC picture blocks, ID3 APIC frames and MPEG4 covr items all end up as an
+//! `Artwork` on the track. The picture types are the ones from the ID3 APIC frame,
+//! which FLAC uses too. Dimensions come from the image header when the tag
+//! doesn't give them, the image itself is never decoded.
+//!
+//! Covers can also be embedded: a cover image from next to the tracks
+//! is written into FLAC, MP3 and MPEG4 files that don't have one, or that have one
+//! that's too big.
+use crate::file;
+use crate::file::FileFormat;
+use crate::flac;
+use crate::mp3;
+use crate::mpeg4::write::{write_items, IlstItem};
+use crate::track::Track;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Picture type for front covers, also used for MPEG4 covr images
+/// which have no type.
+pub const FRONT_COVER: u8 = 3;
+
+/// Image files, next to the tracks of an album, taken as its cover. In order of preference.
+pub const COVER_FILES: [&str; 4] = ["cover.jpg", "folder.jpg", "cover.png", "folder.png"];
+
+// Names of the picture types, indexed by type.
+const PICTURE_TYPES: [&str; 21] = [
+    "Other",
+    "File Icon",
+    "Other File Icon",
+    "Front Cover",
+    "Back Cover",
+    "Leaflet",
+    "Media",
+    "Lead Artist",
+    "Artist",
+    "Conductor",
+    "Band",
+    "Composer",
+    "Lyricist",
+    "Recording Location",
+    "During Recording",
+    "During Performance",
+    "Screen Capture",
+    "Bright Coloured Fish",
+    "Illustration",
+    "Band Logotype",
+    "Publisher Logotype",
+];
+
+pub const JPEG_MIME: &str = "image/jpeg";
+pub const PNG_MIME: &str = "image/png";
+const GIF_MIME: &str = "image/gif";
+const BMP_MIME: &str = "image/bmp";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SOI: &[u8] = &[0xFF, 0xD8];
+
+/// An image embedded in a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artwork {
+    /// ID3 APIC picture type, e.g. 3 for the front cover.
+    pub picture_type: u8,
+    pub mime_type: String,
+    pub description: String,
+    /// Width and height in pixels, 0 if not known.
+    pub width: u32,
+    pub height: u32,
+    /// The image file.
+    pub data: Vec<u8>,
+}
+
+impl Artwork {
+    /// Artwork for the image in data. An empty mime_type is guessed from the image,
+    /// the dimensions are read from its header.
+    pub fn new(picture_type: u8, mime_type: &str, description: &str, data: Vec<u8>) -> Artwork {
+        let mime_type = if mime_type.is_empty() {
+            sniff_mime_type(&data).unwrap_or_default()
+        } else {
+            mime_type
+        };
+        let (width, height) = dimensions(&data).unwrap_or((0, 0));
+        Artwork {
+            picture_type,
+            mime_type: mime_type.to_string(),
+            description: description.to_string(),
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Size of the image in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Name of the picture type, e.g. Front Cover.
+    pub fn type_name(&self) -> &str {
+        PICTURE_TYPES
+            .get(self.picture_type as usize)
+            .copied()
+            .unwrap_or("Unknown")
+    }
+
+    /// File extension for the image, without the dot.
+    pub fn extension(&self) -> &str {
+        match self.mime_type.to_ascii_lowercase().as_str() {
+            JPEG_MIME | "image/jpg" => "jpg",
+            PNG_MIME => "png",
+            GIF_MIME => "gif",
+            BMP_MIME => "bmp",
+            _ => "bin",
+        }
+    }
+}
+
+/// Front Cover image/jpeg 600x600 45123 bytes
+impl fmt::Display for Artwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.type_name(), self.mime_type)?;
+        if self.width > 0 && self.height > 0 {
+            write!(f, " {}x{}", self.width, self.height)?;
+        }
+        write!(f, " {} bytes", self.size())
+    }
+}
+
+/// Mime type of the image, from the first few bytes.
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(JPEG_SOI) {
+        Some(JPEG_MIME)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        Some(PNG_MIME)
+    } else if data.starts_with(b"GIF8") {
+        Some(GIF_MIME)
+    } else if data.starts_with(b"BM") {
+        Some(BMP_MIME)
+    } else {
+        None
+    }
+}
+
+/// Width and height in pixels of a JPEG, PNG or GIF image, from its header.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes([*data.get(i)?, *data.get(i + 1)?]) as u32);
+    if data.starts_with(PNG_SIGNATURE) {
+        // The IHDR chunk comes first: length, type, width, height.
+        let b = data.get(16..24)?;
+        Some((
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            u32::from_be_bytes([b[4], b[5], b[6], b[7]]),
+        ))
+    } else if data.starts_with(b"GIF8") {
+        let b = data.get(6..10)?;
+        Some((
+            u16::from_le_bytes([b[0], b[1]]) as u32,
+            u16::from_le_bytes([b[2], b[3]]) as u32,
+        ))
+    } else if data.starts_with(JPEG_SOI) {
+        // Walk the segments to the start of frame, which has the size.
+        let mut i = 2;
+        while *data.get(i)? == 0xFF {
+            let marker = *data.get(i + 1)?;
+            match marker {
+                // Fill bytes.
+                0xFF => i += 1,
+                // Start of frame, but not DHT, JPG or DAC which share the range.
+                0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                    return Some((be16(i + 7)?, be16(i + 5)?));
+                }
+                _ => i += 2 + be16(i + 2)? as usize,
+            }
+        }
+        None
+    } else {
+        None
+    }
+}
+
+/// Writes the artwork of a track to files next to it, named after the track
+/// and the picture type. Returns the paths written.
+pub fn extract(tk: &Track) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dir = tk.path.parent().unwrap_or_else(|| Path::new(""));
+    let stem = tk
+        .path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut written: Vec<PathBuf> = Vec::new();
+    for (i, a) in tk.artwork.iter().enumerate() {
+        // Number the second and later pictures of the same type.
+        let n = tk.artwork[..i]
+            .iter()
+            .filter(|b| b.picture_type == a.picture_type)
+            .count();
+        let suffix = if n > 0 {
+            format!(" {}", n + 1)
+        } else {
+            String::new()
+        };
+        let p = dir.join(format!(
+            "{} - {}{}.{}",
+            stem,
+            a.type_name(),
+            suffix,
+            a.extension()
+        ));
+        fs::write(&p, &a.data)?;
+        written.push(p);
+    }
+    Ok(written)
+}
+
+/// The cover image in dir, one of `COVER_FILES` matched without regard to case.
+pub fn find_cover(dir: &Path) -> Result<Option<PathBuf>, io::Error> {
+    let mut found: Vec<(usize, PathBuf)> = Vec::new();
+    for e in dir.read_dir()? {
+        let p = e?.path();
+        let name = match p.file_name() {
+            Some(n) => n.to_string_lossy().to_lowercase(),
+            None => continue,
+        };
+        if let Some(i) = COVER_FILES.iter().position(|c| *c == name) {
+            found.push((i, p));
+        }
+    }
+    Ok(found.into_iter().min().map(|(_, p)| p))
+}
+
+/// Read an image file as a front cover.
+pub fn read_cover(p: &Path) -> Result<Artwork, Box<dyn Error>> {
+    let data = fs::read(p)?;
+    if sniff_mime_type(&data) != Some(JPEG_MIME) && sniff_mime_type(&data) != Some(PNG_MIME) {
+        return Err(format!("{} is not a JPEG or PNG image.", p.display()).into());
+    }
+    Ok(Artwork::new(FRONT_COVER, "", "", data))
+}
+
+/// What embedding a cover does to a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverAction {
+    /// The track has no artwork, the cover is added.
+    Add,
+    /// The track's cover is too big and is replaced by the smaller one.
+    Replace,
+    /// The track's artwork stays as it is.
+    Keep,
+}
+
+impl fmt::Display for CoverAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CoverAction::Add => "Add",
+            CoverAction::Replace => "Replace",
+            CoverAction::Keep => "Keep",
+        })
+    }
+}
+
+/// The cover change for a single track.
+#[derive(Debug)]
+pub struct CoverUpdate {
+    pub path: PathBuf,
+    /// The track's current cover, if it has one.
+    pub current: Option<Artwork>,
+    pub action: CoverAction,
+}
+
+/// What to do with the artwork a track already has, when embedding cover.
+/// Front covers bigger than max_bytes are replaced if cover is smaller,
+/// anything else is kept. Other pictures don't count, as writing a cover
+/// only replaces the front cover.
+pub fn cover_action(current: &[Artwork], cover: &Artwork, max_bytes: Option<usize>) -> CoverAction {
+    let old = match current_cover(current) {
+        Some(a) => a,
+        None => return CoverAction::Add,
+    };
+    match max_bytes {
+        Some(max) if old.size() > max && cover.size() < old.size() && old.data != cover.data => {
+            CoverAction::Replace
+        }
+        _ => CoverAction::Keep,
+    }
+}
+
+// The front cover.
+fn current_cover(art: &[Artwork]) -> Option<&Artwork> {
+    art.iter().find(|a| a.picture_type == FRONT_COVER)
+}
+
+/// Work out the cover changes for tracks.
+/// Tracks that aren't FLAC, MP3 or MPEG4 files are reported and skipped.
+pub fn plan_covers(
+    tracks: &[Track],
+    cover: &Artwork,
+    max_bytes: Option<usize>,
+) -> Result<Vec<CoverUpdate>, Box<dyn Error>> {
+    let mut us = Vec::new();
+    for tk in tracks {
+        match file::identify(File::open(&tk.path)?)? {
+            Some(FileFormat::Flac(_)) | Some(FileFormat::MP3(_)) | Some(FileFormat::MPEG4(_)) => (),
+            _ => {
+                eprintln!(
+                    "Covers can only be embedded in FLAC, MP3 and MPEG-4 files: {}",
+                    tk.path.display()
+                );
+                continue;
+            }
+        }
+        us.push(CoverUpdate {
+            path: tk.path.clone(),
+            current: current_cover(&tk.artwork).cloned(),
+            action: cover_action(&tk.artwork, cover, max_bytes),
+        });
+    }
+    Ok(us)
+}
+
+/// Write cover into each file whose update adds or replaces it.
+pub fn embed_covers(us: &[CoverUpdate], cover: &Artwork) -> Result<(), Box<dyn Error>> {
+    for u in us {
+        if u.action != CoverAction::Keep {
+            write_cover(&u.path, cover)?;
+        }
+    }
+    Ok(())
+}
+
+/// Set the front cover of a FLAC, MP3 or MPEG4 file.
+pub fn write_cover(p: &Path, cover: &Artwork) -> Result<(), Box<dyn Error>> {
+    match file::identify(File::open(p)?)? {
+        Some(FileFormat::Flac(_)) => flac::write_cover(p, cover),
+        Some(FileFormat::MP3(_)) => mp3::write_cover(p, cover),
+        Some(FileFormat::MPEG4(_)) => write_items(p, &[IlstItem::Cover(cover.data.clone())]),
+        _ => Err(format!("Can't write a cover to {}.", p.display()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dimensions() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&600u32.to_be_bytes());
+        png.extend_from_slice(&400u32.to_be_bytes());
+        let a = Artwork::new(FRONT_COVER, "", "", png);
+        assert_eq!(a.mime_type, PNG_MIME);
+        assert_eq!((a.width, a.height), (600, 400));
+        assert_eq!(a.extension(), "png");
+        assert_eq!(a.type_name(), "Front Cover");
+
+        // An APP0 segment then a baseline start of frame.
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0x2C, 0x02, 0x58,
+        ];
+        assert_eq!(dimensions(&jpeg), Some((600, 300)));
+        assert_eq!(sniff_mime_type(&jpeg), Some(JPEG_MIME));
+
+        let gif = b"GIF89a\x58\x02\x2C\x01";
+        assert_eq!(dimensions(gif), Some((600, 300)));
+
+        assert_eq!(dimensions(b"not an image"), None);
+        assert_eq!(dimensions(&jpeg[0..10]), None);
+    }
+
+    #[test]
+    fn test_cover_action() {
+        let image = |t, n| Artwork::new(t, JPEG_MIME, "", [vec![0xFF, 0xD8], vec![0; n]].concat());
+        let cover = image(FRONT_COVER, 2);
+        let big = [image(FRONT_COVER, 98)];
+
+        assert_eq!(cover_action(&[], &cover, None), CoverAction::Add);
+        assert_eq!(cover_action(&big, &cover, None), CoverAction::Keep);
+        assert_eq!(cover_action(&big, &cover, Some(50)), CoverAction::Replace);
+        assert_eq!(cover_action(&big, &cover, Some(200)), CoverAction::Keep);
+        // The replacement has to be smaller.
+        assert_eq!(
+            cover_action(&[image(FRONT_COVER, 2)], &big[0], Some(2)),
+            CoverAction::Keep
+        );
+        // Only front covers count, an oversized back cover isn't replaced.
+        assert_eq!(
+            cover_action(&[image(4, 98)], &cover, None),
+            CoverAction::Add
+        );
+        assert_eq!(
+            cover_action(&[image(4, 98)], &cover, Some(50)),
+            CoverAction::Add
+        );
+    }
+
+    #[test]
+    fn test_cover_round_trip() {
+        use crate::file::Decoder;
+        use crate::mpeg4::write::update_items;
+        use crate::mpeg4::Mpeg4;
+
+        let buf = fs::read("test_audio/16 The End.m4a").unwrap();
+        let jpeg = vec![
+            0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x20, 0xFF, 0xD9,
+        ];
+        let out = update_items(&buf, &[IlstItem::Cover(jpeg.clone())]).unwrap();
+        let tk = Mpeg4::default()
+            .get_track(io::Cursor::new(out))
+            .unwrap()
+            .unwrap();
+        assert_eq!(tk.artwork.len(), 1);
+        assert_eq!(tk.artwork[0].mime_type, JPEG_MIME);
+        assert_eq!((tk.artwork[0].width, tk.artwork[0].height), (32, 16));
+        assert_eq!(tk.artwork[0].data, jpeg);
+    }
+}
@@ -6,6 +6,7 @@ extern crate linefeed;
 extern crate structopt;
 
 use crate::display;
+use crate::mp3;
 use crate::run::{readloop, send_directory, PromptUpdate};
 use clap::AppSettings;
 
@@ -73,21 +74,164 @@ pub enum InteractiveCommands {
 
   /// List files in the provided directory.
   #[structopt(name = "list", alias = "ls")]
-  List(FilePath),
+  List(ListPath),
 
   /// Print details of a track.
   #[structopt(name = "describe")]
   Describe(FilePath),
 
+  /// Print the chapters of a track.
+  #[structopt(name = "chapters")]
+  Chapters(FilePath),
+
+  /// Check the tracks of a continuous album, like a live show, have
+  /// the encoder delay and padding needed to play without gaps.
+  #[structopt(name = "gapless")]
+  Gapless(FilePath),
+
+  /// Work with the artwork embedded in tracks.
+  #[structopt(name = "art")]
+  Art(ArtCommands),
+
+  /// Change the ID3 tags of MP3 files.
+  #[structopt(name = "tag")]
+  Tag(TagCommands),
+
   /// Print out the meta structure of the file (e.g. all Mpeg4 boxe types and sizes in order).
   #[structopt(name = "structure")]
   Structure(FilePath),
 
+  /// Check files against the .ffp and .md5 manifests found in a directory.
+  #[structopt(name = "verify")]
+  Verify(FilePath),
+
+  /// Write .ffp and .md5 manifests for the files in a directory.
+  #[structopt(name = "manifest")]
+  Manifest(DryRunPath),
+
+  /// Write the tags from album.yaml and tracklist.yaml to the FLAC files in a directory.
+  #[structopt(name = "apply")]
+  Apply(DryRunPath),
+
+  /// Write an album.yaml for a directory, filled in from the tags on its tracks.
+  #[structopt(name = "template")]
+  Template(DryRunPath),
+
   /// Change working directory.
   #[structopt(name = "cd")]
   CD(FilePath),
 }
 
+/// Commands for the artwork embedded in tracks.
+#[derive(StructOpt, Debug)]
+pub enum ArtCommands {
+  /// Write the artwork of a track, or each track in a directory, to image files beside it.
+  #[structopt(name = "extract")]
+  Extract(FilePath),
+
+  /// Embed a cover image in the tracks of a directory that don't have one.
+  #[structopt(name = "embed")]
+  Embed(EmbedPath),
+}
+
+/// Commands for the ID3 tags of MP3 files. Each works on a file,
+/// or each MP3 file in a directory.
+#[derive(StructOpt, Debug)]
+pub enum TagCommands {
+  /// Set a text frame, e.g. TPE2, to a value.
+  #[structopt(name = "set")]
+  Set(TagSetPath),
+
+  /// Remove every frame with an id, e.g. COMM.
+  #[structopt(name = "remove")]
+  Remove(TagRemovePath),
+
+  /// Convert tags to one ID3v2 version, removing extra ID3v2 tags and ID3v1 tags.
+  #[structopt(name = "normalize")]
+  Normalize(NormalizePath),
+}
+
+/// Abstracts the arguments for setting a frame.
+#[derive(StructOpt, Debug)]
+pub struct TagSetPath {
+  pub frame: String,
+  pub value: String,
+  /// Print what would be written without changing any files.
+  #[structopt(short = "n", long = "dry-run")]
+  pub dry_run: bool,
+  pub path: Vec<String>,
+}
+
+impl TagSetPath {
+  /// Get a PathBuf for this TagSetPath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+}
+
+/// Abstracts the arguments for removing a frame.
+#[derive(StructOpt, Debug)]
+pub struct TagRemovePath {
+  pub frame: String,
+  /// Print what would be written without changing any files.
+  #[structopt(short = "n", long = "dry-run")]
+  pub dry_run: bool,
+  pub path: Vec<String>,
+}
+
+impl TagRemovePath {
+  /// Get a PathBuf for this TagRemovePath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+}
+
+/// Abstracts the arguments for normalizing tags.
+#[derive(StructOpt, Debug)]
+pub struct NormalizePath {
+  /// ID3v2 major version to write, 3 or 4. Version 3 is the one most players read.
+  #[structopt(short = "v", long = "version", default_value = "3")]
+  pub version: u8,
+  /// Print what would be written without changing any files.
+  #[structopt(short = "n", long = "dry-run")]
+  pub dry_run: bool,
+  pub path: Vec<String>,
+}
+
+impl NormalizePath {
+  /// Get a PathBuf for this NormalizePath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+}
+
+/// Abstracts the arguments for embedding a cover in the tracks of a directory.
+#[derive(StructOpt, Debug)]
+pub struct EmbedPath {
+  /// Image to embed, instead of the cover.jpg or folder.jpg found in the directory.
+  #[structopt(short = "i", long = "image")]
+  pub image: Option<String>,
+  /// Replace covers bigger than this many bytes with the image, if it's smaller.
+  #[structopt(short = "m", long = "max-bytes")]
+  pub max_bytes: Option<usize>,
+  /// Print what would be written without changing any files.
+  #[structopt(short = "n", long = "dry-run")]
+  pub dry_run: bool,
+  pub path: Vec<String>,
+}
+
+impl EmbedPath {
+  /// Get a PathBuf for this EmbedPath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+
+  /// Get a PathBuf for the image, if one was given.
+  pub fn image(&self) -> Option<PathBuf> {
+    self.image.as_ref().map(|i| strings_to_pathbuf(std::slice::from_ref(i)))
+  }
+}
+
 /// Abstracts an command argument for files,
 /// providing a meahanism to get a PathBuf.
 #[derive(StructOpt, Debug)]
@@ -106,6 +250,40 @@ impl FilePath {
   }
 }
 
+/// Abstracts a FilePath for the list command, with an option to
+/// write the track titles found in an info file.
+#[derive(StructOpt, Debug)]
+pub struct ListPath {
+  /// Write the titles from the directory's info .txt file to the tracks.
+  #[structopt(short = "w", long = "write-titles")]
+  pub write_titles: bool,
+  pub path: Vec<String>,
+}
+
+impl ListPath {
+  /// Get a PathBuf for this ListPath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+}
+
+/// Abstracts a FilePath for commands that write to files,
+/// with an option to show what would be written instead.
+#[derive(StructOpt, Debug)]
+pub struct DryRunPath {
+  /// Print what would be written without changing any files.
+  #[structopt(short = "n", long = "dry-run")]
+  pub dry_run: bool,
+  pub path: Vec<String>,
+}
+
+impl DryRunPath {
+  /// Get a PathBuf for this DryRunPath.
+  pub fn path(&self) -> PathBuf {
+    strings_to_pathbuf(&self.path)
+  }
+}
+
 /// Abstracts the find argument to get both the find specification e.g. /moov/udta/ilst/trkn,
 /// and the FilePath for file to be operating on.
 #[derive(StructOpt, Debug)]
@@ -180,7 +358,7 @@ pub fn parse_interactive(
   match cmd {
     InteractiveCommands::List(p) => {
       // display::list_files(PathBuf::from(p.to_string()))?;
-      display::list_files(p.path())?;
+      display::list_files(p.path(), p.write_titles)?;
       Ok(ParseResult::Complete)
     }
     InteractiveCommands::Describe(p) => {
@@ -188,6 +366,37 @@ pub fn parse_interactive(
       display::describe_file(p.path())?;
       Ok(ParseResult::Complete)
     }
+    InteractiveCommands::Chapters(p) => {
+      display::list_chapters(p.path())?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Gapless(p) => {
+      display::check_gapless(p.path())?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Art(ArtCommands::Extract(p)) => {
+      display::extract_art(p.path())?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Art(ArtCommands::Embed(p)) => {
+      display::embed_art(p.path(), p.image(), p.max_bytes, p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Tag(TagCommands::Set(p)) => {
+      let edit = mp3::TagEdit::Set(p.frame.clone(), p.value.clone());
+      display::edit_tags(p.path(), edit, p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Tag(TagCommands::Remove(p)) => {
+      let edit = mp3::TagEdit::Remove(p.frame.clone());
+      display::edit_tags(p.path(), edit, p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Tag(TagCommands::Normalize(p)) => {
+      let edit = mp3::TagEdit::Normalize(p.version);
+      display::edit_tags(p.path(), edit, p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
     InteractiveCommands::Structure(p) => {
       // display::display_structure(PathBuf::from(p.to_string()))?;
       display::display_structure(p.path())?;
@@ -197,6 +406,22 @@ pub fn parse_interactive(
       display::display_find_path(p.file_path(), p.find_path)?;
       Ok(ParseResult::Complete)
     }
+    InteractiveCommands::Verify(p) => {
+      display::verify_files(p.path())?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Manifest(p) => {
+      display::write_manifests(p.path(), p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Apply(p) => {
+      display::apply_spec(p.path(), p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
+    InteractiveCommands::Template(p) => {
+      display::write_spec_template(p.path(), p.dry_run)?;
+      Ok(ParseResult::Complete)
+    }
     InteractiveCommands::CD(p) => {
       // let p = PathBuf::from(p.to_string());
       let p = p.path();
@@ -0,0 +1,601 @@
+//! CUE sheets, the track lists that come with recordings kept as one big file,
+//! and the virtual tracks made from them.
+//!
+//! A sheet is lines of commands. Those before the first TRACK are for the
+//! whole recording, the rest for the TRACK above them:
+//! ```text
+//! REM DATE 1977
+//! PERFORMER "Grateful Dead"
+//! TITLE "Barton Hall"
+//! FILE "gd77-05-08.flac" WAVE
+//!   TRACK 01 AUDIO
+//!     TITLE "Minglewood Blues"
+//!     INDEX 01 00:00:00
+//!   TRACK 02 AUDIO
+//!     TITLE "Loser"
+//!     INDEX 00 05:48:12
+//!     INDEX 01 05:50:00
+//! ```
+//! Times are MM:SS:FF, with 75 frames a second. A track is played from its INDEX 01,
+//! an INDEX 00 before it marks the start of the gap between tracks.
+//!
+//! FLAC files can carry a CUESHEET block instead, which has the track offsets
+//! but no titles. Taggers often put the text of the sheet in a CUESHEET comment as well.
+//!
+//! Reference: [https://wiki.hydrogenaud.io/index.php?title=Cue_sheet](https://wiki.hydrogenaud.io/index.php?title=Cue_sheet)
+use crate::track::{CodecFormat, FormatMetadata, MPEG3Format, MPEG4AudioFormat, PCMFormat, Track};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Extension of cue sheet files.
+pub const EXTENSION: &str = "cue";
+/// Vorbis comment some taggers keep the text of a cue sheet in.
+pub const CUESHEET_COMMENT: &str = "CUESHEET";
+// Frames a second in cue sheet times.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// A track in a cue sheet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueTrack {
+    pub number: u32,
+    /// Name of the FILE the track is in.
+    pub file: String,
+    /// AUDIO for audio, anything else is data.
+    pub kind: String,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub isrc: Option<String>,
+    /// Frames of silence to play before the track, that aren't in the file.
+    pub pregap: Option<u64>,
+    /// (index number, frames from the start of the file).
+    pub indexes: Vec<(u32, u64)>,
+    /// REM comments, (name, value).
+    pub rems: Vec<(String, String)>,
+}
+
+impl CueTrack {
+    /// Frames from the start of the file to where the track is played from,
+    /// its INDEX 01, or its first index if it doesn't have one.
+    pub fn start(&self) -> Option<u64> {
+        self.indexes
+            .iter()
+            .find(|(n, _)| *n == 1)
+            .or_else(|| self.indexes.first())
+            .map(|(_, f)| *f)
+    }
+}
+
+/// What's in a cue sheet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Media catalog number.
+    pub catalog: Option<String>,
+    /// REM comments, (name, value), e.g. ("DATE", "1977").
+    pub rems: Vec<(String, String)>,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueSheet {
+    /// The names of the files the tracks are in, in order.
+    pub fn files(&self) -> Vec<&str> {
+        let mut fs: Vec<&str> = Vec::new();
+        for t in &self.tracks {
+            if !fs.contains(&t.file.as_str()) {
+                fs.push(&t.file);
+            }
+        }
+        fs
+    }
+
+    /// The value of a REM comment, e.g. "DATE" or "GENRE".
+    pub fn rem(&self, name: &str) -> Option<&str> {
+        self.rems
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Where a virtual track from a cue sheet is in its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl Span {
+    /// Length of the track.
+    pub fn duration(&self) -> Duration {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Parse the text of a cue sheet.
+pub fn parse(s: &str) -> Result<CueSheet, Box<dyn Error>> {
+    let mut cs = CueSheet::default();
+    let mut file: Option<String> = None;
+    for (i, line) in s.lines().enumerate() {
+        let err = |m: &str| format!("Cue sheet line {}: {}: {}", i + 1, m, line.trim());
+        let args = split_line(line);
+        let (cmd, args) = match args.split_first() {
+            Some((c, a)) => (c.to_ascii_uppercase(), a),
+            None => continue,
+        };
+        let arg = |n: usize| args.get(n).cloned().ok_or_else(|| err("missing argument"));
+        let track = cs.tracks.last_mut();
+        match (cmd.as_str(), track) {
+            ("FILE", _) => file = Some(arg(0)?),
+            ("TRACK", _) => {
+                let number = arg(0)?.parse().map_err(|_| err("bad track number"))?;
+                cs.tracks.push(CueTrack {
+                    number,
+                    file: file.clone().ok_or_else(|| err("TRACK before FILE"))?,
+                    kind: args.get(1).cloned().unwrap_or_default(),
+                    ..Default::default()
+                });
+            }
+            ("INDEX", Some(t)) => {
+                let n = arg(0)?.parse().map_err(|_| err("bad index number"))?;
+                let f = parse_time(&arg(1)?).ok_or_else(|| err("bad time"))?;
+                t.indexes.push((n, f));
+            }
+            ("PREGAP", Some(t)) => {
+                t.pregap = Some(parse_time(&arg(0)?).ok_or_else(|| err("bad time"))?)
+            }
+            ("TITLE", Some(t)) => t.title = Some(arg(0)?),
+            ("TITLE", None) => cs.title = Some(arg(0)?),
+            ("PERFORMER", Some(t)) => t.performer = Some(arg(0)?),
+            ("PERFORMER", None) => cs.performer = Some(arg(0)?),
+            ("ISRC", Some(t)) => t.isrc = Some(arg(0)?),
+            ("CATALOG", _) => cs.catalog = Some(arg(0)?),
+            ("REM", t) => {
+                let rem = (arg(0)?.to_ascii_uppercase(), args[1..].join(" "));
+                match t {
+                    Some(t) => t.rems.push(rem),
+                    None => cs.rems.push(rem),
+                }
+            }
+            // FLAGS, POSTGAP, SONGWRITER, CDTEXTFILE and anything unknown.
+            _ => (),
+        }
+    }
+    Ok(cs)
+}
+
+/// Read a cue sheet file.
+pub fn read(p: &Path) -> Result<CueSheet, Box<dyn Error>> {
+    let b = fs::read(p)?;
+    // Older sheets are often in Windows-1252 rather than UTF-8, which is close
+    // enough to ISO-8859-1 for titles.
+    let s = match String::from_utf8(b) {
+        Ok(s) => s,
+        Err(e) => e.into_bytes().iter().map(|&c| c as char).collect(),
+    };
+    parse(s.trim_start_matches('\u{feff}'))
+}
+
+// The words of a line, with quoted strings as one word without the quotes.
+fn split_line(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let (word, after) = match rest.strip_prefix('"') {
+            Some(r) => match r.find('"') {
+                Some(end) => (&r[..end], &r[end + 1..]),
+                None => (r, ""),
+            },
+            None => match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            },
+        };
+        words.push(word.to_string());
+        rest = after.trim_start();
+    }
+    words
+}
+
+// Frames from an MM:SS:FF time.
+fn parse_time(s: &str) -> Option<u64> {
+    let fs: Vec<u64> = s
+        .split(':')
+        .map(|f| f.parse().ok())
+        .collect::<Option<_>>()?;
+    match fs[..] {
+        // A minutes field too big to count in frames is as bad as one that isn't a number.
+        [m, s, f] if s < 60 && f < FRAMES_PER_SECOND => m
+            .checked_mul(60)?
+            .checked_add(s)?
+            .checked_mul(FRAMES_PER_SECOND)?
+            .checked_add(f),
+        _ => None,
+    }
+}
+
+fn frames_duration(frames: u64) -> Duration {
+    Duration::from_secs(frames / FRAMES_PER_SECOND)
+        + Duration::from_nanos(frames % FRAMES_PER_SECOND * 1_000_000_000 / FRAMES_PER_SECOND)
+}
+
+// Length of the audio of a track, from its format.
+fn format_duration(f: &CodecFormat) -> Duration {
+    match f {
+        CodecFormat::PCM(f) => f.duration(),
+        CodecFormat::MPEG3(f) => f.duration,
+        CodecFormat::MPEG4(f) => f.duration(),
+    }
+}
+
+// A format like f for part of its audio.
+fn span_format(f: &CodecFormat, span: &Span) -> CodecFormat {
+    let samples = |rate: f64| (span.duration().as_secs_f64() * rate).round() as u64;
+    match f {
+        CodecFormat::PCM(f) => CodecFormat::PCM(PCMFormat {
+            total_samples: samples(f.sample_rate as f64),
+            ..f.clone()
+        }),
+        CodecFormat::MPEG3(f) => CodecFormat::MPEG3(MPEG3Format {
+            bitrate: f.bitrate,
+            sample_rate: f.sample_rate,
+            duration: span.duration(),
+            version: f.version,
+            layer: f.layer,
+            frames: None,
+            vbr: f.vbr,
+            gapless: None,
+        }),
+        CodecFormat::MPEG4(f) => CodecFormat::MPEG4(MPEG4AudioFormat {
+            sr: f.sr,
+            channels: f.channels,
+            bits_per_sample: f.bits_per_sample,
+            total_samples: samples(f.sample_rate()),
+            codec: f.codec,
+            max_bitrate: f.max_bitrate,
+            avg_bitrate: f.avg_bitrate,
+            ..Default::default()
+        }),
+    }
+}
+
+/// Split the track of a file into a virtual track for each audio track of the
+/// sheet, ending where the next one starts or at the end of the file.
+/// Album, artist and the rest come from the sheet where it has them,
+/// otherwise from the file.
+pub fn split_track(tk: &Track, cs: &CueSheet, file: &str) -> Vec<Track> {
+    let cts: Vec<(&CueTrack, Duration)> = cs
+        .tracks
+        .iter()
+        .filter(|t| t.file == file && t.kind.eq_ignore_ascii_case("AUDIO"))
+        .filter_map(|t| Some((t, frames_duration(t.start()?))))
+        .collect();
+    let file_end = tk.format.as_ref().map(format_duration);
+    cts.iter()
+        .enumerate()
+        .map(|(i, (ct, start))| {
+            let span = Span {
+                start: *start,
+                end: cts
+                    .get(i + 1)
+                    .map(|(_, s)| *s)
+                    .or(file_end)
+                    .unwrap_or(*start),
+            };
+            Track {
+                path: tk.path.clone(),
+                file_format: tk.file_format.clone(),
+                title: Some(
+                    ct.title
+                        .clone()
+                        .unwrap_or_else(|| format!("Track {:02}", ct.number)),
+                ),
+                artist: ct
+                    .performer
+                    .clone()
+                    .or_else(|| cs.performer.clone())
+                    .or_else(|| tk.artist.clone()),
+                album: cs.title.clone().or_else(|| tk.album.clone()),
+                album_artist: cs.performer.clone().or_else(|| tk.album_artist.clone()),
+                track_number: Some(ct.number),
+                track_total: Some(cts.len() as u32),
+                disk_number: tk.disk_number,
+                disk_total: tk.disk_total,
+                format: tk.format.as_ref().map(|f| span_format(f, &span)),
+                artwork: tk.artwork.clone(),
+                span: Some(span),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// A cue sheet for the embedded CUESHEET block of a FLAC file, using the
+/// text of the CUESHEET comment for titles when there is one.
+/// None unless there's more than one audio track.
+pub fn from_flac(tk: &Track) -> Option<CueSheet> {
+    let md = match &tk.metadata {
+        Some(FormatMetadata::Flac(md)) => md,
+        _ => return None,
+    };
+    let file = tk
+        .path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Vorbis comment keys are case-insensitive.
+    let comment = md
+        .comments
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(CUESHEET_COMMENT))
+        .and_then(|(_, v)| v.first());
+    let mut cs = match comment {
+        Some(s) => parse(s).ok()?,
+        None => {
+            let block = md.cue_sheet.as_ref()?;
+            let rate = match &tk.format {
+                Some(CodecFormat::PCM(f)) if f.sample_rate > 0 => f.sample_rate as u64,
+                _ => return None,
+            };
+            CueSheet {
+                catalog: Some(block.catalog.clone()).filter(|c| !c.is_empty()),
+                tracks: block
+                    .tracks
+                    .iter()
+                    .filter(|t| !t.is_lead_out())
+                    .map(|t| CueTrack {
+                        number: t.number as u32,
+                        kind: if t.audio { "AUDIO" } else { "DATA" }.to_string(),
+                        isrc: Some(t.isrc.clone()).filter(|i| !i.is_empty()),
+                        indexes: t
+                            .indexes
+                            .iter()
+                            .map(|(n, o)| {
+                                let samples = t.offset.saturating_add(*o);
+                                let frames =
+                                    samples as u128 * FRAMES_PER_SECOND as u128 / rate as u128;
+                                (*n as u32, frames as u64)
+                            })
+                            .collect(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        }
+    };
+    // The sheet is for this file, whatever name it was ripped to.
+    for t in &mut cs.tracks {
+        t.file = file.clone();
+    }
+    let audio = cs
+        .tracks
+        .iter()
+        .filter(|t| t.kind.eq_ignore_ascii_case("AUDIO"))
+        .count();
+    Some(cs).filter(|_| audio > 1)
+}
+
+/// Replace the tracks of files that have a cue sheet with virtual tracks,
+/// using the .cue files among files or a FLAC file's embedded sheet.
+/// The .cue files that are used are taken out of files.
+pub fn expand(
+    tracks: Vec<Track>,
+    files: Vec<PathBuf>,
+) -> Result<(Vec<Track>, Vec<PathBuf>), Box<dyn Error>> {
+    let mut sheets = Vec::new();
+    let mut rest = Vec::new();
+    for f in files {
+        let is_cue = f
+            .extension()
+            .filter(|e| e.eq_ignore_ascii_case(EXTENSION))
+            .is_some();
+        if !is_cue {
+            rest.push(f);
+            continue;
+        }
+        match read(&f) {
+            Ok(cs) => sheets.push((f, cs)),
+            Err(e) => {
+                eprintln!("Couldn't read {}: {}", f.display(), e);
+                rest.push(f);
+            }
+        }
+    }
+
+    let mut used = vec![false; sheets.len()];
+    let mut out = Vec::new();
+    for tk in tracks {
+        let found = sheets
+            .iter()
+            .enumerate()
+            .find_map(|(i, (p, cs))| Some((i, cs.clone(), sheet_file(&tk.path, p, cs)?)));
+        match found {
+            Some((i, cs, file)) => {
+                used[i] = true;
+                out.extend(split_track(&tk, &cs, &file));
+            }
+            None => match from_flac(&tk) {
+                Some(cs) => {
+                    let file = cs.tracks[0].file.clone();
+                    out.extend(split_track(&tk, &cs, &file));
+                }
+                None => out.push(tk),
+            },
+        }
+    }
+    for ((p, _), u) in sheets.into_iter().zip(used) {
+        if !u {
+            rest.push(p);
+        }
+    }
+    Ok((out, rest))
+}
+
+// The FILE of a sheet that's the audio file p: one with the same name, or failing
+// that the same name without its extension, as rippers often write the sheet for
+// a WAV file that's later encoded. The sheet has to be in the same directory.
+fn sheet_file(p: &Path, cue: &Path, cs: &CueSheet) -> Option<String> {
+    if p.parent() != cue.parent() {
+        return None;
+    }
+    let name = p.file_name()?.to_string_lossy().to_string();
+    let stem = p.file_stem()?.to_string_lossy().to_string();
+    let files = cs.files();
+    files
+        .iter()
+        .find(|f| f.eq_ignore_ascii_case(&name))
+        .or_else(|| {
+            files.iter().find(|f| {
+                Path::new(f)
+                    .file_stem()
+                    .filter(|s| s.to_string_lossy().eq_ignore_ascii_case(&stem))
+                    .is_some()
+            })
+        })
+        .map(|f| f.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flac;
+    use crate::track::FlacMetadata;
+
+    const SHEET: &str = r#"REM GENRE Rock
+REM DATE 1977
+PERFORMER "Grateful Dead"
+TITLE "Barton Hall"
+FILE "gd77-05-08.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "Minglewood Blues"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Loser"
+    PERFORMER "Jerry Garcia"
+    ISRC USABC7700002
+    PREGAP 00:02:00
+    INDEX 00 05:48:12
+    INDEX 01 05:50:00
+  TRACK 03 AUDIO
+    INDEX 01 12:00:37
+"#;
+
+    fn pcm_track(seconds: u64) -> Track {
+        Track {
+            path: PathBuf::from("/music/gd77-05-08.flac"),
+            file_format: Some("flac".to_string()),
+            format: Some(CodecFormat::PCM(PCMFormat {
+                sample_rate: 44_100,
+                channels: 2,
+                bits_per_sample: 16,
+                total_samples: seconds * 44_100,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let cs = parse(SHEET).unwrap();
+        assert_eq!(cs.title.as_deref(), Some("Barton Hall"));
+        assert_eq!(cs.performer.as_deref(), Some("Grateful Dead"));
+        assert_eq!(cs.rem("date"), Some("1977"));
+        assert_eq!(cs.files(), vec!["gd77-05-08.wav"]);
+        assert_eq!(cs.tracks.len(), 3);
+        let t = &cs.tracks[1];
+        assert_eq!(t.title.as_deref(), Some("Loser"));
+        assert_eq!(t.performer.as_deref(), Some("Jerry Garcia"));
+        assert_eq!(t.isrc.as_deref(), Some("USABC7700002"));
+        assert_eq!(t.pregap, Some(150));
+        assert_eq!(t.indexes, vec![(0, 26_112), (1, 26_250)]);
+        assert_eq!(t.start(), Some(26_250));
+
+        assert!(parse("TRACK 01 AUDIO").is_err());
+        assert!(parse("FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:61:00").is_err());
+        // Minutes that overflow the frame count.
+        let huge = format!(
+            "FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 {}:00:00",
+            u64::MAX / 60
+        );
+        assert!(parse(&huge).is_err());
+        assert_eq!(
+            frames_duration(u64::MAX).as_secs(),
+            u64::MAX / FRAMES_PER_SECOND
+        );
+        assert_eq!(
+            split_line("TITLE \"Two  Words\" x"),
+            vec!["TITLE", "Two  Words", "x"]
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let cs = parse(SHEET).unwrap();
+        let tk = pcm_track(1200);
+        let file = sheet_file(&tk.path, Path::new("/music/gd.cue"), &cs).unwrap();
+        assert_eq!(file, "gd77-05-08.wav");
+        assert_eq!(sheet_file(&tk.path, Path::new("/other/gd.cue"), &cs), None);
+
+        let tks = split_track(&tk, &cs, &file);
+        assert_eq!(tks.len(), 3);
+        assert_eq!(tks[0].title.as_deref(), Some("Minglewood Blues"));
+        assert_eq!(tks[0].album.as_deref(), Some("Barton Hall"));
+        assert_eq!(tks[1].artist.as_deref(), Some("Jerry Garcia"));
+        assert_eq!(tks[2].title.as_deref(), Some("Track 03"));
+        assert_eq!(tks[2].track_total, Some(3));
+        let span = tks[1].span.unwrap();
+        assert_eq!(span.start, Duration::from_secs(350));
+        assert_eq!(span.duration(), Duration::from_nanos(370_493_333_333));
+        // The last track runs to the end of the file.
+        assert_eq!(tks[2].span.unwrap().end, Duration::from_secs(1200));
+        match &tks[0].format {
+            Some(CodecFormat::PCM(f)) => assert_eq!(f.total_samples, 350 * 44_100),
+            f => panic!("Wrong format {:?}", f),
+        }
+    }
+
+    #[test]
+    fn test_from_flac() {
+        let mut tk = pcm_track(600);
+        let track = |number, offset, isrc: &str| flac::CueTrack {
+            number,
+            offset,
+            isrc: isrc.to_string(),
+            audio: true,
+            pre_emphasis: false,
+            indexes: vec![(1, 0)],
+        };
+        tk.metadata = Some(FormatMetadata::Flac(FlacMetadata {
+            cue_sheet: Some(flac::CueSheet {
+                is_cd: true,
+                tracks: vec![
+                    track(1, 0, "USABC7700001"),
+                    track(2, 44_100 * 300, ""),
+                    track(170, 44_100 * 600, ""),
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let cs = from_flac(&tk).unwrap();
+        assert_eq!(cs.tracks.len(), 2);
+        assert_eq!(cs.tracks[0].isrc.as_deref(), Some("USABC7700001"));
+        assert_eq!(cs.tracks[1].start(), Some(300 * 75));
+        assert_eq!(cs.files(), vec!["gd77-05-08.flac"]);
+
+        // The CUESHEET comment, whatever the case of its key, has the titles.
+        if let Some(FormatMetadata::Flac(md)) = &mut tk.metadata {
+            md.comments
+                .insert("cuesheet".to_string(), vec![SHEET.to_string()]);
+        }
+        let cs = from_flac(&tk).unwrap();
+        assert_eq!(cs.tracks[0].title.as_deref(), Some("Minglewood Blues"));
+        assert_eq!(cs.tracks[0].file, "gd77-05-08.flac");
+
+        assert_eq!(from_flac(&pcm_track(600)), None);
+    }
+}
@@ -1,8 +1,15 @@
 //! Output for the application.
 extern crate num_format;
 use crate::album;
+use crate::art;
+use crate::cue;
 use crate::file;
+use crate::flac;
+use crate::info;
+use crate::manifest;
+use crate::mp3;
 use crate::mpeg4;
+use crate::spec;
 use crate::track;
 
 use format::consts::FORMAT_CLEAN;
@@ -11,22 +18,23 @@ use prettytable::{format, Cell, Row, Table};
 
 use std::env;
 use std::error::Error;
+use std::fs;
 use std::io;
-use std::io::Read;
 use std::path::PathBuf;
 use std::time::Duration;
 const NONE_SHORT: &str = "-";
 
 /// Display regular files and audio files, separately dispaying
 /// metadata of the audio file if found.
-pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
+/// For a directory with an info .txt file the titles from it are shown
+/// next to the current ones, and written to the tracks with write_titles.
+pub fn list_files(mut p: PathBuf, write_titles: bool) -> Result<(), Box<dyn Error>> {
   if !p.exists() {
     p = env::current_dir()?
   }
 
   // Make sure we can find it ....
-  let album;
-  let files;
+  let mut dir = None;
   if !p.as_path().exists() {
     return Err(Box::new(std::io::Error::new(
       std::io::ErrorKind::NotFound,
@@ -35,16 +43,14 @@ pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
   }
   // If it's a file get the track build an album around it.
   // Otherwise, build an album around the files in the directory.
-  if p.is_file() {
-    let (tracks, f) = track::files_from(p)?;
-    album = album::album_from_tracks(tracks);
-    files = f;
-  } else {
+  // Files with a cue sheet are listed as the tracks of the sheet.
+  if p.is_dir() {
     p = dir_or_cwd(p)?;
-    let (a, f) = album::album_from_path(p)?;
-    album = a;
-    files = f;
+    dir = Some(p.clone());
   }
+  let (tracks, files) = track::files_from(p)?;
+  let (tracks, files) = cue::expand(tracks, files)?;
+  let album = album::album_from_tracks(tracks);
 
   if !album.tracks.is_empty() {
     // Display album information
@@ -102,7 +108,11 @@ pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
               t.title.as_ref().unwrap_or(&NONE_SHORT.to_string()),
               format_duration(&f.duration(), true),
               format!("{} KHz", (f.sample_rate as f64 / 1000.0)),
-              format!("{} bits", f.bits_per_sample.to_string()),
+              match f.sample_format {
+                track::SampleFormat::Float => format!("{} bits float", f.bits_per_sample),
+                track::SampleFormat::Int => format!("{} bits", f.bits_per_sample),
+                track::SampleFormat::Compressed => format!("{} bits compressed", f.bits_per_sample),
+              },
               t.file_format.as_ref().unwrap_or(&NONE_SHORT.to_string()),
               pn,
             ]);
@@ -130,7 +140,7 @@ pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
               format!("{}", f.codec),
               format!(
                 "{} Kbps",
-                (f.avg_bitrate / 1000).to_formatted_string(&Locale::en)
+                (f.bitrate() / 1000).to_formatted_string(&Locale::en)
               ),
               pn,
             ]);
@@ -149,6 +159,12 @@ pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
       }
     }
     table.printstd();
+
+    if let Some(d) = dir {
+      if let Some((ip, info)) = info::info_from(&d)? {
+        list_info(&ip, &info, &album.tracks, write_titles)?;
+      }
+    }
   }
 
   // let (tracks, files) = track::files_from(path)?;
@@ -162,6 +178,57 @@ pub fn list_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
+// Print what was found in an info file and the titles it has for the tracks.
+fn list_info(
+  p: &PathBuf,
+  info: &info::Info,
+  tracks: &[track::Track],
+  write_titles: bool,
+) -> Result<(), Box<dyn Error>> {
+  println!("\nInfo: {}", path_file_name(p));
+  println!("Artist: {}", info.artist.as_deref().unwrap_or(NONE_SHORT));
+  println!("Date: {}", info.date.as_deref().unwrap_or(NONE_SHORT));
+  println!("Venue: {}", info.venue.as_deref().unwrap_or(NONE_SHORT));
+  if !info.lineage.is_empty() {
+    println!("Lineage: {}", info.lineage.join(" > "));
+  }
+
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row!["Track", "Title", "Info Title", "File"]);
+  let matches = info.match_tracks(tracks);
+  for (t, m) in tracks.iter().zip(&matches) {
+    table.add_row(row![
+      t.tracks_display(),
+      t.title.as_deref().unwrap_or(NONE_SHORT),
+      m.map_or(NONE_SHORT.to_string(), |it| it.tag_title()),
+      path_file_name(&t.path),
+    ]);
+  }
+  table.printstd();
+
+  if write_titles {
+    let mut n = 0;
+    for (t, m) in tracks.iter().zip(&matches) {
+      let it = match m {
+        Some(it) => it,
+        None => continue,
+      };
+      if let Some(track::FormatMetadata::Flac(_)) = t.metadata {
+        if t.title.as_deref() != Some(&it.tag_title()) {
+          flac::write_comments(&t.path, &[("TITLE".to_string(), vec![it.tag_title()])])?;
+          n += 1;
+        }
+      } else {
+        eprintln!("Titles can only be written to FLAC files: {}", path_file_name(&t.path));
+      }
+    }
+    println!("Wrote {} titles.", n);
+  }
+
+  Ok(())
+}
+
 /// Prints a detailed description of an audio file, including listing
 /// all found metadata.
 pub fn describe_file(p: PathBuf) -> Result<(), Box<dyn Error>> {
@@ -188,6 +255,253 @@ pub fn describe_file(p: PathBuf) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
+/// Prints the chapters of an audio file.
+pub fn list_chapters(p: PathBuf) -> Result<(), Box<dyn Error>> {
+  if !p.is_file() {
+    return Err(format!("{} is not a file.", p.as_path().display()).into());
+  }
+
+  let (tracks, _) = track::files_from(p)?;
+  for tk in tracks {
+    if tk.chapters.is_empty() {
+      println!("No chapters.");
+    } else {
+      print_chapters(&tk.chapters);
+    }
+  }
+  Ok(())
+}
+
+fn print_chapters(chapters: &[track::Chapter]) {
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row!["#", "Start", "Duration", "Title"]);
+  for (i, c) in chapters.iter().enumerate() {
+    table.add_row(row![
+      i + 1,
+      format_duration(&c.start, true),
+      format_duration(&c.duration, true),
+      c.title,
+    ]);
+  }
+  table.printstd();
+}
+
+/// Prints the encoder delay, padding and playable duration of the tracks
+/// in a directory, and which of them are missing the gapless information
+/// that tracks of a continuous album need to run into each other without a gap.
+pub fn check_gapless(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+  let (album, _) = album::album_from_path(dir_or_cwd(p)?)?;
+
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row![
+    "Track", "Title", "Delay", "Padding", "Duration", "Playable", "Gapless"
+  ]);
+  for t in &album.tracks {
+    let g = t.gapless();
+    let duration = match &t.format {
+      Some(track::CodecFormat::PCM(f)) => Some(f.duration()),
+      Some(track::CodecFormat::MPEG3(f)) => Some(f.duration),
+      Some(track::CodecFormat::MPEG4(f)) => Some(f.duration()),
+      None => None,
+    };
+    table.add_row(row![
+      t.tracks_display(),
+      t.title.as_deref().unwrap_or(NONE_SHORT),
+      g.map_or(NONE_SHORT.to_string(), |g| g.delay.to_string()),
+      g.map_or(NONE_SHORT.to_string(), |g| g.padding.to_string()),
+      duration.map_or(NONE_SHORT.to_string(), |d| format_duration(&d, true)),
+      t.playable_duration()
+        .map_or(NONE_SHORT.to_string(), |d| format_duration(&d, true)),
+      match g {
+        Some(g) => g.source.to_string(),
+        None if t.needs_gapless() => "Missing".to_string(),
+        None => "Not needed".to_string(),
+      },
+    ]);
+  }
+  table.printstd();
+
+  let missing = album.missing_gapless();
+  if missing.is_empty() {
+    println!("All {} tracks will play gaplessly.", album.tracks.len());
+  } else {
+    println!(
+      "{} of {} tracks are missing gapless information:",
+      missing.len(),
+      album.tracks.len()
+    );
+    for t in missing {
+      println!("{}", path_file_name(&t.path));
+    }
+  }
+  Ok(())
+}
+
+// Encoder delay, padding and the playable duration, if there's gapless information.
+fn gapless_tes(g: Option<&track::Gapless>, playable: Duration) -> Vec<Te<'static>> {
+  match g {
+    Some(g) => vec![
+      Te("Encoder Delay", format!("{} samples", g.delay)),
+      Te("Padding", format!("{} samples", g.padding)),
+      Te("Playable Duration", format_duration(&playable, false)),
+      Te("Gapless Source", g.source.to_string()),
+    ],
+    None => vec![],
+  }
+}
+
+/// Writes the artwork of a track, or of each track in a directory, to image files
+/// beside it, printing each file written.
+pub fn extract_art(p: PathBuf) -> Result<(), Box<dyn Error>> {
+  let (tracks, _) = track::files_from(p)?;
+  for tk in tracks {
+    if tk.artwork.is_empty() {
+      println!("No artwork in {}.", path_file_name(&tk.path));
+      continue;
+    }
+    for (a, f) in tk.artwork.iter().zip(art::extract(&tk)?) {
+      println!("{}: {}", path_file_name(&f), a);
+    }
+  }
+  Ok(())
+}
+
+/// Embeds a cover image in the tracks of a directory that don't have one, printing
+/// each change. The image is the one given, or the cover.jpg or folder.jpg in the
+/// directory. Covers bigger than max_bytes are replaced when the image is smaller.
+/// With dry_run the changes are printed but not written.
+pub fn embed_art(
+  mut p: PathBuf,
+  image: Option<PathBuf>,
+  max_bytes: Option<usize>,
+  dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+  let dir = dir_or_cwd(p)?;
+  let image = match image {
+    Some(i) => i,
+    None => art::find_cover(&dir)?.ok_or_else(|| {
+      format!(
+        "No cover image ({}) in {}.",
+        art::COVER_FILES.join(", "),
+        dir.display()
+      )
+    })?,
+  };
+  let cover = art::read_cover(&image)?;
+  println!("Cover: {} {}", path_file_name(&image), cover);
+
+  let (album, _) = album::album_from_path(dir)?;
+  let updates = art::plan_covers(&album.tracks, &cover, max_bytes)?;
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row!["File", "Current", "Action"]);
+  for u in &updates {
+    table.add_row(row![
+      path_file_name(&u.path),
+      u.current
+        .as_ref()
+        .map_or(NONE_SHORT.to_string(), |a| a.to_string()),
+      u.action,
+    ]);
+  }
+  table.printstd();
+
+  let changed = updates
+    .iter()
+    .filter(|u| u.action != art::CoverAction::Keep)
+    .count();
+  if dry_run {
+    println!("Would update {} of {} files.", changed, updates.len());
+  } else {
+    art::embed_covers(&updates, &cover)?;
+    println!("Updated {} of {} files.", changed, updates.len());
+  }
+  Ok(())
+}
+
+/// Edits the ID3 tags of an MP3 file, or each MP3 file in a directory, printing
+/// the changes to each. With dry_run the changes are printed but not written.
+pub fn edit_tags(p: PathBuf, edit: mp3::TagEdit, dry_run: bool) -> Result<(), Box<dyn Error>> {
+  let (tracks, _) = track::files_from(p)?;
+  let mut updates = Vec::new();
+  for tk in tracks {
+    match file::identify(fs::File::open(&tk.path)?)? {
+      Some(file::FileFormat::MP3(_)) => updates.push(mp3::plan_tag_update(&tk.path, &edit)?),
+      _ => eprintln!("Only MP3 tags can be edited: {}", tk.path.display()),
+    }
+  }
+  updates.sort_by(|a, b| a.path.cmp(&b.path));
+
+  let version = |v: u8| match v {
+    0 => NONE_SHORT.to_string(),
+    v => format!("ID3v2.{}", v),
+  };
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row!["File", "Version", "Change"]);
+  for u in &updates {
+    let mut changes: Vec<String> = u.changes.iter().map(|c| c.to_string()).collect();
+    if u.duplicates > 0 {
+      changes.push(format!("remove {} more ID3v2 tags", u.duplicates));
+    }
+    if u.remove_v1 {
+      changes.push("remove ID3v1 tag".to_string());
+    }
+    let v = if u.from == u.to {
+      version(u.to)
+    } else {
+      format!("{} -> {}", version(u.from), version(u.to))
+    };
+    table.add_row(row![
+      path_file_name(&u.path),
+      v,
+      changes.first().map_or("", |c| c.as_str())
+    ]);
+    for c in changes.iter().skip(1) {
+      table.add_row(row!["", "", c]);
+    }
+  }
+  table.printstd();
+
+  let changed = updates.iter().filter(|u| !u.is_empty()).count();
+  if dry_run {
+    println!("Would update {} of {} files.", changed, updates.len());
+  } else {
+    for u in &updates {
+      mp3::write_tag_update(u)?;
+    }
+    println!("Updated {} of {} files.", changed, updates.len());
+  }
+  Ok(())
+}
+
+// Width of the bands in the bitrate distribution.
+const BITRATE_STEP: u32 = 32_000;
+
+// One line for each band the bitrate was in with the percentage of the track in it.
+fn bitrate_distribution(si: &mpeg4::samples::SampleIndex) -> String {
+  si.bitrate_distribution(BITRATE_STEP)
+    .iter()
+    .map(|(low, share)| {
+      format!(
+        "{:>4} - {:>4} Kbps {:5.1}%",
+        low / 1000,
+        (low + BITRATE_STEP) / 1000,
+        share * 100.0
+      )
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
 struct Te<'a>(&'a str, String);
 
 fn print_te_list(v: Vec<Te>) {
@@ -251,6 +565,12 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
     tk.disk_total
       .map_or(NONE_SHORT.to_string(), |v| v.to_string()),
   ));
+  if let Some(id) = tk.musicbrainz_track_id {
+    tes.push(Te("MusicBrainz Track", id));
+  }
+  if let Some(id) = tk.musicbrainz_album_id {
+    tes.push(Te("MusicBrainz Album", id));
+  }
 
   // Codec Specific
   if let Some(c) = tk.format {
@@ -261,15 +581,29 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
           "Sample Rate",
           format!("{} Hz", sf.sample_rate.to_formatted_string(&Locale::en)),
         ));
-        tes.push(Te(
-          "Sample Size",
-          format!("{} bits", sf.bits_per_sample.to_string()),
-        ));
+        tes.push(Te("Sample Format", sf.sample_format.to_string()));
+        if sf.container_bits > sf.bits_per_sample {
+          tes.push(Te(
+            "Sample Size",
+            format!("{} bits in {}", sf.bits_per_sample, sf.container_bits),
+          ));
+        } else {
+          tes.push(Te(
+            "Sample Size",
+            format!("{} bits", sf.bits_per_sample.to_string()),
+          ));
+        }
         tes.push(Te(
           "Samples",
           sf.total_samples.to_formatted_string(&Locale::en),
         ));
         tes.push(Te("Channels", sf.channels.to_formatted_string(&Locale::en)));
+        if let Some(mask) = sf.channel_mask {
+          tes.push(Te(
+            "Channel Mask",
+            format!("{:#x} ({})", mask, sf.speakers().join(", ")),
+          ));
+        }
         tes.push(Te("Duration", format_duration(&sf.duration(), false)));
       }
 
@@ -277,11 +611,21 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
       track::CodecFormat::MPEG3(f) => {
         tes.push(Te("Version", f.version_string()));
         tes.push(Te("Layer", f.layer_string()));
-        tes.push(Te("Bitrate", format!("{} kbps", f.bitrate.to_string())));
+        tes.push(Te(
+          "Bitrate",
+          format!("{} kbps{}", f.bitrate, if f.vbr { " (VBR)" } else { "" }),
+        ));
+        tes.push(Te(
+          "Frames",
+          f.frames
+            .map_or(NONE_SHORT.to_string(), |n| n.to_formatted_string(&Locale::en)),
+        ));
         tes.push(Te(
           "Sample Rate",
           format!("{} KHz", f.sample_rate.to_formatted_string(&Locale::en)),
         ));
+        tes.push(Te("Duration", format_duration(&f.duration, false)));
+        tes.extend(gapless_tes(f.gapless.as_ref(), f.playable_duration()));
       }
 
       // Mpeg4
@@ -302,8 +646,14 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
         tes.push(Te("Codec", format!("{}", f.codec)));
         tes.push(Te(
           "Average Bit Rate",
-          format!("{} bps", f.avg_bitrate.to_formatted_string(&Locale::en)),
+          format!("{} bps", f.bitrate().to_formatted_string(&Locale::en)),
         ));
+        if let Some(si) = &f.samples {
+          tes.push(Te(
+            "Bit Rate Distribution",
+            bitrate_distribution(si),
+          ));
+        }
         tes.push(Te(
           "Maxium Bit Rate",
           format!("{} bps", f.max_bitrate.to_formatted_string(&Locale::en)),
@@ -311,6 +661,7 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
         tes.push(Te("Channels", f.channels.to_formatted_string(&Locale::en)));
         tes.push(Te("Channel Config", format!("{}", f.channel_config)));
         tes.push(Te("Duration", format_duration(&f.duration(), false)));
+        tes.extend(gapless_tes(f.gapless.as_ref(), f.playable_duration()));
         tes.push(Te("DRM Protection", f.protected.to_string()));
         tes.push(Te(
           "DRM Type",
@@ -323,6 +674,10 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
 
   // Tail of basic track
   tes.push(Te("File Size", format!("{} bytes", fs_str)));
+  if !tk.artwork.is_empty() {
+    let art: Vec<String> = tk.artwork.iter().map(|a| a.to_string()).collect();
+    tes.push(Te("Artwork", art.join("\n")));
+  }
   // Extra metadata (not the Hashes of collected metadata) we want to display.
   if let Some(track::FormatMetadata::MP4(mmd)) = &tk.metadata {
     let data_percent = if fs_int == 0 {
@@ -340,11 +695,26 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
     ));
     tes.push(Te("Creation Date:", format!("{}", mmd.creation)));
     tes.push(Te("Modification Date:", format!("{}", mmd.modification)));
+    if !mmd.traks.is_empty() {
+      let traks: Vec<String> = mmd.traks.iter().map(|t| t.to_string()).collect();
+      tes.push(Te("Tracks:", traks.join("\n")));
+    }
+  }
+  if let Some(track::FormatMetadata::Flac(fmd)) = &tk.metadata {
+    tes.push(Te(
+      "Audio MD5",
+      fmd.md5.clone().unwrap_or_else(|| NONE_SHORT.to_string()),
+    ));
   }
 
   // Display.
   print_te_list(tes);
   println!();
+  if !tk.chapters.is_empty() {
+    println!("Chapters");
+    print_chapters(&tk.chapters);
+    println!();
+  }
 
   // Display Tagged Metadata.
   if let Some(md) = &tk.metadata {
@@ -353,6 +723,8 @@ fn describe_track(tk: track::Track) -> Result<(), Box<dyn Error>> {
       track::FormatMetadata::Flac(fmd) => fmd.print(o)?,
       track::FormatMetadata::ID3(imd) => imd.print(o)?,
       track::FormatMetadata::MP4(mmd) => mmd.print(o)?,
+      track::FormatMetadata::Riff(rmd) => rmd.print(o)?,
+      track::FormatMetadata::Aiff(amd) => amd.print(o)?,
     }
   }
   Ok(())
@@ -385,13 +757,11 @@ pub fn display_find_path(p: PathBuf, find_path: String) -> Result<(), Box<dyn Er
   if let Some(ft) = file::identify(&mut file)? {
     match ft {
       file::FileFormat::MPEG4(_) => {
-        let mut vbuf = Vec::<u8>::new();
-        let _n = file.read_to_end(&mut vbuf);
-        let buf = vbuf.as_slice();
-        if let Some(bx) = mpeg4::find::find_box(&find_path, buf) {
+        let found = mpeg4::find::find_box_in(&find_path, &file, |bx| {
           println!("{:?}", bx);
           mpeg4::util::dump_buffer(bx.buf);
-        } else {
+        })?;
+        if !found {
           println!("Couldn't find box in path: {}", find_path);
         };
       }
@@ -404,6 +774,143 @@ pub fn display_find_path(p: PathBuf, find_path: String) -> Result<(), Box<dyn Er
   Ok(())
 }
 
+/// Checks the files in a directory against all of the FLAC fingerprint (.ffp)
+/// and md5 (.md5) manifests found there, displaying a table of results for each manifest.
+pub fn verify_files(mut p: PathBuf) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+
+  let manifests = manifest::manifests_from(&p)?;
+  if manifests.is_empty() {
+    println!("No manifests found in: {}", p.as_path().display());
+    return Ok(());
+  }
+
+  for m in manifests {
+    println!();
+    println!("{}: {}", m.kind, path_file_name(&m.path));
+    let vs = manifest::verify(&m)?;
+
+    let mut table = Table::new();
+    table.set_format(*FORMAT_CLEAN);
+    table.add_row(row!["Status", "File", "Expected", "Actual"]);
+    for v in &vs {
+      table.add_row(row![
+        v.status,
+        v.file_name,
+        v.expected.as_ref().unwrap_or(&NONE_SHORT.to_string()),
+        v.actual
+          .as_ref()
+          .or(v.error.as_ref())
+          .unwrap_or(&NONE_SHORT.to_string()),
+      ]);
+    }
+    table.printstd();
+
+    let count = |s| vs.iter().filter(|v| v.status == s).count();
+    println!(
+      "{} matched, {} mismatched, {} missing, {} extra, {} unreadable.",
+      count(manifest::Status::Match),
+      count(manifest::Status::Mismatch),
+      count(manifest::Status::Missing),
+      count(manifest::Status::Extra),
+      count(manifest::Status::Unreadable),
+    );
+  }
+
+  Ok(())
+}
+
+/// Generates and writes the FLAC fingerprint (.ffp) and md5 (.md5) manifests
+/// for a directory. With dry_run the manifests are printed instead of written.
+pub fn write_manifests(mut p: PathBuf, dry_run: bool) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+  p = dir_or_cwd(p)?;
+
+  for m in manifest::generate(&p)? {
+    if dry_run {
+      println!("\nWould write {}: {}", m.kind, path_file_name(&m.path));
+      print!("{}", m.text().replace("\r\n", "\n"));
+    } else {
+      m.write()?;
+      println!(
+        "Wrote {}: {} ({} files)",
+        m.kind,
+        path_file_name(&m.path),
+        m.entries.len()
+      );
+    }
+  }
+
+  Ok(())
+}
+
+/// Applies the tags in album.yaml and tracklist.yaml to the FLAC files of a directory,
+/// printing each change. With dry_run the changes are printed but not written.
+pub fn apply_spec(mut p: PathBuf, dry_run: bool) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+  let spec = spec::read_specs(&p)?;
+  let dir = dir_or_cwd(p)?;
+
+  let updates = spec::plan(&spec, &dir)?;
+  let mut table = Table::new();
+  table.set_format(*FORMAT_CLEAN);
+  table.add_row(row!["File", "Key", "Old", "New"]);
+  for u in &updates {
+    for c in &u.changes {
+      table.add_row(row![
+        path_file_name(&u.path),
+        c.key,
+        c.old.as_ref().unwrap_or(&NONE_SHORT.to_string()),
+        c.new,
+      ]);
+    }
+  }
+  table.printstd();
+
+  let changed = updates.iter().filter(|u| !u.changes.is_empty()).count();
+  if dry_run {
+    println!("Would update {} of {} files.", changed, updates.len());
+  } else {
+    spec::apply(&updates)?;
+    println!("Updated {} of {} files.", changed, updates.len());
+  }
+
+  Ok(())
+}
+
+/// Writes an album.yaml for a directory using the tags already on its tracks.
+/// An existing album.yaml is not replaced. With dry_run the YAML is printed instead.
+pub fn write_spec_template(mut p: PathBuf, dry_run: bool) -> Result<(), Box<dyn Error>> {
+  if !p.exists() {
+    p = env::current_dir()?
+  }
+  p = dir_or_cwd(p)?;
+
+  let (album, _) = album::album_from_path(p.clone())?;
+  let yaml = spec::spec_from_album(&album).to_yaml()?;
+  let sp = p.join(spec::ALBUM_SPEC_FILE);
+  if dry_run {
+    println!("Would write: {}", path_file_name(&sp));
+    print!("{}", yaml);
+  } else if sp.exists() {
+    return Err(Box::new(io::Error::new(
+      io::ErrorKind::AlreadyExists,
+      format!("Won't replace: {}", sp.as_path().display()),
+    )));
+  } else {
+    fs::write(&sp, yaml)?;
+    println!("Wrote: {} ({} tracks)", path_file_name(&sp), album.tracks.len());
+  }
+
+  Ok(())
+}
+
 // UTIL
 
 fn title_row(f: &Option<track::CodecFormat>) -> Row {
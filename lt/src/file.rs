@@ -1,4 +1,5 @@
 //! Model for audio file format that integrates specific readers and providing identification and other functions.
+use crate::aiff;
 use crate::flac;
 // use crate::id3;
 use crate::mp3;
@@ -24,6 +25,7 @@ pub enum FileFormat {
     MP4A(mp4::Mp4),
     MP3(mp3::Mp3),
     WAV(wav::Wav),
+    AIFF(aiff::Aiff),
     // ID3(id3::Id3),
 }
 
@@ -37,6 +39,7 @@ impl std::fmt::Display for FileFormat {
             // FileFormat::MP4P => f.write_str("MP4P")?,
             FileFormat::MP3(_) => f.write_str("MP3")?,
             FileFormat::WAV(_) => f.write_str("WAV")?,
+            FileFormat::AIFF(_) => f.write_str("AIFF")?,
             // FileFormat::ID3(_) => f.write_str("ID3")?,
             // FileFormat::Unknown => f.write_str("Unknown")?,
         };
@@ -55,6 +58,7 @@ impl std::fmt::Debug for FileFormat {
             // FileFormat::MP4P => f.write_str("MP4P")?,
             FileFormat::MP3(_) => f.write_str("MP3")?,
             FileFormat::WAV(_) => f.write_str("WAV")?,
+            FileFormat::AIFF(_) => f.write_str("AIFF")?,
             // FileFormat::ID3(_) => f.write_str("ID3")?,
             // FileFormat::Unknown => f.write_str("Unknown")?,
         };
@@ -78,6 +82,7 @@ pub fn identify(mut r: impl Read + Seek) -> Result<Option<FileFormat>, std::io::
         mpeg4::identify,
         // mp4::identify,
         wav::identify,
+        aiff::identify,
         mp3::identify,
         // id3::identify,
     ];
@@ -97,6 +102,7 @@ pub fn display_structure(p: &path::PathBuf) -> Result<(), Box<dyn Error>> {
     if let Some(ff) = identify(&mut file)? {
         match ff {
             FileFormat::MPEG4(d) => d.display_structure(&file)?,
+            FileFormat::Flac(d) => d.display_structure(&file)?,
             _ => println!("Structure display not implemented for {} files.", ff),
         }
     };
@@ -1,11 +1,16 @@
-//! Entrypoints integrated with Albums and Track into Flac metadata reading.
+//! Entrypoints integrated with Albums and Track into Flac metadata reading and writing.
 //!
+use crate::art::Artwork;
 use crate::file;
 use crate::file::FileFormat;
 use crate::track;
+use metaflac::block::PictureType;
 use metaflac::{Block, Tag};
+use std::collections::HashMap;
 use std::error::Error;
-use std::io::{Read, Seek};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
 
 const DISCTOTAL: &str = "DISCTOTAL";
 const DISCNUMBER: &str = "DISCNUMBER";
@@ -13,6 +18,154 @@ const DISCNUMBER: &str = "DISCNUMBER";
 // const VENDOR: &str = "VENDOR";
 const ALT_TOTALTRACKS: &str = "TRACKTOTAL";
 
+// Samples in a CD frame, a 75th of a second at 44.1kHz.
+const CD_FRAME_SAMPLES: u64 = 588;
+// Sample number of a seek point that's a placeholder.
+const SEEK_PLACEHOLDER: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+// Size of a metadata block header.
+const BLOCK_HEADER_SIZE: u64 = 4;
+// Block header flag for the last metadata block.
+const LAST_BLOCK: u8 = 0x80;
+
+/// A track of a CUESHEET block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueTrack {
+    /// 1 to 99, or 170 (CD) or 255 for the lead-out.
+    pub number: u8,
+    /// Offset in samples from the start of the audio.
+    pub offset: u64,
+    /// International Standard Recording Code, empty if there isn't one.
+    pub isrc: String,
+    pub audio: bool,
+    pub pre_emphasis: bool,
+    /// (index number, offset in samples from the track's offset).
+    pub indexes: Vec<(u8, u64)>,
+}
+
+impl CueTrack {
+    /// Whether this is the lead-out track that ends the sheet.
+    pub fn is_lead_out(&self) -> bool {
+        self.number == 170 || self.number == 255
+    }
+
+    /// Offset of the track's INDEX 01, where it's played from,
+    /// or its offset if it doesn't have one.
+    pub fn start(&self) -> u64 {
+        self.offset
+            + self
+                .indexes
+                .iter()
+                .find(|(n, _)| *n == 1)
+                .map_or(0, |(_, o)| *o)
+    }
+}
+
+/// The CUESHEET block, usually the table of contents of the CD the file was ripped from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    /// Media catalog number, empty if there isn't one.
+    pub catalog: String,
+    /// Samples of lead-in.
+    pub lead_in: u64,
+    pub is_cd: bool,
+    /// The tracks, in order, ending with the lead-out.
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Format samples as the MM:SS:FF of a cue sheet, with 75 frames a second.
+pub fn cd_time(samples: u64) -> String {
+    let frames = samples / CD_FRAME_SAMPLES;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / (75 * 60),
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+/// A summary of the SEEKTABLE block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeekTable {
+    pub points: usize,
+    /// Points left empty for later.
+    pub placeholders: usize,
+    /// Sample number of the last point.
+    pub last_sample: u64,
+}
+
+impl fmt::Display for SeekTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} points, {} placeholders, to sample {}",
+            self.points, self.placeholders, self.last_sample
+        )
+    }
+}
+
+/// An APPLICATION block, data for an application registered with an id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Application {
+    /// Four characters, e.g. "riff" for the chunks flac keeps from a WAV file.
+    pub id: String,
+    pub size: usize,
+}
+
+/// The header of a metadata block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// File offset of the header.
+    pub offset: u64,
+    pub block_type: u8,
+    /// Size of the block, not including the header.
+    pub length: u32,
+    pub last: bool,
+}
+
+impl BlockHeader {
+    /// Name of the block type.
+    pub fn name(&self) -> &'static str {
+        match self.block_type {
+            0 => "STREAMINFO",
+            1 => "PADDING",
+            2 => "APPLICATION",
+            3 => "SEEKTABLE",
+            4 => "VORBIS_COMMENT",
+            5 => "CUESHEET",
+            6 => "PICTURE",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+/// Read the headers of the metadata blocks of a FLAC file.
+pub fn read_block_headers(mut r: impl Read + Seek) -> Result<Vec<BlockHeader>, Box<dyn Error>> {
+    let mut b = [0; 4];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut b)?;
+    if &b[..] != FLAC_HEADER {
+        return Err("Not a FLAC file.".into());
+    }
+    let mut offset = FLAC_HEADER.len() as u64;
+    let mut hs = Vec::new();
+    loop {
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut b)?;
+        let h = BlockHeader {
+            offset,
+            block_type: b[0] & !LAST_BLOCK,
+            length: u32::from_be_bytes([0, b[1], b[2], b[3]]),
+            last: b[0] & LAST_BLOCK != 0,
+        };
+        hs.push(h);
+        offset += BLOCK_HEADER_SIZE + h.length as u64;
+        if h.last {
+            break;
+        }
+    }
+    Ok(hs)
+}
+
 /// Flac file reader.
 #[derive(Default, Debug)]
 pub struct Flac;
@@ -29,6 +182,31 @@ pub fn identify(b: &[u8]) -> Option<FileFormat> {
     }
 }
 
+impl Flac {
+    /// Print the metadata blocks of the file, and where the audio starts.
+    pub fn display_structure(&self, r: impl Read + Seek) -> Result<(), Box<dyn Error>> {
+        let hs = read_block_headers(r)?;
+        println!("fLaC [4]");
+        for h in &hs {
+            println!(
+                "\t{} [{}]    type {} at offset {}{}",
+                h.name(),
+                BLOCK_HEADER_SIZE + h.length as u64,
+                h.block_type,
+                h.offset,
+                if h.last { " (last)" } else { "" }
+            );
+        }
+        if let Some(h) = hs.last() {
+            println!(
+                "Audio frames start at offset {}",
+                h.offset + BLOCK_HEADER_SIZE + h.length as u64
+            );
+        }
+        Ok(())
+    }
+}
+
 const FORMAT_NAME: &str = "flac";
 impl file::Decoder for Flac {
     /// Return the format name.
@@ -73,14 +251,55 @@ fn hydrate(t: &Tag, tk: &mut track::Track) {
         match b {
             Block::StreamInfo(si) => si_hydrate(si, tk),
             Block::VorbisComment(vc) => vorbis_hydrate(&vc, tk),
-            // Block::SeekTable(st) => println!("{:?}", st),
-            // Block::CueSheet(cs) => println!("CueSheet: {:?}", cs),
-            // Block::Application(ap) => println!("Application: {:?}", ap),
-            // Block::Padding(pd) => println!("Padding: {:?}", pd),
-            // Block::Picture(p) => println!("{:?}", p),
-            // Block::Unknown(b) => println!("Unknown {:?}", b),
-            _ => (), // TODO(jdr) should figure out how to attach arbitrary data to a track.
+            Block::Picture(p) => picture_hydrate(p, tk),
+            b => {
+                if let Some(track::FormatMetadata::Flac(md)) = &mut tk.metadata {
+                    block_hydrate(b, md);
+                }
+            }
+        }
+    }
+}
+
+// Keep the blocks that don't fill in the track on the FLAC metadata.
+fn block_hydrate(b: &Block, md: &mut track::FlacMetadata) {
+    match b {
+        Block::CueSheet(cs) => {
+            md.cue_sheet = Some(CueSheet {
+                catalog: cs.catalog_num.trim_end_matches('\0').to_string(),
+                lead_in: cs.num_leadin,
+                is_cd: cs.is_cd,
+                tracks: cs
+                    .tracks
+                    .iter()
+                    .map(|t| CueTrack {
+                        number: t.number,
+                        offset: t.offset,
+                        isrc: t.isrc.trim_end_matches('\0').to_string(),
+                        audio: t.is_audio,
+                        pre_emphasis: t.pre_emphasis,
+                        indexes: t.indices.iter().map(|i| (i.point_num, i.offset)).collect(),
+                    })
+                    .collect(),
+            })
+        }
+        Block::SeekTable(st) => {
+            let real = st
+                .seekpoints
+                .iter()
+                .filter(|p| p.sample_number != SEEK_PLACEHOLDER);
+            md.seek_table = Some(SeekTable {
+                points: st.seekpoints.len(),
+                placeholders: st.seekpoints.len() - real.clone().count(),
+                last_sample: real.map(|p| p.sample_number).max().unwrap_or(0),
+            });
         }
+        Block::Application(a) => md.applications.push(Application {
+            id: String::from_utf8_lossy(&a.id).to_string(),
+            size: a.data.len(),
+        }),
+        Block::Padding(n) => md.padding += *n as u64,
+        _ => (),
     }
 }
 
@@ -90,8 +309,31 @@ fn si_hydrate(si: &metaflac::block::StreamInfo, tk: &mut track::Track) {
         channels: si.num_channels,
         bits_per_sample: si.bits_per_sample as u16,
         total_samples: si.total_samples,
+        ..Default::default()
     };
     tk.format = Some(track::CodecFormat::PCM(f));
+
+    // An all zero signature means the encoder didn't compute one.
+    if let Some(track::FormatMetadata::Flac(md)) = &mut tk.metadata {
+        if si.md5.iter().any(|b| *b != 0) {
+            md.md5 = Some(si.md5.iter().map(|b| format!("{:02x}", b)).collect());
+        }
+    }
+}
+
+fn picture_hydrate(p: &metaflac::block::Picture, tk: &mut track::Track) {
+    let mut a = Artwork::new(
+        p.picture_type as u8,
+        &p.mime_type,
+        &p.description,
+        p.data.clone(),
+    );
+    // Prefer the image's own header, but the block may know when that doesn't.
+    if a.width == 0 || a.height == 0 {
+        a.width = p.width;
+        a.height = p.height;
+    }
+    tk.artwork.push(a);
 }
 
 fn vorbis_hydrate(vc: &metaflac::block::VorbisComment, tk: &mut track::Track) {
@@ -145,3 +387,172 @@ fn vorbis_hydrate(vc: &metaflac::block::VorbisComment, tk: &mut track::Track) {
         }
     };
 }
+
+/// Read the Vorbis comments from a FLAC file.
+pub fn read_comments(p: &Path) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>> {
+    let t = Tag::read_from_path(p)?;
+    Ok(t.vorbis_comments()
+        .map_or_else(HashMap::new, |vc| vc.comments.clone()))
+}
+
+/// Set Vorbis comments in a FLAC file, replacing any values already there for each key.
+/// Vorbis comment keys are case insensitive, so existing keys are matched without
+/// regard to case and replaced by the key given here.
+pub fn write_comments(p: &Path, comments: &[(String, Vec<String>)]) -> Result<(), Box<dyn Error>> {
+    let mut t = Tag::read_from_path(p)?;
+    let vc = t.vorbis_comments_mut();
+    for (k, v) in comments {
+        let old: Vec<String> = vc
+            .comments
+            .keys()
+            .filter(|ok| ok.eq_ignore_ascii_case(k))
+            .cloned()
+            .collect();
+        for ok in old {
+            vc.comments.remove(&ok);
+        }
+        vc.comments.insert(k.clone(), v.clone());
+    }
+    t.save()?;
+    Ok(())
+}
+
+/// Set the front cover of a FLAC file, replacing any front cover already there.
+pub fn write_cover(p: &Path, a: &Artwork) -> Result<(), Box<dyn Error>> {
+    let mut t = Tag::read_from_path(p)?;
+    t.remove_picture_type(PictureType::CoverFront);
+    t.add_picture(a.mime_type.clone(), PictureType::CoverFront, a.data.clone());
+    t.save()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metaflac::block;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_blocks() {
+        let mut md = track::FlacMetadata {
+            ..Default::default()
+        };
+        let cs = block::CueSheet {
+            catalog_num: "1234567890123\0\0".to_string(),
+            num_leadin: 88_200,
+            is_cd: true,
+            tracks: vec![
+                block::CueSheetTrack {
+                    offset: 0,
+                    number: 1,
+                    isrc: "USABC0000001".to_string(),
+                    is_audio: true,
+                    pre_emphasis: false,
+                    indices: vec![block::CueSheetTrackIndex {
+                        offset: 0,
+                        point_num: 1,
+                    }],
+                },
+                block::CueSheetTrack {
+                    offset: 588 * 75 * 61,
+                    number: 2,
+                    isrc: String::new(),
+                    is_audio: true,
+                    pre_emphasis: false,
+                    indices: vec![
+                        block::CueSheetTrackIndex {
+                            offset: 0,
+                            point_num: 0,
+                        },
+                        block::CueSheetTrackIndex {
+                            offset: 588 * 150,
+                            point_num: 1,
+                        },
+                    ],
+                },
+                block::CueSheetTrack {
+                    offset: 588 * 75 * 200,
+                    number: 170,
+                    isrc: String::new(),
+                    is_audio: true,
+                    pre_emphasis: false,
+                    indices: Vec::new(),
+                },
+            ],
+        };
+        block_hydrate(&Block::CueSheet(cs), &mut md);
+        block_hydrate(
+            &Block::SeekTable(block::SeekTable {
+                seekpoints: vec![
+                    block::SeekPoint {
+                        sample_number: 0,
+                        offset: 0,
+                        num_samples: 4096,
+                    },
+                    block::SeekPoint {
+                        sample_number: 441_000,
+                        offset: 1000,
+                        num_samples: 4096,
+                    },
+                    block::SeekPoint {
+                        sample_number: SEEK_PLACEHOLDER,
+                        offset: 0,
+                        num_samples: 0,
+                    },
+                ],
+            }),
+            &mut md,
+        );
+        block_hydrate(
+            &Block::Application(block::Application {
+                id: b"riff".to_vec(),
+                data: vec![0; 44],
+            }),
+            &mut md,
+        );
+        block_hydrate(&Block::Padding(8192), &mut md);
+        block_hydrate(&Block::Padding(100), &mut md);
+
+        let cue = md.cue_sheet.unwrap();
+        assert_eq!(cue.catalog, "1234567890123");
+        assert_eq!(cue.tracks[0].isrc, "USABC0000001");
+        assert_eq!(cd_time(cue.tracks[1].offset), "01:01:00");
+        assert_eq!(cd_time(cue.tracks[1].start()), "01:03:00");
+        assert!(cue.tracks[2].is_lead_out());
+        assert_eq!(
+            md.seek_table.unwrap().to_string(),
+            "3 points, 1 placeholders, to sample 441000"
+        );
+        assert_eq!(
+            md.applications,
+            vec![Application {
+                id: "riff".to_string(),
+                size: 44
+            }]
+        );
+        assert_eq!(md.padding, 8292);
+    }
+
+    #[test]
+    fn test_block_headers() {
+        let mut f = FLAC_HEADER.to_vec();
+        f.extend_from_slice(&[0, 0, 0, 34]);
+        f.resize(f.len() + 34, 0);
+        f.extend_from_slice(&[LAST_BLOCK | 1, 0, 0x01, 0x00]);
+        f.resize(f.len() + 256, 0);
+        f.extend_from_slice(&[0xFF, 0xF8]);
+
+        let hs = read_block_headers(Cursor::new(&f)).unwrap();
+        assert_eq!(hs.len(), 2);
+        assert_eq!(
+            (hs[0].name(), hs[0].offset, hs[0].length),
+            ("STREAMINFO", 4, 34)
+        );
+        assert_eq!(
+            (hs[1].name(), hs[1].offset, hs[1].length),
+            ("PADDING", 42, 256)
+        );
+        assert!(hs[1].last && !hs[0].last);
+        assert!(read_block_headers(Cursor::new(&f[1..])).is_err());
+    }
+}
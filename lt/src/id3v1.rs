@@ -0,0 +1,348 @@
+//! ID3v1 and ID3v1.1 tags, the 128 byte TAG trailer at the end of older MP3 files.
+//!
+//! Each field has a fixed size and is padded with zeros, so titles longer than
+//! 30 characters are cut short. ID3v1.1 takes the last two bytes of the comment
+//! for a zero and the track number.
+//!
+//! Reference: [https://id3.org/ID3v1](https://id3.org/ID3v1)
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of the tag.
+pub const SIZE: u64 = 128;
+/// Size of the title, artist and album fields.
+pub const FIELD_SIZE: usize = 30;
+const HEADER: &[u8] = b"TAG";
+/// Genre number used for no genre.
+pub const NO_GENRE: u8 = 255;
+
+/// Genre names by genre number, the 80 of ID3v1 and Winamp's extensions.
+pub const GENRES: [&str; 192] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "AlternRock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+    // Winamp extensions.
+    "Folk",
+    "Folk-Rock",
+    "National Folk",
+    "Swing",
+    "Fast Fusion",
+    "Bebob",
+    "Latin",
+    "Revival",
+    "Celtic",
+    "Bluegrass",
+    "Avantgarde",
+    "Gothic Rock",
+    "Progressive Rock",
+    "Psychedelic Rock",
+    "Symphonic Rock",
+    "Slow Rock",
+    "Big Band",
+    "Chorus",
+    "Easy Listening",
+    "Acoustic",
+    "Humour",
+    "Speech",
+    "Chanson",
+    "Opera",
+    "Chamber Music",
+    "Sonata",
+    "Symphony",
+    "Booty Bass",
+    "Primus",
+    "Porn Groove",
+    "Satire",
+    "Slow Jam",
+    "Club",
+    "Tango",
+    "Samba",
+    "Folklore",
+    "Ballad",
+    "Power Ballad",
+    "Rhythmic Soul",
+    "Freestyle",
+    "Duet",
+    "Punk Rock",
+    "Drum Solo",
+    "A capella",
+    "Euro-House",
+    "Dance Hall",
+    "Goa",
+    "Drum & Bass",
+    "Club-House",
+    "Hardcore",
+    "Terror",
+    "Indie",
+    "BritPop",
+    "Afro-Punk",
+    "Polsk Punk",
+    "Beat",
+    "Christian Gangsta Rap",
+    "Heavy Metal",
+    "Black Metal",
+    "Crossover",
+    "Contemporary Christian",
+    "Christian Rock",
+    "Merengue",
+    "Salsa",
+    "Thrash Metal",
+    "Anime",
+    "JPop",
+    "Synthpop",
+    "Abstract",
+    "Art Rock",
+    "Baroque",
+    "Bhangra",
+    "Big Beat",
+    "Breakbeat",
+    "Chillout",
+    "Downtempo",
+    "Dub",
+    "EBM",
+    "Eclectic",
+    "Electro",
+    "Electroclash",
+    "Emo",
+    "Experimental",
+    "Garage",
+    "Global",
+    "IDM",
+    "Illbient",
+    "Industro-Goth",
+    "Jam Band",
+    "Krautrock",
+    "Leftfield",
+    "Lounge",
+    "Math Rock",
+    "New Romantic",
+    "Nu-Breakz",
+    "Post-Punk",
+    "Post-Rock",
+    "Psytrance",
+    "Shoegaze",
+    "Space Rock",
+    "Trop Rock",
+    "World Music",
+    "Neoclassical",
+    "Audiobook",
+    "Audio Theatre",
+    "Neue Deutsche Welle",
+    "Podcast",
+    "Indie Rock",
+    "G-Funk",
+    "Dubstep",
+    "Garage Rock",
+    "Psybient",
+];
+
+/// What's in an ID3v1 or ID3v1.1 tag.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Id3v1 {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub year: String,
+    pub comment: String,
+    /// Track number, only in ID3v1.1.
+    pub track: Option<u8>,
+    /// Genre number, see `GENRES`.
+    pub genre: u8,
+}
+
+impl Id3v1 {
+    /// Parse a tag from the 128 bytes at the start of b.
+    /// Returns None if they aren't a tag.
+    pub fn parse(b: &[u8]) -> Option<Id3v1> {
+        let b = b.get(..SIZE as usize)?;
+        if !b.starts_with(HEADER) {
+            return None;
+        }
+        let comment = &b[97..127];
+        // ID3v1.1 has a zero before the track number where the comment ends.
+        let (comment, track) = if comment[28] == 0 && comment[29] != 0 {
+            (&comment[..28], Some(comment[29]))
+        } else {
+            (comment, None)
+        };
+        Some(Id3v1 {
+            title: latin1(&b[3..33]),
+            artist: latin1(&b[33..63]),
+            album: latin1(&b[63..93]),
+            year: latin1(&b[93..97]),
+            comment: latin1(comment),
+            track,
+            genre: b[127],
+        })
+    }
+
+    /// "ID3v1.1" if the tag has a track number, otherwise "ID3v1".
+    pub fn version(&self) -> &'static str {
+        match self.track {
+            Some(_) => "ID3v1.1",
+            None => "ID3v1",
+        }
+    }
+
+    /// Name of the genre, None for no genre or a number past the end of `GENRES`.
+    pub fn genre_name(&self) -> Option<&'static str> {
+        GENRES.get(self.genre as usize).copied()
+    }
+}
+
+// Fields are ISO-8859-1, which maps straight to the first 256 characters,
+// padded with zeros (or sometimes spaces).
+fn latin1(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    let s: String = b[..end].iter().map(|&c| c as char).collect();
+    s.trim_end().to_string()
+}
+
+/// Read the tag at the end of a file, if there is one.
+pub fn read(mut r: impl Read + Seek) -> Result<Option<Id3v1>, Box<dyn Error>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    if len < SIZE {
+        return Ok(None);
+    }
+    let mut b = [0; SIZE as usize];
+    r.seek(SeekFrom::Start(len - SIZE))?;
+    r.read_exact(&mut b)?;
+    Ok(Id3v1::parse(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tag(title: &[u8], comment: &[u8], genre: u8) -> Vec<u8> {
+        let mut b = b"TAG".to_vec();
+        for (s, n) in &[
+            (title, 30),
+            (&b"Artist"[..], 30),
+            (b"Album", 30),
+            (b"1970", 4),
+        ] {
+            let mut f = s.to_vec();
+            f.resize(*n, 0);
+            b.extend_from_slice(&f);
+        }
+        let mut c = comment.to_vec();
+        c.resize(30, 0);
+        b.extend_from_slice(&c);
+        b.push(genre);
+        b
+    }
+
+    #[test]
+    fn test_id3v1() {
+        let v1 = Id3v1::parse(&tag(b"Title", b"A comment", 17)).unwrap();
+        assert_eq!(v1.title, "Title");
+        assert_eq!((v1.artist.as_str(), v1.album.as_str()), ("Artist", "Album"));
+        assert_eq!(v1.year, "1970");
+        assert_eq!(v1.comment, "A comment");
+        assert_eq!(v1.track, None);
+        assert_eq!(v1.version(), "ID3v1");
+        assert_eq!(v1.genre_name(), Some("Rock"));
+
+        // ID3v1.1 with a track number and Latin-1 text.
+        let mut comment = b"Short".to_vec();
+        comment.resize(28, 0);
+        comment.extend_from_slice(&[0, 7]);
+        let v11 = Id3v1::parse(&tag(b"Caf\xe9", &comment, NO_GENRE)).unwrap();
+        assert_eq!(v11.title, "Caf\u{e9}");
+        assert_eq!(v11.comment, "Short");
+        assert_eq!(v11.track, Some(7));
+        assert_eq!(v11.version(), "ID3v1.1");
+        assert_eq!(v11.genre_name(), None);
+
+        assert_eq!(GENRES[191], "Psybient");
+        assert_eq!(Id3v1::parse(b"TAG"), None);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut file = vec![0xFF; 1000];
+        file.extend_from_slice(&tag(b"Title", b"", 0));
+        let v1 = read(Cursor::new(&file)).unwrap().unwrap();
+        assert_eq!(v1.genre_name(), Some("Blues"));
+        assert_eq!(read(Cursor::new(&file[..1000])).unwrap(), None);
+    }
+}
@@ -0,0 +1,634 @@
+//! ID3v2 frames that the id3 crate leaves as unknown bytes, and the typed entries
+//! `ID3Metadata` keeps for them and the frames it does decode.
+//!
+//! These are the popularimeter (POPM) and play counter (PCNT), unique file
+//! identifier (UFID), private (PRIV), general encapsulated object (GEOB), and the
+//! chapter (CHAP) and table of contents (CTOC) frames of the ID3v2 chapter addendum.
+//!
+//! References:
+//!
+//! [https://id3.org/id3v2.4.0-frames](https://id3.org/id3v2.4.0-frames)
+//!
+//! [https://id3.org/id3v2-chapters-1.0](https://id3.org/id3v2-chapters-1.0)
+use crate::mpeg3;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+/// UFID owner used by MusicBrainz for the recording id.
+pub const MUSICBRAINZ_OWNER: &str = "http://musicbrainz.org";
+
+// Text encodings.
+const LATIN1: u8 = 0;
+const UTF16: u8 = 1;
+const UTF16BE: u8 = 2;
+
+// Size of a frame header in ID3v2.3 and ID3v2.4.
+const FRAME_HEADER_SIZE: usize = 10;
+
+/// User defined text, from a TXXX frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserText {
+    pub description: String,
+    pub value: String,
+}
+
+/// A URL, from one of the W frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// Frame id, e.g. WOAR for the artist's page or WXXX for a user defined link.
+    pub id: String,
+    /// Only for WXXX.
+    pub description: String,
+    pub url: String,
+}
+
+/// Unsynchronised lyrics, from a USLT frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lyrics {
+    /// ISO 639-2 language code.
+    pub lang: String,
+    pub description: String,
+    pub text: String,
+}
+
+/// Synchronised lyrics or text, from a SYLT frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncedLyrics {
+    pub lang: String,
+    /// What the text is, e.g. Lyrics, Chord or Event.
+    pub content_type: String,
+    /// Whether the times are milliseconds, rather than MPEG frames.
+    pub milliseconds: bool,
+    /// (time, text) of each line.
+    pub lines: Vec<(u32, String)>,
+}
+
+/// A rating and play count for a user, from a POPM frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Popularimeter {
+    pub email: String,
+    /// 1 is worst and 255 best, 0 is unknown.
+    pub rating: u8,
+    pub play_count: u64,
+}
+
+impl Popularimeter {
+    /// The rating as 0 to 5 stars, the way Windows Media Player maps them.
+    pub fn stars(&self) -> u8 {
+        match self.rating {
+            0 => 0,
+            1..=31 => 1,
+            32..=95 => 2,
+            96..=159 => 3,
+            160..=223 => 4,
+            _ => 5,
+        }
+    }
+}
+
+/// An identifier for the track in some database, from a UFID frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniqueId {
+    /// Who gave out the identifier, usually a URL, e.g. `MUSICBRAINZ_OWNER`.
+    pub owner: String,
+    pub id: Vec<u8>,
+}
+
+impl UniqueId {
+    /// The identifier as text, they're usually ASCII.
+    pub fn id_string(&self) -> String {
+        String::from_utf8_lossy(&self.id).to_string()
+    }
+}
+
+/// Data private to some application, from a PRIV frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Private {
+    pub owner: String,
+    pub data: Vec<u8>,
+}
+
+/// A file of any kind, from a GEOB frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub mime_type: String,
+    pub filename: String,
+    pub description: String,
+    pub data: Vec<u8>,
+}
+
+/// A chapter, from a CHAP frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Identifies the chapter in a table of contents.
+    pub element_id: String,
+    /// Start and end in milliseconds.
+    pub start: u32,
+    pub end: u32,
+    /// From a TIT2 frame inside the chapter.
+    pub title: Option<String>,
+}
+
+/// A table of contents, from a CTOC frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableOfContents {
+    pub element_id: String,
+    /// The root of the tables of contents.
+    pub top_level: bool,
+    /// Whether the children are in order.
+    pub ordered: bool,
+    /// Element ids of the chapters and tables of contents in this one.
+    pub children: Vec<String>,
+    /// From a TIT2 frame inside the table of contents.
+    pub title: Option<String>,
+}
+
+impl fmt::Display for TableOfContents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}: {}",
+            self.element_id,
+            if self.top_level { " top level" } else { "" },
+            if self.ordered { " ordered" } else { "" },
+            self.children.join(", ")
+        )
+    }
+}
+
+/// Parse the body of a POPM frame.
+pub fn parse_popm(b: &[u8]) -> Option<Popularimeter> {
+    let (email, b) = terminated(LATIN1, b)?;
+    let (&rating, counter) = b.split_first()?;
+    Some(Popularimeter {
+        email,
+        rating,
+        // The counter may be left out.
+        play_count: counter_value(counter),
+    })
+}
+
+/// Parse the body of a PCNT frame, the number of times the track's been played.
+pub fn parse_pcnt(b: &[u8]) -> Option<u64> {
+    if b.len() < 4 {
+        return None;
+    }
+    Some(counter_value(b))
+}
+
+/// Parse the body of a UFID frame.
+pub fn parse_ufid(b: &[u8]) -> Option<UniqueId> {
+    let (owner, id) = terminated(LATIN1, b)?;
+    Some(UniqueId {
+        owner,
+        id: id.to_vec(),
+    })
+}
+
+/// Parse the body of a PRIV frame.
+pub fn parse_priv(b: &[u8]) -> Option<Private> {
+    let (owner, data) = terminated(LATIN1, b)?;
+    Some(Private {
+        owner,
+        data: data.to_vec(),
+    })
+}
+
+/// Parse the body of a GEOB frame.
+pub fn parse_geob(b: &[u8]) -> Option<Object> {
+    let (&enc, b) = b.split_first()?;
+    let (mime_type, b) = terminated(LATIN1, b)?;
+    let (filename, b) = terminated(enc, b)?;
+    let (description, data) = terminated(enc, b)?;
+    Some(Object {
+        mime_type,
+        filename,
+        description,
+        data: data.to_vec(),
+    })
+}
+
+/// Parse the body of a CHAP frame from a tag of major version 3 or 4.
+pub fn parse_chap(b: &[u8], version: u8) -> Option<Chapter> {
+    let (element_id, b) = terminated(LATIN1, b)?;
+    let times = b.get(..16)?;
+    Some(Chapter {
+        element_id,
+        start: be_u32(&times[0..4]),
+        end: be_u32(&times[4..8]),
+        // The byte offsets that follow are rarely used.
+        title: sub_frame_title(&b[16..], version),
+    })
+}
+
+/// Parse the body of a CTOC frame from a tag of major version 3 or 4.
+pub fn parse_ctoc(b: &[u8], version: u8) -> Option<TableOfContents> {
+    let (element_id, b) = terminated(LATIN1, b)?;
+    let (&flags, b) = b.split_first()?;
+    let (&count, mut b) = b.split_first()?;
+    let mut children = Vec::new();
+    for _ in 0..count {
+        let (child, rest) = terminated(LATIN1, b)?;
+        children.push(child);
+        b = rest;
+    }
+    Some(TableOfContents {
+        element_id,
+        top_level: flags & 0x02 != 0,
+        ordered: flags & 0x01 != 0,
+        children,
+        title: sub_frame_title(b, version),
+    })
+}
+
+// The text of a TIT2 in the frames embedded in a CHAP or CTOC.
+fn sub_frame_title(mut b: &[u8], version: u8) -> Option<String> {
+    while b.len() >= FRAME_HEADER_SIZE {
+        let id = &b[..4];
+        let size = if version >= 4 {
+            b[4..8]
+                .iter()
+                .fold(0usize, |s, &x| s << 7 | (x & 0x7F) as usize)
+        } else {
+            be_u32(&b[4..8]) as usize
+        };
+        let body = b.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + size)?;
+        if id == b"TIT2" {
+            let (&enc, text) = body.split_first()?;
+            return Some(decode(enc, text));
+        }
+        b = &b[FRAME_HEADER_SIZE + size..];
+    }
+    None
+}
+
+/// Decode text in one of the ID3v2 encodings: 0 ISO-8859-1, 1 UTF-16 with
+/// a byte order mark, 2 UTF-16 big endian and 3 UTF-8. Trailing zeros are dropped.
+pub fn decode(enc: u8, b: &[u8]) -> String {
+    let s = match enc {
+        LATIN1 => b.iter().map(|&c| c as char).collect(),
+        UTF16 | UTF16BE => {
+            let (little, b) = match b {
+                [0xFF, 0xFE, rest @ ..] if enc == UTF16 => (true, rest),
+                [0xFE, 0xFF, rest @ ..] if enc == UTF16 => (false, rest),
+                _ => (false, b),
+            };
+            let units: Vec<u16> = b
+                .chunks_exact(2)
+                .map(|c| {
+                    if little {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(b).to_string(),
+    };
+    s.trim_end_matches('\0').to_string()
+}
+
+// Text in encoding enc up to its terminator, and what's after the terminator.
+// UTF-16 text ends with two zero bytes on a character boundary, the others with one.
+fn terminated(enc: u8, b: &[u8]) -> Option<(String, &[u8])> {
+    let end = match enc {
+        UTF16 | UTF16BE => (0..b.len() / 2)
+            .map(|i| i * 2)
+            .find(|&i| b[i] == 0 && b[i + 1] == 0)?,
+        _ => b.iter().position(|&c| c == 0)?,
+    };
+    let width = if enc == UTF16 || enc == UTF16BE { 2 } else { 1 };
+    Some((decode(enc, &b[..end]), &b[end + width..]))
+}
+
+fn counter_value(b: &[u8]) -> u64 {
+    b.iter().fold(0u64, |n, &x| n << 8 | x as u64)
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// The (id, value) of each frame of a tag, in order.
+pub type Frames = Vec<(String, String)>;
+
+/// A text frame whose value differs between two versions of a tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameChange {
+    pub id: String,
+    /// None when the frame is added.
+    pub old: Option<String>,
+    /// None when the frame is removed.
+    pub new: Option<String>,
+}
+
+impl fmt::Display for FrameChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(o), Some(n)) => write!(f, "{}: {} -> {}", self.id, o, n),
+            (None, Some(n)) => write!(f, "{}: add {}", self.id, n),
+            (Some(o), None) => write!(f, "{}: remove {}", self.id, o),
+            (None, None) => write!(f, "{}", self.id),
+        }
+    }
+}
+
+// ID3v2.4 frames with no ID3v2.3 equivalent: encoding, release and tagging times,
+// mood, produced notice and set subtitle.
+const V24_ONLY: [&str; 6] = ["TDEN", "TDRL", "TDTG", "TMOO", "TPRO", "TSST"];
+// ID3v2.3 frames dropped from ID3v2.4: recording dates and size.
+const V23_ONLY: [&str; 2] = ["TRDA", "TSIZ"];
+
+/// Whether a frame id is a plain text frame, T*** other than the user text TXXX.
+/// These are the only frames that are converted between versions or set.
+pub fn is_text_frame(id: &str) -> bool {
+    id.len() == 4 && id.starts_with('T') && id != "TXXX"
+}
+
+/// Convert the (id, value) text frames of a tag to ID3v2 major version 3 or 4.
+/// Other frames, e.g. APIC or TXXX, are kept as they are, repeats included.
+///
+/// ID3v2.4 keeps the recording time in TDRC, "yyyy-MM-ddTHH:mm", and the original
+/// release time in TDOR, where ID3v2.3 has the year, "ddMM" date and "HHmm" time
+/// in TYER, TDAT and TIME, and the original year in TORY. ID3v2.4 separates
+/// values with a zero, ID3v2.3 with a "/". Frames the other version doesn't
+/// have are dropped. Converted frames take the place of the first frame they
+/// came from.
+pub fn convert_text_frames(frames: &[(String, String)], to: u8) -> Frames {
+    let get = |id: &str| {
+        frames
+            .iter()
+            .find(|(i, _)| i == id)
+            .map(|(_, v)| v.as_str())
+    };
+    let mut out = Frames::new();
+    let push = |out: &mut Frames, id: &str, v: String| {
+        if !v.is_empty() && !out.iter().any(|(i, _)| i == id) {
+            out.push((id.to_string(), v));
+        }
+    };
+    for (id, v) in frames {
+        if !is_text_frame(id) {
+            out.push((id.clone(), v.clone()));
+            continue;
+        }
+        match (to, id.as_str()) {
+            (4, "TYER") | (4, "TDAT") | (4, "TIME") => {
+                let t = get("TDRC")
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| recording_time(get("TYER"), get("TDAT"), get("TIME")));
+                push(&mut out, "TDRC", t);
+            }
+            (4, "TORY") => push(&mut out, "TDOR", v.clone()),
+            (4, i) if V23_ONLY.contains(&i) => (),
+            (3, "TDRC") => {
+                let digits = |r: std::ops::Range<usize>| v.get(r).unwrap_or_default().to_string();
+                push(&mut out, "TYER", digits(0..4));
+                // TDAT is "ddMM" and TIME "HHmm", so they need the whole date and time.
+                if v.len() >= 10 {
+                    push(&mut out, "TDAT", digits(8..10) + &digits(5..7));
+                }
+                if v.len() >= 16 {
+                    push(&mut out, "TIME", digits(11..13) + &digits(14..16));
+                }
+            }
+            (3, "TDOR") => push(&mut out, "TORY", v.chars().take(4).collect()),
+            (3, i) if V24_ONLY.contains(&i) => (),
+            (3, _) => push(&mut out, id, v.replace('\0', "/")),
+            _ => push(&mut out, id, v.clone()),
+        }
+    }
+    out
+}
+
+// An ID3v2.4 TDRC time from the ID3v2.3 year, "ddMM" date and "HHmm" time.
+fn recording_time(year: Option<&str>, date: Option<&str>, time: Option<&str>) -> String {
+    let mut t = year.unwrap_or_default().to_string();
+    if let Some(d) = date.filter(|d| d.len() == 4 && t.len() == 4) {
+        t += &format!("-{}-{}", &d[2..4], &d[0..2]);
+        if let Some(h) = time.filter(|h| h.len() == 4) {
+            t += &format!("T{}:{}", &h[0..2], &h[2..4]);
+        }
+    }
+    t
+}
+
+/// The differences between the frames of a tag before and after a change,
+/// in the order the frames appear. Values of frames with the same id are joined.
+/// Frames that aren't text only change by being removed.
+pub fn frame_changes(old: &[(String, String)], new: &[(String, String)]) -> Vec<FrameChange> {
+    let values = |fs: &[(String, String)], id: &str| {
+        let vs: Vec<&str> = fs
+            .iter()
+            .filter(|(i, _)| i == id)
+            .map(|(_, v)| v.as_str())
+            .collect();
+        Some(vs.join("; ")).filter(|_| !vs.is_empty())
+    };
+    let mut ids: Vec<&str> = Vec::new();
+    for (id, _) in old.iter().chain(new) {
+        if !ids.contains(&id.as_str()) {
+            ids.push(id);
+        }
+    }
+    ids.into_iter()
+        .filter_map(|id| {
+            let (o, n) = (values(old, id), values(new, id));
+            Some(FrameChange {
+                id: id.to_string(),
+                old: o.clone(),
+                new: n.clone(),
+            })
+            .filter(|_| o != n && (is_text_frame(id) || n.is_none()))
+        })
+        .collect()
+}
+
+/// The size and major version of each ID3v2 tag at the start of a file,
+/// there's more than one when a tagger added a tag instead of replacing the old one.
+pub fn leading_tags(mut r: impl Read + Seek) -> Result<Vec<(u64, u8)>, Box<dyn Error>> {
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    let mut header = [0; 10];
+    loop {
+        r.seek(SeekFrom::Start(offset))?;
+        if r.read_exact(&mut header).is_err() {
+            break;
+        }
+        let size = mpeg3::id3v2_size(&header) as u64;
+        if size == 0 {
+            break;
+        }
+        tags.push((size, header[3]));
+        offset += size;
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tit2(title: &str, version: u8) -> Vec<u8> {
+        let size = title.len() as u32 + 1;
+        let size = if version >= 4 {
+            // Synchsafe, though it only matters past 127.
+            (size & 0x7F) | (size & 0x3F80) << 1
+        } else {
+            size
+        };
+        let mut b = b"TIT2".to_vec();
+        b.extend_from_slice(&size.to_be_bytes());
+        b.extend_from_slice(&[0, 0, 3]);
+        b.extend_from_slice(title.as_bytes());
+        b
+    }
+
+    #[test]
+    fn test_frames() {
+        let popm = parse_popm(b"me@example.com\0\xC4\x00\x00\x01\x00").unwrap();
+        assert_eq!(popm.email, "me@example.com");
+        assert_eq!((popm.rating, popm.stars(), popm.play_count), (196, 4, 256));
+        assert_eq!(parse_popm(b"me\0\x01").unwrap().play_count, 0);
+        assert_eq!(parse_pcnt(&[0, 0, 0, 9]), Some(9));
+        assert_eq!(parse_pcnt(&[9]), None);
+
+        let ufid = parse_ufid(b"http://musicbrainz.org\0a1b2-c3").unwrap();
+        assert_eq!(ufid.owner, MUSICBRAINZ_OWNER);
+        assert_eq!(ufid.id_string(), "a1b2-c3");
+        let pv = parse_priv(b"WM/MediaClassPrimaryID\0\x01\x02").unwrap();
+        assert_eq!(
+            (pv.owner.as_str(), pv.data.len()),
+            ("WM/MediaClassPrimaryID", 2)
+        );
+
+        // UTF-16 file name and description.
+        let geob =
+            parse_geob(b"\x01text/plain\0\xFF\xFEa\0.\0t\0\0\0\xFF\xFEd\0\0\0hello").unwrap();
+        assert_eq!(geob.mime_type, "text/plain");
+        assert_eq!(geob.filename, "a.t");
+        assert_eq!(geob.description, "d");
+        assert_eq!(geob.data, b"hello");
+        assert_eq!(parse_ufid(b"no terminator"), None);
+    }
+
+    fn frames(fs: &[(&str, &str)]) -> Vec<(String, String)> {
+        fs.iter()
+            .map(|(i, v)| (i.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_convert() {
+        let v23 = frames(&[
+            ("TIT2", "Title"),
+            ("TYER", "1971"),
+            ("TDAT", "0105"),
+            ("TIME", "2030"),
+            ("TPE1", "One/Two"),
+            ("TORY", "1970"),
+            ("TSIZ", "1234"),
+        ]);
+        let v24 = convert_text_frames(&v23, 4);
+        assert_eq!(
+            v24,
+            frames(&[
+                ("TIT2", "Title"),
+                ("TDRC", "1971-05-01T20:30"),
+                ("TPE1", "One/Two"),
+                ("TDOR", "1970"),
+            ])
+        );
+        let changes = frame_changes(&v23, &v24);
+        assert_eq!(
+            changes.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec![
+                "TYER: remove 1971",
+                "TDAT: remove 0105",
+                "TIME: remove 2030",
+                "TORY: remove 1970",
+                "TSIZ: remove 1234",
+                "TDRC: add 1971-05-01T20:30",
+                "TDOR: add 1970",
+            ]
+        );
+
+        // Back again, with ID3v2.4 multiple values.
+        let mut v24 = v24;
+        v24.push(("TCON".to_string(), "Rock\0Blues".to_string()));
+        v24.push(("TMOO".to_string(), "Mellow".to_string()));
+        let back = convert_text_frames(&v24, 3);
+        assert_eq!(back[..4], v23[..4]);
+        assert_eq!(
+            back[4..],
+            frames(&[
+                ("TPE1", "One/Two"),
+                ("TORY", "1970"),
+                ("TCON", "Rock/Blues")
+            ])[..]
+        );
+
+        // Just a year, and a version that's already right.
+        let y = frames(&[("TDRC", "1971")]);
+        assert_eq!(convert_text_frames(&y, 3), frames(&[("TYER", "1971")]));
+        assert_eq!(convert_text_frames(&y, 4), y);
+        assert!(frame_changes(&y, &y).is_empty());
+
+        // A partial date or time is left out rather than written short.
+        assert_eq!(
+            convert_text_frames(&frames(&[("TDRC", "1971-05")]), 3),
+            frames(&[("TYER", "1971")])
+        );
+        assert_eq!(
+            convert_text_frames(&frames(&[("TDRC", "1971-05-01T20")]), 3),
+            frames(&[("TYER", "1971"), ("TDAT", "0105")])
+        );
+    }
+
+    #[test]
+    fn test_leading_tags() {
+        let tag = |v: u8, size: u8| {
+            let mut b = vec![b'I', b'D', b'3', v, 0, 0, 0, 0, 0, size];
+            b.resize(10 + size as usize, 0);
+            b
+        };
+        let file = [tag(4, 20), tag(3, 5), vec![0xFF, 0xFB, 0x90, 0x44]].concat();
+        let tags = leading_tags(std::io::Cursor::new(&file)).unwrap();
+        assert_eq!(tags, vec![(30, 4), (15, 3)]);
+        assert!(leading_tags(std::io::Cursor::new(&file[45..]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chapters() {
+        for &version in &[3, 4] {
+            let mut chap = b"ch1\0".to_vec();
+            for t in &[1000u32, 61_000, u32::MAX, u32::MAX] {
+                chap.extend_from_slice(&t.to_be_bytes());
+            }
+            chap.extend_from_slice(&tit2("Intro", version));
+            let c = parse_chap(&chap, version).unwrap();
+            assert_eq!(
+                c,
+                Chapter {
+                    element_id: "ch1".to_string(),
+                    start: 1000,
+                    end: 61_000,
+                    title: Some("Intro".to_string())
+                }
+            );
+        }
+
+        let mut ctoc = b"toc\0\x03\x02ch1\0ch2\0".to_vec();
+        ctoc.extend_from_slice(&tit2("Contents", 3));
+        let t = parse_ctoc(&ctoc, 3).unwrap();
+        assert!(t.top_level && t.ordered);
+        assert_eq!(t.children, vec!["ch1", "ch2"]);
+        assert_eq!(t.title.as_deref(), Some("Contents"));
+        assert_eq!(t.to_string(), "toc top level ordered: ch1, ch2");
+    }
+}
@@ -0,0 +1,292 @@
+//! Reader for the free-form info files that come with live recordings
+//! (e.g. etree trading folders).
+//!
+//! There's no standard for these, but most look something like:
+//! ```text
+//! ARTIST
+//! 1970-11-20
+//! VENUE,CITY,STATE
+//!
+//! 1-FIRST SONG > SECOND SONG
+//! 2-THIRD SONG >
+//! 3-FOURTH SONG
+//!
+//! SB MASTER > ANALOG 1ST GEN > WAVE > FLAC
+//! ```
+//! The lines before the first track are the header: the artist first, then the date
+//! and the venue. The track lines are numbered in order, a trailing `>` marks a segue
+//! into the next track. The lineage is the first group of lines after the tracks that
+//! are chained with `>`.
+use crate::manifest;
+use crate::track::Track;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INFO_EXTENSION: &str = "txt";
+const SEGUE: char = '>';
+// Characters that can separate a track number from its title.
+const NUMBER_SEPARATORS: &[char] = &['-', '.', ')', ':', ' ', '\t'];
+// Characters that can separate the parts of a date.
+const DATE_SEPARATORS: &[char] = &['-', '/', '.'];
+
+/// A numbered track from an info file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InfoTrack {
+    pub number: u32,
+    /// The title without a trailing segue marker.
+    pub title: String,
+    /// True if this track segues into the next one.
+    pub segue: bool,
+}
+
+impl InfoTrack {
+    /// The title as it's usually tagged, with " >" added for a segue.
+    pub fn tag_title(&self) -> String {
+        if self.segue {
+            format!("{} {}", self.title, SEGUE)
+        } else {
+            self.title.clone()
+        }
+    }
+}
+
+/// The details found in an info file.
+#[derive(Debug, Default)]
+pub struct Info {
+    pub artist: Option<String>,
+    /// The date as written, without any trailing notes e.g. "(?)".
+    pub date: Option<String>,
+    pub venue: Option<String>,
+    pub tracks: Vec<InfoTrack>,
+    /// Each step of the recording's lineage, source first.
+    pub lineage: Vec<String>,
+}
+
+impl Info {
+    /// The info track for each of tracks, or None if there isn't one.
+    /// Tracks are matched by track number, tracks without a number are matched
+    /// by their position in file name order.
+    pub fn match_tracks(&self, tracks: &[Track]) -> Vec<Option<&InfoTrack>> {
+        let mut by_name: Vec<&Track> = tracks.iter().collect();
+        by_name.sort_by(|a, b| a.path.cmp(&b.path));
+
+        tracks
+            .iter()
+            .map(|tk| match tk.track_number {
+                Some(n) => self.tracks.iter().find(|it| it.number == n),
+                None => by_name
+                    .iter()
+                    .position(|t| t.path == tk.path)
+                    .and_then(|i| self.tracks.get(i)),
+            })
+            .collect()
+    }
+}
+
+/// Parse the text of an info file.
+pub fn parse(s: &str) -> Info {
+    let mut info = Info {
+        ..Default::default()
+    };
+    let lines: Vec<&str> = s.lines().map(|l| l.trim()).collect();
+
+    // Header, up to the first track.
+    let mut header = Vec::new();
+    let mut i = 0;
+    while i < lines.len() && track_line(lines[i], 1).is_none() {
+        if !lines[i].is_empty() {
+            header.push(lines[i]);
+        }
+        i += 1;
+    }
+    if i == lines.len() {
+        // No tracks, so no way to tell the header from the notes.
+        header.truncate(3);
+    }
+    let mut rest = header.iter();
+    info.artist = rest.next().map(|a| a.to_string());
+    let rest: Vec<&&str> = rest.collect();
+    info.date = rest.iter().find_map(|l| date(l));
+    info.venue = rest
+        .iter()
+        .filter(|l| date(l).is_none())
+        .find(|l| l.contains(','))
+        .map(|l| {
+            l.split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+
+    // Tracks, numbered in order.
+    while i < lines.len() {
+        if let Some((n, t)) = track_line(lines[i], info.tracks.len() as u32 + 1) {
+            let segue = t.ends_with(SEGUE);
+            info.tracks.push(InfoTrack {
+                number: n,
+                title: t.trim_end_matches(SEGUE).trim_end().to_string(),
+                segue,
+            });
+        } else if !info.tracks.is_empty() && !lines[i].is_empty() {
+            break;
+        }
+        i += 1;
+    }
+
+    // Lineage, the first block of lines after the tracks chained with '>'.
+    while i < lines.len() && !lines[i].contains(SEGUE) {
+        i += 1;
+    }
+    while i < lines.len() && !lines[i].is_empty() {
+        info.lineage.extend(
+            lines[i]
+                .split(SEGUE)
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string()),
+        );
+        i += 1;
+    }
+
+    info
+}
+
+// A track line is the expected track number followed by a separator and a title.
+fn track_line(l: &str, expected: u32) -> Option<(u32, &str)> {
+    let digits = l.find(|c: char| !c.is_ascii_digit())?;
+    let n = l[..digits].parse::<u32>().ok()?;
+    let t = &l[digits..];
+    if n != expected || !t.starts_with(NUMBER_SEPARATORS) {
+        return None;
+    }
+    let t = t.trim_start_matches(NUMBER_SEPARATORS).trim();
+    if t.is_empty() {
+        None
+    } else {
+        Some((n, t))
+    }
+}
+
+// The first word of a line, if it's a date like 1970-11-20, 11/20/70 or 20.11.1970.
+fn date(l: &str) -> Option<String> {
+    let d = l.split_whitespace().next()?;
+    let parts: Vec<&str> = d.split(DATE_SEPARATORS).collect();
+    if parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+    {
+        Some(d.to_string())
+    } else {
+        None
+    }
+}
+
+/// Read and parse an info file.
+pub fn read_info(p: &Path) -> Result<Info, Box<dyn Error>> {
+    // These are often not UTF-8.
+    let b = fs::read(p)?;
+    Ok(parse(&String::from_utf8_lossy(&b)))
+}
+
+/// Find the info file in a directory: the first .txt file, that isn't a manifest,
+/// with numbered tracks in it.
+pub fn info_from(dir: &Path) -> Result<Option<(PathBuf, Info)>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for f in dir.read_dir()?.flatten() {
+        let p = f.path();
+        let txt = matches!(p.extension(), Some(e) if e.eq_ignore_ascii_case(INFO_EXTENSION));
+        if txt && p.is_file() && manifest::manifest_kind(&p).is_none() {
+            paths.push(p);
+        }
+    }
+    paths.sort();
+
+    for p in paths {
+        let info = read_info(&p)?;
+        if !info.tracks.is_empty() {
+            return Ok(Some((p, info)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "test_audio/LEON RUSSELL FILLMORE EAST NOV 20 1970 SB";
+
+    #[test]
+    fn test_info_from() {
+        let (p, info) = info_from(Path::new(TEST_DIR)).unwrap().unwrap();
+        assert_eq!(
+            p.file_name().unwrap(),
+            "LEON RUSSELL FILLMORE EAST NOV 20 1970 SB.txt"
+        );
+        assert_eq!(info.artist.as_deref(), Some("LEON RUSSELL"));
+        assert_eq!(info.date.as_deref(), Some("1970-11-20"));
+        assert_eq!(info.venue.as_deref(), Some("FILLMORE EAST, NEW YORK, NY"));
+
+        assert_eq!(info.tracks.len(), 20);
+        assert_eq!(
+            info.tracks[0].title,
+            "BILL GRAHAM INTRO > GIRL FROM THE NORTH COUNTRY"
+        );
+        assert!(!info.tracks[0].segue);
+        assert_eq!(info.tracks[13].title, "GIVE PEACE A CHANCE");
+        assert!(info.tracks[13].segue);
+        assert_eq!(info.tracks[14].tag_title(), "MASTERS OF WAR >");
+        assert_eq!(info.tracks[19].number, 20);
+
+        assert_eq!(
+            info.lineage,
+            vec![
+                "SB MASTER",
+                "ANALOG 1ST GEN",
+                "UNKNOWN TRANSFER INFO",
+                "WAVE",
+                "TRADERS LITTLE HELPER",
+                "FLAC"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match_tracks() {
+        let info = parse("Artist\n11/20/70\n\n01. One\n02. Two >\n03) Three\n");
+        assert_eq!(info.date.as_deref(), Some("11/20/70"));
+        assert_eq!(info.venue, None);
+
+        let tks = vec![
+            Track {
+                path: PathBuf::from("b.flac"),
+                ..Default::default()
+            },
+            Track {
+                path: PathBuf::from("a.flac"),
+                ..Default::default()
+            },
+            Track {
+                path: PathBuf::from("c.flac"),
+                track_number: Some(3),
+                ..Default::default()
+            },
+        ];
+        let ms: Vec<_> = info
+            .match_tracks(&tks)
+            .iter()
+            .map(|m| m.map(|it| it.tag_title()))
+            .collect();
+        assert_eq!(
+            ms,
+            vec![
+                Some("Two >".to_string()),
+                Some("One".to_string()),
+                Some("Three".to_string())
+            ]
+        );
+    }
+}
@@ -4,17 +4,28 @@ extern crate prettytable;
 extern crate num_derive;
 // #[macro_use]
 // extern crate lazy_static;
+pub mod aiff;
 pub mod album;
+pub mod ape;
+pub mod art;
 pub mod cmd;
 pub mod completion;
+pub mod cue;
 pub mod display;
 pub mod file;
 pub mod flac;
 pub mod id3;
+pub mod id3v1;
+pub mod id3v2;
+pub mod info;
+pub mod manifest;
 pub mod mp3;
 pub mod mp4;
+pub mod mpeg3;
 #[macro_use]
 pub mod mpeg4;
+pub mod riff;
 pub mod run;
+pub mod spec;
 pub mod track;
 pub mod wav;
@@ -0,0 +1,533 @@
+//! Reading and checking of the checksum manifests that travel with audio folders.
+//!
+//! Two kinds of manifest are supported:
+//!
+//! FLAC fingerprint files (`.ffp`), with one `name.flac:md5` line per file, where
+//! the md5 is the signature of the decoded audio stored in the FLAC STREAMINFO block.
+//!
+//! md5sum files (`.md5`), with one `md5 *name` line per file, where the md5 is
+//! computed over the whole file.
+//!
+//! Manifests can be verified against a directory, or generated from one.
+use crate::file;
+use crate::file::FileFormat;
+use crate::track;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const FFP_EXTENSION: &str = "ffp";
+const MD5_EXTENSION: &str = "md5";
+// Trading folders often ship the md5sum output as "<name> md5.txt".
+const MD5_TXT_SUFFIX: &str = "md5.txt";
+// The manifests we've seen in the wild are written with DOS line endings.
+const LINE_ENDING: &str = "\r\n";
+
+/// The kind of checksum a manifest carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestKind {
+    /// FLAC fingerprint: STREAMINFO md5 of the decoded audio.
+    Fingerprint,
+    /// md5sum of the whole file.
+    Checksum,
+}
+
+impl fmt::Display for ManifestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestKind::Fingerprint => f.write_str("FLAC Fingerprint"),
+            ManifestKind::Checksum => f.write_str("MD5 Checksum"),
+        }
+    }
+}
+
+/// A single line of a manifest.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// File name as written in the manifest, relative to the manifest's directory.
+    pub file_name: String,
+    /// Lower case hex md5.
+    pub hash: String,
+}
+
+/// A manifest file and its entries.
+#[derive(Debug)]
+pub struct Manifest {
+    /// Location of the manifest file.
+    pub path: PathBuf,
+    /// What the hashes in the manifest are computed over.
+    pub kind: ManifestKind,
+    /// Entries in the order they appear in the file.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Result of checking one file against a manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The file was found and the hash matches.
+    Match,
+    /// The file was found but the hash doesn't match (or couldn't be computed).
+    Mismatch,
+    /// The file is listed in the manifest but isn't in the directory.
+    Missing,
+    /// The file is in the directory but isn't listed in the manifest.
+    Extra,
+    /// The file couldn't be read, e.g. a FLAC file too corrupt to find its signature in.
+    Unreadable,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Match => f.write_str("OK"),
+            Status::Mismatch => f.write_str("MISMATCH"),
+            Status::Missing => f.write_str("MISSING"),
+            Status::Extra => f.write_str("EXTRA"),
+            Status::Unreadable => f.write_str("UNREADABLE"),
+        }
+    }
+}
+
+/// The outcome of verifying a single file.
+#[derive(Debug)]
+pub struct Verification {
+    pub file_name: String,
+    /// Hash from the manifest, None for extra files.
+    pub expected: Option<String>,
+    /// Hash computed from the file, None if it couldn't be computed.
+    pub actual: Option<String>,
+    pub status: Status,
+    /// Why the file couldn't be read, for Unreadable.
+    pub error: Option<String>,
+}
+
+/// Determine the kind of manifest from the file name, None if it isn't one.
+pub fn manifest_kind(p: &Path) -> Option<ManifestKind> {
+    let name = p.file_name()?.to_string_lossy().to_lowercase();
+    match p.extension().map(|e| e.to_string_lossy().to_lowercase()) {
+        Some(ref e) if e == FFP_EXTENSION => Some(ManifestKind::Fingerprint),
+        Some(ref e) if e == MD5_EXTENSION => Some(ManifestKind::Checksum),
+        _ if name.ends_with(MD5_TXT_SUFFIX) => Some(ManifestKind::Checksum),
+        _ => None,
+    }
+}
+
+/// Find and read all of the manifests in a directory.
+pub fn manifests_from(p: &Path) -> Result<Vec<Manifest>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    if p.is_dir() {
+        for f in p.read_dir()?.flatten() {
+            paths.push(f.path());
+        }
+    } else if p.is_file() {
+        paths.push(p.to_path_buf());
+    }
+    paths.sort();
+
+    let mut manifests = Vec::new();
+    for p in paths {
+        if p.is_file() && manifest_kind(&p).is_some() {
+            manifests.push(read_manifest(&p)?);
+        }
+    }
+    Ok(manifests)
+}
+
+/// Read a manifest from a file.
+pub fn read_manifest(p: &Path) -> Result<Manifest, Box<dyn Error>> {
+    let kind = match manifest_kind(p) {
+        Some(k) => k,
+        None => {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Not a manifest file: {}", p.display()),
+            )))
+        }
+    };
+
+    // Manifests are usually ASCII, but file names may not be.
+    let bytes = fs::read(p)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let entries = text
+        .lines()
+        .filter_map(|l| match kind {
+            ManifestKind::Fingerprint => parse_ffp_line(l),
+            ManifestKind::Checksum => parse_md5_line(l),
+        })
+        .collect();
+
+    Ok(Manifest {
+        path: p.to_path_buf(),
+        kind,
+        entries,
+    })
+}
+
+/// Parse a `name.flac:md5` line.
+/// Returns None for blank lines, comments (`;` or `#`) and lines that don't parse.
+pub fn parse_ffp_line(l: &str) -> Option<ManifestEntry> {
+    let l = l.trim();
+    if l.is_empty() || l.starts_with(';') || l.starts_with('#') {
+        return None;
+    }
+    // File names can contain ':' so split on the last one.
+    let i = l.rfind(':')?;
+    let (name, hash) = (l[0..i].trim(), l[i + 1..].trim());
+    if name.is_empty() || !is_md5(hash) {
+        return None;
+    }
+    Some(ManifestEntry {
+        file_name: name.to_string(),
+        hash: hash.to_lowercase(),
+    })
+}
+
+/// Parse a `md5 *name` (binary mode) or `md5  name` (text mode) line.
+/// Returns None for blank lines, comments (`;` or `#`) and lines that don't parse.
+pub fn parse_md5_line(l: &str) -> Option<ManifestEntry> {
+    let l = l.trim();
+    if l.is_empty() || l.starts_with(';') || l.starts_with('#') {
+        return None;
+    }
+    let i = l.find(char::is_whitespace)?;
+    let (hash, name) = (&l[0..i], l[i..].trim_start());
+    let name = name.strip_prefix('*').unwrap_or(name);
+    if name.is_empty() || !is_md5(hash) {
+        return None;
+    }
+    Some(ManifestEntry {
+        file_name: name.to_string(),
+        hash: hash.to_lowercase(),
+    })
+}
+
+fn is_md5(s: &str) -> bool {
+    s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Compute the md5 of an entire file, as a lower case hex string.
+pub fn file_md5(p: &Path) -> Result<String, io::Error> {
+    let mut f = File::open(p)?;
+    let mut ctx = md5::Context::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.consume(&buf[0..n]);
+    }
+    Ok(format!("{:x}", ctx.compute()))
+}
+
+/// Get the STREAMINFO audio md5 from a FLAC file.
+/// Returns None if the file isn't a FLAC file or has no signature.
+pub fn stream_md5(p: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(tk) = track::get_track(&p.to_path_buf())? {
+        if let Some(track::FormatMetadata::Flac(md)) = tk.metadata {
+            return Ok(md.md5);
+        }
+    }
+    Ok(None)
+}
+
+fn is_flac(p: &Path) -> Result<bool, io::Error> {
+    let f = File::open(p)?;
+    Ok(matches!(file::identify(f)?, Some(FileFormat::Flac(_))))
+}
+
+/// Check every entry of a manifest against the files in the manifest's directory,
+/// and report files in the directory that the manifest doesn't list.
+///
+/// For fingerprints only FLAC files are considered extra, for checksums
+/// any regular file other than the manifest itself is.
+/// Files that can't be read are reported as Unreadable, and the rest are still checked.
+pub fn verify(m: &Manifest) -> Result<Vec<Verification>, Box<dyn Error>> {
+    let dir = match m.path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut vs = Vec::new();
+    for e in &m.entries {
+        // Windows tools write sub-directories with '\'.
+        let p = dir.join(e.file_name.replace("\\", "/"));
+        let mut error = None;
+        let (actual, status) = if !p.is_file() {
+            (None, Status::Missing)
+        } else {
+            let actual = match m.kind {
+                ManifestKind::Fingerprint => stream_md5(&p),
+                ManifestKind::Checksum => file_md5(&p).map(Some).map_err(|e| e.into()),
+            };
+            match actual {
+                Ok(a) if a.as_ref() == Some(&e.hash) => (a, Status::Match),
+                Ok(a) => (a, Status::Mismatch),
+                Err(err) => {
+                    error = Some(err.to_string());
+                    (None, Status::Unreadable)
+                }
+            }
+        };
+        vs.push(Verification {
+            file_name: e.file_name.clone(),
+            expected: Some(e.hash.clone()),
+            actual,
+            status,
+            error,
+        });
+    }
+
+    // Anything left in the directory is extra.
+    let mut extras = Vec::new();
+    let mut unreadable = Vec::new();
+    for f in dir.read_dir()? {
+        let p = match f {
+            Ok(f) => f.path(),
+            Err(_) => continue,
+        };
+        if !p.is_file() || p == m.path {
+            continue;
+        }
+        let name = file_name(&p);
+        if m.entries.iter().any(|e| e.file_name == name) {
+            continue;
+        }
+        if m.kind == ManifestKind::Fingerprint {
+            match is_flac(&p) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    unreadable.push((name, e.to_string()));
+                    continue;
+                }
+            }
+        }
+        extras.push(name);
+    }
+    extras.sort();
+    for name in extras {
+        vs.push(Verification {
+            file_name: name,
+            expected: None,
+            actual: None,
+            status: Status::Extra,
+            error: None,
+        });
+    }
+    unreadable.sort();
+    for (name, e) in unreadable {
+        vs.push(Verification {
+            file_name: name,
+            expected: None,
+            actual: None,
+            status: Status::Unreadable,
+            error: Some(e),
+        });
+    }
+
+    Ok(vs)
+}
+
+impl ManifestEntry {
+    /// The entry as a line in a manifest of the given kind, without a line ending.
+    pub fn line(&self, kind: ManifestKind) -> String {
+        match kind {
+            ManifestKind::Fingerprint => format!("{}:{}", self.file_name, self.hash),
+            ManifestKind::Checksum => format!("{} *{}", self.hash, self.file_name),
+        }
+    }
+}
+
+impl Manifest {
+    /// The contents of the manifest file.
+    pub fn text(&self) -> String {
+        let mut s = String::new();
+        for e in &self.entries {
+            s += &e.line(self.kind);
+            s += LINE_ENDING;
+        }
+        s
+    }
+
+    /// Write the manifest to its path, replacing any file already there.
+    pub fn write(&self) -> Result<(), io::Error> {
+        fs::write(&self.path, self.text())
+    }
+}
+
+/// Create a fingerprint (.ffp) and a checksum (.md5) manifest for the files in a directory.
+/// Neither is written, use `Manifest::write` for that.
+///
+/// The manifests are named after the directory. The fingerprint lists the FLAC tracks
+/// that `track::files_from` finds, and is left out if there aren't any. The checksum
+/// lists every regular file, tracks and non-audio files alike, including the new
+/// fingerprint but not itself.
+pub fn generate(p: &Path) -> Result<Vec<Manifest>, Box<dyn Error>> {
+    if !p.is_dir() {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("File is not a directory: {} ", p.display()),
+        )));
+    }
+    let dir_name = match p.canonicalize()?.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => "manifest".to_string(),
+    };
+    let ffp_path = p.join(format!("{}.{}", dir_name, FFP_EXTENSION));
+    let md5_path = p.join(format!("{}.{}", dir_name, MD5_EXTENSION));
+
+    let (tracks, files) = track::files_from(p.to_path_buf())?;
+
+    let mut ffp = Manifest {
+        path: ffp_path,
+        kind: ManifestKind::Fingerprint,
+        entries: Vec::new(),
+    };
+    let mut md5 = Manifest {
+        path: md5_path,
+        kind: ManifestKind::Checksum,
+        entries: Vec::new(),
+    };
+
+    let mut paths = Vec::new();
+    for tk in tracks {
+        if let Some(track::FormatMetadata::Flac(md)) = tk.metadata {
+            match md.md5 {
+                Some(h) => ffp.entries.push(ManifestEntry {
+                    file_name: file_name(&tk.path),
+                    hash: h,
+                }),
+                None => eprintln!("No audio MD5 in: {}", tk.path.display()),
+            }
+        }
+        paths.push(tk.path);
+    }
+    paths.extend(files.into_iter().filter(|f| f.is_file()));
+
+    // These get replaced, so their current contents don't count.
+    paths.retain(|f| *f != ffp.path && *f != md5.path);
+    for f in paths {
+        md5.entries.push(ManifestEntry {
+            file_name: file_name(&f),
+            hash: file_md5(&f)?,
+        });
+    }
+
+    let mut ms = Vec::new();
+    if !ffp.entries.is_empty() {
+        ffp.entries.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        md5.entries.push(ManifestEntry {
+            file_name: file_name(&ffp.path),
+            hash: format!("{:x}", md5::compute(ffp.text().as_bytes())),
+        });
+        ms.push(ffp);
+    }
+    md5.entries.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    ms.push(md5);
+
+    Ok(ms)
+}
+
+fn file_name(p: &Path) -> String {
+    match p.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => p.to_string_lossy().into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "test_audio/LEON RUSSELL FILLMORE EAST NOV 20 1970 SB";
+
+    #[test]
+    fn test_parse_lines() {
+        let e = parse_ffp_line("LRUSSELL FE 11 20 70 01.flac:be26b1cbd5dd49ab0cd3e31affd87a3c\r")
+            .unwrap();
+        assert_eq!(e.file_name, "LRUSSELL FE 11 20 70 01.flac");
+        assert_eq!(e.hash, "be26b1cbd5dd49ab0cd3e31affd87a3c");
+
+        let e = parse_md5_line("293F4136A8AAAD2E64E0B366F533480A *LRUSSELL FE 11 20 70 01.flac")
+            .unwrap();
+        assert_eq!(e.file_name, "LRUSSELL FE 11 20 70 01.flac");
+        assert_eq!(e.hash, "293f4136a8aaad2e64e0b366f533480a");
+
+        let e = parse_md5_line("293f4136a8aaad2e64e0b366f533480a  text mode.flac").unwrap();
+        assert_eq!(e.file_name, "text mode.flac");
+
+        assert_eq!(parse_ffp_line(""), None);
+        assert_eq!(parse_ffp_line("; comment"), None);
+        assert_eq!(parse_ffp_line("no hash here.flac:"), None);
+        assert_eq!(parse_md5_line("No errors occured."), None);
+    }
+
+    #[test]
+    fn test_read_manifests() {
+        let ms = manifests_from(Path::new(TEST_DIR)).unwrap();
+        assert_eq!(ms.len(), 3);
+        for m in &ms {
+            match m.kind {
+                ManifestKind::Fingerprint => assert_eq!(m.entries.len(), 20),
+                ManifestKind::Checksum => assert_eq!(m.entries.len(), 22),
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let m = read_manifest(
+            &Path::new(TEST_DIR).join("LEON RUSSELL FILLMORE EAST NYC NOV 20 1970 SB.md5"),
+        )
+        .unwrap();
+        let vs = verify(&m).unwrap();
+
+        // The flac files aren't in the fixture, only the info file and the ffp are.
+        // The info file has been edited since the manifest was made.
+        let count = |s| vs.iter().filter(|v| v.status == s).count();
+        assert_eq!(count(Status::Missing), 20);
+        assert_eq!(count(Status::Match), 1);
+        assert_eq!(count(Status::Mismatch), 1);
+        assert!(vs
+            .iter()
+            .any(|v| v.status == Status::Extra && v.file_name == "album.yaml"));
+    }
+
+    #[test]
+    fn test_lines() {
+        let e = ManifestEntry {
+            file_name: "LRUSSELL FE 11 20 70 01.flac".to_string(),
+            hash: "be26b1cbd5dd49ab0cd3e31affd87a3c".to_string(),
+        };
+        assert_eq!(parse_ffp_line(&e.line(ManifestKind::Fingerprint)), Some(e));
+
+        // Regenerating the fixture's fingerprint gives back the same file.
+        let p = Path::new(TEST_DIR).join("LEON RUSSELL FILLMORE EAST NYC NOV 20 1970 SB.ffp");
+        let m = read_manifest(&p).unwrap();
+        assert_eq!(m.text().as_bytes(), fs::read(&p).unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_generate() {
+        // No flac files in the fixture, so just a checksum manifest.
+        let ms = generate(Path::new(TEST_DIR)).unwrap();
+        assert_eq!(ms.len(), 1);
+        let m = &ms[0];
+        assert_eq!(m.kind, ManifestKind::Checksum);
+        assert_eq!(
+            file_name(&m.path),
+            "LEON RUSSELL FILLMORE EAST NOV 20 1970 SB.md5"
+        );
+        let e = m
+            .entries
+            .iter()
+            .find(|e| e.file_name == "LEON RUSSELL FILLMORE EAST NYC NOV 20 1970 SB.ffp")
+            .unwrap();
+        assert_eq!(e.hash, "98fbd5b7459503b698a3a83efa85782f");
+    }
+}
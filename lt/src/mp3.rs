@@ -1,16 +1,20 @@
 //! Entrypoints integrated with Albums and Track into MP3 metadata reading.
+use crate::ape;
+use crate::art::Artwork;
 use crate::file;
+use crate::id3v1;
+use crate::id3v2;
+use crate::mpeg3;
 use crate::track;
 use id3::Tag;
-use mp3_metadata;
 
 // use puremp3;
 // use std::collections::HashMap;
 use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-// use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-// use std::path::PathBuf;
 
 /// MP3 file reader.
 #[derive(Default, Debug)]
@@ -20,14 +24,14 @@ pub struct Mp3;
 // of either determining the number of bytes it needs or to
 // fail reasonablly if the buffer isn't big enough.
 // TODO(jdr): Add ID3 tag version information.
-// TODO(jdr): This doesn't handle APE or version 1 ID3 tags. Do it.
 // Obviously it would be good to get the standard, but buying it seems
 // a little ridiculous and I haven't yet found it in torrents.
 
 const ID3_HEADER: &[u8] = b"ID3";
 /// Identifies files that are MP3 files.
-/// Start with the ID3 variants, currently doesn't support much else.
-/// Buffer wants 3 bytes. It will return None if b.len() < 3.
+/// Either they start with an ID3v2 tag, or with the sync of an
+/// MPEG 1, 2 or 2.5 layer I, II or III frame.
+/// Buffer wants 4 bytes. It will return None if b.len() < 3.
 ///
 /// References:
 ///
@@ -35,7 +39,7 @@ const ID3_HEADER: &[u8] = b"ID3";
 ///
 /// [http://id3.org/Home](http://id3.org/Home)
 pub fn identify(b: &[u8]) -> Option<file::FileFormat> {
-    if b.len() >= 3 && &b[0..3] == ID3_HEADER {
+    if (b.len() >= 3 && &b[0..3] == ID3_HEADER) || mpeg3::FrameHeader::parse(b).is_some() {
         Some(file::FileFormat::MP3(Mp3 {}))
     } else {
         None
@@ -52,130 +56,183 @@ impl file::Decoder for Mp3 {
         &mut self,
         mut r: impl Read + Seek,
     ) -> Result<Option<track::Track>, Box<dyn Error>> {
-        let mut buf = Vec::new();
-        r.read_to_end(&mut buf)?;
-        let md = mp3_metadata::read_from_slice(&buf)?;
-
         // Create a track.
         let mut tk = track::Track {
             file_format: Some(FORMAT_NAME.to_string()),
             ..Default::default()
         };
 
-        // Grab the metadata and fill in the track.
-        // println!("Path: {}", self.path.as_path().display());
-        if let Some(t) = &md.tag {
-            tk.title = Some(t.title.clone());
-            tk.artist = Some(t.artist.clone());
-            tk.album = Some(t.album.clone());
-        }
-
-        for oi in &md.optional_info {
-            tk.track_number = oi.track_number.as_ref().and_then(|v| {
-                let sp: Vec<&str> = v.split('/').collect();
-                if sp.len() > 1 {
-                    tk.track_total = parse_to_opt(sp[1]);
-                };
-
-                // return through the map.
-                parse_to_opt(sp[0])
-            });
-        }
-
-        if !md.frames.is_empty() {
-            // let mut br = HashMap::new();
-            // let mut sf = HashMap::new();
-            // for f in &md.frames {
-            //     let mut c = br.entry(f.bitrate).or_insert(0);
-            //     *c += 1;
-            //     c = sf.entry(f.sampling_freq).or_insert(0);
-            //     *c += 1;
-            // }
-            // for (k, v) in &br {
-            //     println!("birate: {}  {} times", k, v)
-            // }
-            // for (k, v) in &sf {
-            //     println!("sample freq: {}  {} times", k, v)
-            // }
-
-            // TODO(jdr) Clearly these are not really the right values.
-            // Definitely needs more investigation. See the commented
-            // code above.
-            let f = &md.frames[0];
-            let li = md.frames.len() - 1;
-            let lf = &md.frames[li];
-            let lfd;
-            if let Some(d) = lf.duration {
-                lfd = d;
-            } else {
-                lfd = Duration::new(0, 0);
-            }
-            let mf = track::MPEG3Format {
-                bitrate: f.bitrate,
-                sample_rate: f.sampling_freq,
-                duration: lf.position + lfd,
-                version: match f.version {
-                    mp3_metadata::Version::Reserved => track::MPVersion::Reserved,
-                    mp3_metadata::Version::MPEG1 => track::MPVersion::MPEG1,
-                    mp3_metadata::Version::MPEG2 => track::MPVersion::MPEG2,
-                    mp3_metadata::Version::MPEG2_5 => track::MPVersion::MPEG2_5,
-                    mp3_metadata::Version::Unknown => track::MPVersion::Unknown,
-                },
-                layer: match f.layer {
-                    mp3_metadata::Layer::Reserved => track::MP3Layer::Reserved,
-                    mp3_metadata::Layer::Layer1 => track::MP3Layer::Layer1,
-                    mp3_metadata::Layer::Layer2 => track::MP3Layer::Layer2,
-                    mp3_metadata::Layer::Layer3 => track::MP3Layer::Layer3,
-                    mp3_metadata::Layer::Unknown => track::MP3Layer::Unknown,
-                },
-            };
-            tk.format = Some(track::CodecFormat::MPEG3(mf));
-        };
+        // The audio format comes from the first frame, and its Xing or VBRI header if it has one.
+        if let Some(s) = mpeg3::read_stream(&mut r)? {
+            tk.format = Some(track::CodecFormat::MPEG3(track::MPEG3Format {
+                bitrate: (s.bitrate() / 1000) as u16,
+                sample_rate: s.header.sample_rate as u16,
+                duration: s.duration(),
+                version: s.header.version,
+                layer: s.header.layer,
+                frames: s.frames(),
+                vbr: s.vbr(),
+                gapless: s.gapless(),
+            }));
+        }
 
         // Now use the ID3 package to get the complete set of ID3 tags from
         // the file.
         r.seek(SeekFrom::Start(0))?;
-        let tag = Tag::read_from(r)?;
+        let tag = match Tag::read_from(&mut r) {
+            Ok(t) => t,
+            Err(id3::Error {
+                kind: id3::ErrorKind::NoTag,
+                ..
+            }) => Tag::new(),
+            Err(e) => return Err(Box::new(e)),
+        };
 
-        let omd = if tag.frames().count() > 0 {
-            let mut md = track::ID3Metadata {
-                ..Default::default()
-            };
+        let mut md = read_tag(&mut tk, &tag);
 
-            for fr in tag.frames() {
-                // eprintln!("Frame: {:?}", fr);
-                match fr.content() {
-                    id3::Content::Text(s) => {
-                        update_track(&mut tk, &fr, s);
-                        md.text
-                            .entry(fr.id().to_string())
-                            .and_modify(|v| v.push(s.clone()))
-                            .or_insert_with(|| vec![s.clone()]);
-                        // eprintln!("md: {:?}", md);
-                    }
-                    id3::Content::Comment(c) => {
-                        md.comments
-                            .entry(fr.id().to_string())
-                            .and_modify(|v| {
-                                v.push((c.lang.clone(), c.description.clone(), c.text.clone()))
-                            })
-                            .or_insert_with(|| {
-                                vec![(c.lang.clone(), c.description.clone(), c.text.clone())]
-                            });
-                    }
-                    _ => (),
-                }
-            }
+        // Then the ID3v1 and APEv2 tags at the end of the file, which only fill in
+        // what the ID3v2 tag didn't have, APEv2 first.
+        md.v1 = id3v1::read(&mut r)?;
+        let mut end = r.seek(SeekFrom::End(0))?;
+        if md.v1.is_some() {
+            end -= id3v1::SIZE;
+        }
+        md.ape = ape::read(&mut r, end)?;
+        fill_from_trailing_tags(&mut tk, &md);
+
+        tk.metadata = if tag.frames().count() > 0 || md.v1.is_some() || md.ape.is_some() {
             Some(track::FormatMetadata::ID3(md))
         } else {
             None
         };
-        tk.metadata = omd;
 
         Ok(Some(tk))
     }
 }
 
+/// Fill in the track from the frames of an ID3v2 tag, and return the frames as
+/// metadata. Used for MP3 files and the ID3 chunks of AIFF files.
+pub fn read_tag(tk: &mut track::Track, tag: &Tag) -> track::ID3Metadata {
+    let mut md = track::ID3Metadata {
+        ..Default::default()
+    };
+    // Frames inside CHAP and CTOC have sizes that depend on the version.
+    let version = match tag.version() {
+        id3::Version::Id3v24 => 4,
+        _ => 3,
+    };
+    for fr in tag.frames() {
+        // eprintln!("Frame: {:?}", fr);
+        match fr.content() {
+            id3::Content::Text(s) => {
+                update_track(tk, &fr, s);
+                md.text
+                    .entry(fr.id().to_string())
+                    .and_modify(|v| v.push(s.clone()))
+                    .or_insert_with(|| vec![s.clone()]);
+                // eprintln!("md: {:?}", md);
+            }
+            id3::Content::Comment(c) => {
+                md.comments
+                    .entry(fr.id().to_string())
+                    .and_modify(|v| v.push((c.lang.clone(), c.description.clone(), c.text.clone())))
+                    .or_insert_with(|| {
+                        vec![(c.lang.clone(), c.description.clone(), c.text.clone())]
+                    });
+            }
+            id3::Content::ExtendedText(t) => {
+                update_track_user_text(tk, &t.description, &t.value);
+                md.user_text.push(id3v2::UserText {
+                    description: t.description.clone(),
+                    value: t.value.clone(),
+                });
+            }
+            id3::Content::Link(url) => md.links.push(id3v2::Link {
+                id: fr.id().to_string(),
+                description: String::new(),
+                url: url.clone(),
+            }),
+            id3::Content::ExtendedLink(l) => md.links.push(id3v2::Link {
+                id: fr.id().to_string(),
+                description: l.description.clone(),
+                url: l.link.clone(),
+            }),
+            id3::Content::Lyrics(l) => md.lyrics.push(id3v2::Lyrics {
+                lang: l.lang.clone(),
+                description: l.description.clone(),
+                text: l.text.clone(),
+            }),
+            id3::Content::SynchronisedLyrics(l) => md.synced_lyrics.push(id3v2::SyncedLyrics {
+                lang: l.lang.clone(),
+                content_type: format!("{:?}", l.content_type),
+                milliseconds: l.timestamp_format == id3::frame::TimestampFormat::MS,
+                lines: l.content.clone(),
+            }),
+            id3::Content::Picture(p) => tk.artwork.push(Artwork::new(
+                picture_type(p.picture_type),
+                &p.mime_type,
+                &p.description,
+                p.data.clone(),
+            )),
+            // The id3 crate leaves these frames as bytes.
+            id3::Content::Unknown(b) => match fr.id() {
+                "POPM" => md.ratings.extend(id3v2::parse_popm(b)),
+                "PCNT" => md.play_count = id3v2::parse_pcnt(b),
+                "UFID" => {
+                    if let Some(u) = id3v2::parse_ufid(b) {
+                        if u.owner == id3v2::MUSICBRAINZ_OWNER {
+                            tk.musicbrainz_track_id = Some(u.id_string());
+                        }
+                        md.unique_ids.push(u);
+                    }
+                }
+                "PRIV" => md.private.extend(id3v2::parse_priv(b)),
+                "GEOB" => md.objects.extend(id3v2::parse_geob(b)),
+                "CHAP" => md.chapters.extend(id3v2::parse_chap(b, version)),
+                "CTOC" => md.tocs.extend(id3v2::parse_ctoc(b, version)),
+                _ => (),
+            },
+        }
+    }
+    if tk.chapters.is_empty() {
+        tk.chapters = track_chapters(&md.chapters);
+    }
+    md
+}
+
+// Fill in what's missing from the track from the APEv2 tag, then the ID3v1 tag.
+fn fill_from_trailing_tags(tk: &mut track::Track, md: &track::ID3Metadata) {
+    if let Some(ape) = &md.ape {
+        let text = |k| ape.text(k).map(|s| s.to_string());
+        let number = |k| -> (Option<u32>, Option<u32>) {
+            match ape.text(k) {
+                Some(v) => {
+                    let sp: Vec<&str> = v.split('/').collect();
+                    (parse_to_opt(sp[0]), sp.get(1).and_then(|t| parse_to_opt(t)))
+                }
+                None => (None, None),
+            }
+        };
+        tk.title = tk.title.take().or_else(|| text("Title"));
+        tk.artist = tk.artist.take().or_else(|| text("Artist"));
+        tk.album = tk.album.take().or_else(|| text("Album"));
+        tk.album_artist = tk.album_artist.take().or_else(|| text("Album Artist"));
+        let (n, t) = number("Track");
+        tk.track_number = tk.track_number.or(n);
+        tk.track_total = tk.track_total.or(t);
+        let (n, t) = number("Disc");
+        tk.disk_number = tk.disk_number.or(n);
+        tk.disk_total = tk.disk_total.or(t);
+    }
+    if let Some(v1) = &md.v1 {
+        let text = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        tk.title = tk.title.take().or_else(|| text(&v1.title));
+        tk.artist = tk.artist.take().or_else(|| text(&v1.artist));
+        tk.album = tk.album.take().or_else(|| text(&v1.album));
+        tk.track_number = tk.track_number.or_else(|| v1.track.map(u32::from));
+    }
+}
+
 fn parse_to_opt<T: std::str::FromStr>(s: &str) -> Option<T> {
     match s.parse::<T>() {
         Ok(n) => Some(n),
@@ -183,6 +240,35 @@ fn parse_to_opt<T: std::str::FromStr>(s: &str) -> Option<T> {
     }
 }
 
+// The APIC picture type number.
+fn picture_type(t: id3::frame::PictureType) -> u8 {
+    use id3::frame::PictureType::*;
+    match t {
+        Other => 0,
+        Icon => 1,
+        OtherIcon => 2,
+        CoverFront => 3,
+        CoverBack => 4,
+        Leaflet => 5,
+        Media => 6,
+        LeadArtist => 7,
+        Artist => 8,
+        Conductor => 9,
+        Band => 10,
+        Composer => 11,
+        Lyricist => 12,
+        RecordingLocation => 13,
+        DuringRecording => 14,
+        DuringPerformance => 15,
+        ScreenCapture => 16,
+        BrightFish => 17,
+        Illustration => 18,
+        BandLogo => 19,
+        PublisherLogo => 20,
+        Undefined(n) => n,
+    }
+}
+
 fn update_track(tk: &mut track::Track, fr: &id3::Frame, s: &str) {
     // Some of these values are presented as "num/total".
     let sp: Vec<&str> = s.split('/').collect();
@@ -203,6 +289,9 @@ fn update_track(tk: &mut track::Track, fr: &id3::Frame, s: &str) {
                 tk.track_total = parse_to_opt(sp[1]);
             }
         }
+        "TIT2" => {
+            tk.title.get_or_insert_with(|| s.to_string());
+        }
         "TALB" => {
             if tk.album == None {
                 tk.album = Some(s.to_string());
@@ -223,11 +312,12 @@ fn update_track(tk: &mut track::Track, fr: &id3::Frame, s: &str) {
                 tk.artist = Some(s.to_string());
             }
         }
-        // Band/Orchestra/Accopmaniment
+        // Band/Orchestra/Accopmaniment, which is where album artists usually go.
         "TPE2" => {
             if tk.artist == None {
                 tk.artist = Some(s.to_string());
             }
+            tk.album_artist.get_or_insert_with(|| s.to_string());
         }
         // Conductor
         "TPE3" => {
@@ -238,3 +328,422 @@ fn update_track(tk: &mut track::Track, fr: &id3::Frame, s: &str) {
         _ => (),
     }
 }
+
+// TXXX descriptions taggers use for fields that have no frame of their own.
+fn update_track_user_text(tk: &mut track::Track, description: &str, value: &str) {
+    let value = Some(value.to_string());
+    match description.to_ascii_uppercase().as_str() {
+        "ALBUM ARTIST" | "ALBUMARTIST" => tk.album_artist = value,
+        "MUSICBRAINZ TRACK ID" => {
+            // UFID is where MusicBrainz puts it, so this is only a fallback.
+            if tk.musicbrainz_track_id.is_none() {
+                tk.musicbrainz_track_id = value;
+            }
+        }
+        "MUSICBRAINZ ALBUM ID" => tk.musicbrainz_album_id = value,
+        _ => (),
+    }
+}
+
+// Track chapters from CHAP frames, in order of their start.
+fn track_chapters(chapters: &[id3v2::Chapter]) -> Vec<track::Chapter> {
+    let mut chs: Vec<_> = chapters
+        .iter()
+        .map(|c| track::Chapter {
+            start: Duration::from_millis(c.start as u64),
+            duration: Duration::from_millis(c.end.saturating_sub(c.start) as u64),
+            title: c.title.clone().unwrap_or_else(|| c.element_id.clone()),
+        })
+        .collect();
+    chs.sort_by_key(|c| c.start);
+    chs
+}
+
+/// Set the front cover (APIC) of an MP3 file, replacing any front cover already there.
+/// The tag keeps its major version, a file without an ID3 tag gets a new ID3v2.4 one.
+pub fn write_cover(p: &Path, a: &Artwork) -> Result<(), Box<dyn Error>> {
+    let from = id3v2::leading_tags(File::open(p)?)?
+        .first()
+        .map_or(0, |t| t.1);
+    let mut tag = match Tag::read_from_path(p) {
+        Ok(t) => t,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Tag::new(),
+        Err(e) => return Err(Box::new(e)),
+    };
+    tag.remove_picture_by_type(id3::frame::PictureType::CoverFront);
+    tag.add_picture(id3::frame::Picture {
+        mime_type: a.mime_type.clone(),
+        picture_type: id3::frame::PictureType::CoverFront,
+        description: a.description.clone(),
+        data: a.data.clone(),
+    });
+    let version = if from == 3 {
+        id3::Version::Id3v23
+    } else {
+        id3::Version::Id3v24
+    };
+    tag.write_to_path(p, version)?;
+    Ok(())
+}
+
+/// A change to the ID3 tags of an MP3 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagEdit {
+    /// Set a text frame, e.g. TPE2, to a value.
+    Set(String, String),
+    /// Remove every frame with an id.
+    Remove(String),
+    /// Convert the ID3v2 tag to major version 3 or 4, and remove any
+    /// ID3v2 tags after it and the ID3v1 tag.
+    Normalize(u8),
+}
+
+/// What a tag edit does to an MP3 file.
+#[derive(Debug)]
+pub struct TagUpdate {
+    pub path: PathBuf,
+    /// Major version of the file's ID3v2 tag, 0 if it has none.
+    pub from: u8,
+    /// Major version the tag is written as.
+    pub to: u8,
+    pub changes: Vec<id3v2::FrameChange>,
+    /// ID3v2 tags after the first, which are removed.
+    pub duplicates: usize,
+    /// Whether the ID3v1 tag is removed.
+    pub remove_v1: bool,
+}
+
+impl TagUpdate {
+    /// Whether the update leaves the file as it is.
+    pub fn is_empty(&self) -> bool {
+        self.from == self.to && self.changes.is_empty() && self.duplicates == 0 && !self.remove_v1
+    }
+}
+
+// The (id, value) frames of a tag, with a summary for the value of frames that aren't text.
+// The summaries are only for the preview, those frames are never written from them.
+fn tag_frames(tag: &Tag) -> id3v2::Frames {
+    tag.frames()
+        .map(|fr| {
+            let v = match fr.content() {
+                id3::Content::Text(s) | id3::Content::Link(s) => s.clone(),
+                id3::Content::ExtendedText(t) => format!("{}: {}", t.description, t.value),
+                id3::Content::Comment(c) => c.text.clone(),
+                id3::Content::Lyrics(l) => l.text.clone(),
+                id3::Content::Picture(p) => format!("{} {} bytes", p.mime_type, p.data.len()),
+                _ => fr.id().to_string(),
+            };
+            (fr.id().to_string(), v)
+        })
+        .collect()
+}
+
+// The frames after an edit, and the major version to write them as.
+// Edits other than Normalize keep the version, unless it's ID3v2.2 which
+// can't be written, or there isn't a tag.
+fn edit_frames(
+    frames: &[(String, String)],
+    edit: &TagEdit,
+    from: u8,
+) -> Result<(id3v2::Frames, u8), Box<dyn Error>> {
+    let keep = if from == 3 { 3 } else { 4 };
+    Ok(match edit {
+        TagEdit::Set(id, v) => {
+            if !id3v2::is_text_frame(id) {
+                return Err(format!("{} is not a text frame that can be set.", id).into());
+            }
+            let mut fs: Vec<_> = frames.iter().filter(|(i, _)| i != id).cloned().collect();
+            fs.push((id.clone(), v.clone()));
+            (fs, keep)
+        }
+        TagEdit::Remove(id) => (
+            frames.iter().filter(|(i, _)| i != id).cloned().collect(),
+            keep,
+        ),
+        TagEdit::Normalize(to @ 3) | TagEdit::Normalize(to @ 4) => {
+            (id3v2::convert_text_frames(frames, *to), *to)
+        }
+        TagEdit::Normalize(v) => return Err(format!("Can't write ID3v2.{} tags.", v).into()),
+    })
+}
+
+/// Work out what an edit does to the tags of an MP3 file.
+pub fn plan_tag_update(p: &Path, edit: &TagEdit) -> Result<TagUpdate, Box<dyn Error>> {
+    let mut f = File::open(p)?;
+    let tags = id3v2::leading_tags(&mut f)?;
+    let from = tags.first().map_or(0, |t| t.1);
+    let frames = match Tag::read_from_path(p) {
+        Ok(t) => tag_frames(&t),
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Vec::new(),
+        Err(e) => return Err(Box::new(e)),
+    };
+    let (new, to) = edit_frames(&frames, edit, from)?;
+    let normalize = matches!(edit, TagEdit::Normalize(_));
+    let changes = id3v2::frame_changes(&frames, &new);
+    // A set or remove that changes no frame leaves the file alone, whatever its version.
+    let to = if !normalize && changes.is_empty() {
+        from
+    } else {
+        to
+    };
+    Ok(TagUpdate {
+        path: p.to_path_buf(),
+        from,
+        to,
+        changes,
+        duplicates: if normalize {
+            tags.len().saturating_sub(1)
+        } else {
+            0
+        },
+        remove_v1: normalize && id3v1::read(&mut f)?.is_some(),
+    })
+}
+
+/// Write an update to its file.
+pub fn write_tag_update(u: &TagUpdate) -> Result<(), Box<dyn Error>> {
+    if u.is_empty() {
+        return Ok(());
+    }
+    let mut tag = match Tag::read_from_path(&u.path) {
+        Ok(t) => t,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => Tag::new(),
+        Err(e) => return Err(Box::new(e)),
+    };
+    // Only text frames are set, every other frame stays as it was read.
+    for c in &u.changes {
+        match &c.new {
+            Some(v) if id3v2::is_text_frame(&c.id) => tag.set_text(c.id.as_str(), v.as_str()),
+            Some(_) => return Err(format!("{} is not a text frame that can be set.", c.id).into()),
+            None => {
+                tag.remove(&c.id);
+            }
+        }
+    }
+
+    let version = if u.to == 3 {
+        id3::Version::Id3v23
+    } else {
+        id3::Version::Id3v24
+    };
+    if u.duplicates == 0 && !u.remove_v1 {
+        tag.write_to_path(&u.path, version)?;
+        return Ok(());
+    }
+
+    // Write the new tag and the audio after the old tags, without the ID3v1 tag, to the side
+    // and move it into place, so a failure doesn't leave the file without a tag.
+    let mut f = File::open(&u.path)?;
+    let mut end = f.seek(SeekFrom::End(0))?;
+    if u.remove_v1 {
+        end = end.saturating_sub(id3v1::SIZE);
+    }
+    let start = id3v2::leading_tags(&mut f)?
+        .iter()
+        .map(|t| t.0)
+        .sum::<u64>()
+        .min(end);
+    let mut tmp = u.path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut out = io::BufWriter::new(File::create(&tmp)?);
+    tag.write_to(&mut out, version)?;
+    f.seek(SeekFrom::Start(start))?;
+    io::copy(&mut (&mut f).take(end - start), &mut out)?;
+    out.flush()?;
+    drop(out);
+    fs::rename(&tmp, &u.path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailing_tags() -> track::ID3Metadata {
+        let mut md = track::ID3Metadata {
+            ..Default::default()
+        };
+        md.v1 = Some(id3v1::Id3v1 {
+            title: "A Title That Is Too Long For I".to_string(),
+            artist: "Old Artist".to_string(),
+            album: "Album".to_string(),
+            year: "1970".to_string(),
+            track: Some(3),
+            genre: 17,
+            ..Default::default()
+        });
+        md.ape = Some(ape::ApeTag {
+            version: 2000,
+            offset: 0,
+            items: ape::parse_items(
+                &[
+                    &10u32.to_le_bytes()[..],
+                    &[0; 4],
+                    b"Artist\0APE Artist",
+                    &3u32.to_le_bytes(),
+                    &[0; 4],
+                    b"Track\x003/9",
+                ]
+                .concat(),
+                2,
+            )
+            .unwrap(),
+        });
+        md
+    }
+
+    #[test]
+    fn test_trailing_tags() {
+        // APEv2 comes before ID3v1, and both only fill in what's missing.
+        let md = trailing_tags();
+        let mut tk = track::Track {
+            title: Some("From ID3v2".to_string()),
+            ..Default::default()
+        };
+        fill_from_trailing_tags(&mut tk, &md);
+        assert_eq!(tk.title.as_deref(), Some("From ID3v2"));
+        assert_eq!(tk.artist.as_deref(), Some("APE Artist"));
+        assert_eq!(tk.album.as_deref(), Some("Album"));
+        assert_eq!((tk.track_number, tk.track_total), (Some(3), Some(9)));
+    }
+
+    #[test]
+    fn test_user_text() {
+        let mut tk = track::Track {
+            musicbrainz_track_id: Some("from-ufid".to_string()),
+            ..Default::default()
+        };
+        for (d, v) in &[
+            ("ALBUM ARTIST", "Various Artists"),
+            ("MusicBrainz Track Id", "from-txxx"),
+            ("MusicBrainz Album Id", "album-id"),
+            ("Something Else", "ignored"),
+        ] {
+            update_track_user_text(&mut tk, d, v);
+        }
+        assert_eq!(tk.album_artist.as_deref(), Some("Various Artists"));
+        assert_eq!(tk.musicbrainz_track_id.as_deref(), Some("from-ufid"));
+        assert_eq!(tk.musicbrainz_album_id.as_deref(), Some("album-id"));
+    }
+
+    #[test]
+    fn test_track_chapters() {
+        let chap = |id: &str, start, end, title: Option<&str>| id3v2::Chapter {
+            element_id: id.to_string(),
+            start,
+            end,
+            title: title.map(|t| t.to_string()),
+        };
+        let chs = track_chapters(&[
+            chap("ch2", 60_000, 90_500, None),
+            chap("ch1", 0, 60_000, Some("Intro")),
+        ]);
+        assert_eq!(chs.len(), 2);
+        assert_eq!(chs[0].title, "Intro");
+        assert_eq!(chs[1].title, "ch2");
+        assert_eq!(chs[1].start, Duration::from_secs(60));
+        assert_eq!(chs[1].duration, Duration::from_millis(30_500));
+    }
+
+    #[test]
+    fn test_edit_frames() {
+        let frames: Vec<(String, String)> = [("TIT2", "Title"), ("TYER", "1971"), ("COMM", "Hi")]
+            .iter()
+            .map(|(i, v)| (i.to_string(), v.to_string()))
+            .collect();
+        let set = TagEdit::Set("TIT2".to_string(), "New".to_string());
+        let (fs, to) = edit_frames(&frames, &set, 3).unwrap();
+        assert_eq!(to, 3);
+        assert_eq!(
+            id3v2::frame_changes(&frames, &fs)[0].to_string(),
+            "TIT2: Title -> New"
+        );
+        assert!(edit_frames(
+            &frames,
+            &TagEdit::Set("COMM".to_string(), "x".to_string()),
+            3
+        )
+        .is_err());
+
+        let (fs, to) = edit_frames(&frames, &TagEdit::Remove("COMM".to_string()), 2).unwrap();
+        assert_eq!((fs.len(), to), (2, 4));
+
+        let (fs, to) = edit_frames(&frames, &TagEdit::Normalize(4), 3).unwrap();
+        assert_eq!(to, 4);
+        assert_eq!(fs[1], ("TDRC".to_string(), "1971".to_string()));
+        assert!(edit_frames(&frames, &TagEdit::Normalize(2), 3).is_err());
+
+        let u = TagUpdate {
+            path: PathBuf::new(),
+            from: 4,
+            to: 4,
+            changes: Vec::new(),
+            duplicates: 0,
+            remove_v1: false,
+        };
+        assert!(u.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_keeps_other_frames() {
+        let frames: Vec<(String, String)> = [
+            ("APIC", "image/jpeg 1000 bytes"),
+            ("TXXX", "MusicBrainz Album Id: 1234"),
+            ("TYER", "1971"),
+            ("APIC", "image/png 2000 bytes"),
+            ("TXXX", "REPLAYGAIN_TRACK_GAIN: -6.2 dB"),
+            ("COMM", "One"),
+            ("COMM", "Two"),
+        ]
+        .iter()
+        .map(|(i, v)| (i.to_string(), v.to_string()))
+        .collect();
+        let (fs, _) = edit_frames(&frames, &TagEdit::Normalize(4), 3).unwrap();
+        let count = |id: &str| fs.iter().filter(|(i, _)| i == id).count();
+        assert_eq!((count("APIC"), count("TXXX"), count("COMM")), (2, 2, 2));
+        let changes: Vec<String> = id3v2::frame_changes(&frames, &fs)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(changes, vec!["TYER: remove 1971", "TDRC: add 1971"]);
+
+        let (fs, _) = edit_frames(&frames, &TagEdit::Normalize(3), 4).unwrap();
+        assert!(id3v2::frame_changes(&frames, &fs).is_empty());
+    }
+
+    #[test]
+    fn test_tag_differences() {
+        let mut md = trailing_tags();
+        for (k, v) in &[
+            ("TIT2", "A Title That Is Too Long For ID3v1"),
+            ("TPE1", "APE Artist"),
+            ("TRCK", "03/9"),
+            ("TCON", "(17)"),
+            ("TDRC", "1971-05-01"),
+        ] {
+            md.text.insert(k.to_string(), vec![v.to_string()]);
+        }
+        let diffs: Vec<(&str, &str, String)> = md
+            .differences()
+            .into_iter()
+            .map(|d| (d.field, d.tag, d.value))
+            .collect();
+        // The cut short title, the track number and genre are the same.
+        assert_eq!(
+            diffs,
+            vec![
+                ("Artist", "ID3v1", "Old Artist".to_string()),
+                ("Year", "ID3v1", "1970".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,573 @@
+//! Native reading of MPEG audio (MP3) frames.
+//!
+//! An MP3 file is a run of frames, each with a 4 byte header giving its version,
+//! layer, bitrate and sample rate, usually after an ID3v2 tag. Encoders like LAME
+//! put a Xing (VBR) or Info (CBR) header in the first frame in place of audio,
+//! with the number of frames and, in LAME's extension to it, the encoder delay
+//! and padding needed for gapless playback.
+//!
+//! References:
+//!
+//! [http://mpgedit.org/mpgedit/mpeg_format/mpeghdr.htm](http://mpgedit.org/mpgedit/mpeg_format/mpeghdr.htm)
+//!
+//! [http://gabriel.mp3-tech.org/mp3infotag.html](http://gabriel.mp3-tech.org/mp3infotag.html)
+use crate::ape;
+use crate::id3v1;
+use crate::track::{Gapless, GaplessSource, MP3Layer, MPVersion};
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+const BILLION: u128 = 1_000_000_000;
+
+// Bytes read after the ID3v2 tag looking for the first frame.
+const SCAN_SIZE: usize = 64 * 1024;
+
+const ID3V2_HEADER_SIZE: usize = 10;
+// Flag in the ID3v2 header for a footer after the tag.
+const ID3V2_FOOTER: u8 = 0x10;
+
+// Bitrates in kbit/s by bitrate index, 0 is free format and 15 is not allowed.
+const V1_L1_BITRATES: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const V1_L2_BITRATES: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const V1_L3_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const V2_L1_BITRATES: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const V2_L23_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+// Sample rates of MPEG-1 by sample rate index, MPEG-2 is half and MPEG-2.5 a quarter.
+const V1_SAMPLE_RATES: [u32; 3] = [44_100, 48_000, 32_000];
+
+// Channel mode of a single channel.
+const MONO: u8 = 3;
+
+/// Xing header flags, saying which of the optional fields follow.
+pub const XING_FRAMES: u32 = 0x01;
+pub const XING_BYTES: u32 = 0x02;
+pub const XING_TOC: u32 = 0x04;
+pub const XING_QUALITY: u32 = 0x08;
+const XING_TOC_SIZE: usize = 100;
+
+// Offset of the encoder delay and padding from the start of the LAME extension.
+const LAME_DELAY_OFFSET: usize = 21;
+
+// Offset of a VBRI header in the frame.
+const VBRI_OFFSET: usize = 4 + 32;
+
+/// What's in an MPEG audio frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: MPVersion,
+    pub layer: MP3Layer,
+    /// Bits per second.
+    pub bitrate: u32,
+    /// Samples per second.
+    pub sample_rate: u32,
+    /// 0 stereo, 1 joint stereo, 2 dual channel and 3 mono.
+    pub channel_mode: u8,
+    /// Whether the frame has an extra padding slot.
+    pub padding: bool,
+}
+
+impl FrameHeader {
+    /// Parse the 4 byte frame header at the start of b.
+    /// Returns None if it isn't one: no sync, or a reserved or free format value.
+    pub fn parse(b: &[u8]) -> Option<FrameHeader> {
+        if b.len() < 4 || b[0] != 0xFF || b[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = match (b[1] >> 3) & 0x03 {
+            0 => MPVersion::MPEG2_5,
+            2 => MPVersion::MPEG2,
+            3 => MPVersion::MPEG1,
+            _ => return None,
+        };
+        let layer = match (b[1] >> 1) & 0x03 {
+            1 => MP3Layer::Layer3,
+            2 => MP3Layer::Layer2,
+            3 => MP3Layer::Layer1,
+            _ => return None,
+        };
+        let bitrates = match (version, layer) {
+            (MPVersion::MPEG1, MP3Layer::Layer1) => &V1_L1_BITRATES,
+            (MPVersion::MPEG1, MP3Layer::Layer2) => &V1_L2_BITRATES,
+            (MPVersion::MPEG1, _) => &V1_L3_BITRATES,
+            (_, MP3Layer::Layer1) => &V2_L1_BITRATES,
+            _ => &V2_L23_BITRATES,
+        };
+        let bitrate = *bitrates.get((b[2] >> 4) as usize)? * 1000;
+        let sample_rate = *V1_SAMPLE_RATES.get(((b[2] >> 2) & 0x03) as usize)?;
+        if bitrate == 0 {
+            return None;
+        }
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate,
+            sample_rate: match version {
+                MPVersion::MPEG1 => sample_rate,
+                MPVersion::MPEG2 => sample_rate / 2,
+                _ => sample_rate / 4,
+            },
+            channel_mode: b[3] >> 6,
+            padding: b[2] & 0x02 != 0,
+        })
+    }
+
+    /// Samples of audio in each frame, per channel.
+    pub fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (MP3Layer::Layer1, _) => 384,
+            (MP3Layer::Layer3, MPVersion::MPEG2) | (MP3Layer::Layer3, MPVersion::MPEG2_5) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// Length of the frame in bytes, including the header.
+    pub fn length(&self) -> usize {
+        // Layer 1 slots are 4 bytes, the others 1.
+        let pad = self.padding as usize;
+        match self.layer {
+            MP3Layer::Layer1 => (12 * self.bitrate / self.sample_rate) as usize * 4 + pad * 4,
+            _ => (self.samples() / 8 * self.bitrate / self.sample_rate) as usize + pad,
+        }
+    }
+
+    // Offset of a Xing header in the frame, after the header and the side information.
+    fn xing_offset(&self) -> usize {
+        let mono = self.channel_mode == MONO;
+        4 + match (self.version, mono) {
+            (MPVersion::MPEG1, true) => 17,
+            (MPVersion::MPEG1, false) => 32,
+            (_, true) => 9,
+            (_, false) => 17,
+        }
+    }
+}
+
+/// The LAME extension to a Xing header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lame {
+    /// Encoder and version, e.g. "LAME3.100".
+    pub encoder: String,
+    /// Samples of silence the encoder added at the start.
+    pub delay: u32,
+    /// Samples the encoder added to fill the last frame.
+    pub padding: u32,
+}
+
+/// A Xing or Info header from the first frame of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xing {
+    /// An Info header is written for a constant bitrate file, Xing for a variable one.
+    pub vbr: bool,
+    /// Number of audio frames, not counting the one holding this header.
+    pub frames: Option<u32>,
+    /// Number of bytes of audio.
+    pub bytes: Option<u32>,
+    pub lame: Option<Lame>,
+}
+
+impl Xing {
+    /// Read a Xing or Info header from the frame starting b, with header h.
+    pub fn parse(b: &[u8], h: &FrameHeader) -> Option<Xing> {
+        let mut b = b.get(h.xing_offset()..)?;
+        let vbr = match b.get(..4)? {
+            b"Xing" => true,
+            b"Info" => false,
+            _ => return None,
+        };
+        let flags = be_u32(b.get(4..8)?);
+        b = &b[8..];
+        let mut field = |flag| -> Option<Option<u32>> {
+            if flags & flag == 0 {
+                return Some(None);
+            }
+            let v = be_u32(b.get(..4)?);
+            b = &b[4..];
+            Some(Some(v))
+        };
+        let frames = field(XING_FRAMES)?;
+        let bytes = field(XING_BYTES)?;
+        if flags & XING_TOC != 0 {
+            b = b.get(XING_TOC_SIZE..)?;
+        }
+        if flags & XING_QUALITY != 0 {
+            b = b.get(4..)?;
+        }
+        Some(Xing {
+            vbr,
+            frames,
+            bytes,
+            lame: lame(b),
+        })
+    }
+
+    /// Encoder delay, padding and the exact number of samples from the LAME extension.
+    /// Needs the frame count as well.
+    pub fn gapless(&self, h: &FrameHeader) -> Option<Gapless> {
+        let lame = self.lame.as_ref()?;
+        let total = self.frames? as u64 * h.samples() as u64;
+        Some(Gapless {
+            delay: lame.delay,
+            padding: lame.padding,
+            valid_samples: total.saturating_sub(lame.delay as u64 + lame.padding as u64),
+            source: GaplessSource::Lame,
+        })
+    }
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+// The LAME extension at the start of b, if there is one.
+fn lame(b: &[u8]) -> Option<Lame> {
+    let encoder = b.get(..9)?;
+    if !(encoder.starts_with(b"LAME")
+        || encoder.starts_with(b"Lavc")
+        || encoder.starts_with(b"Lavf"))
+    {
+        return None;
+    }
+    let dp = b.get(LAME_DELAY_OFFSET..LAME_DELAY_OFFSET + 3)?;
+    Some(Lame {
+        encoder: String::from_utf8_lossy(encoder)
+            .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string(),
+        delay: (dp[0] as u32) << 4 | (dp[1] as u32) >> 4,
+        padding: (dp[1] as u32 & 0x0F) << 8 | dp[2] as u32,
+    })
+}
+
+/// Size of the ID3v2 tag at the start of b, including its header and footer,
+/// or 0 if there isn't one.
+pub fn id3v2_size(b: &[u8]) -> usize {
+    if b.len() < ID3V2_HEADER_SIZE || &b[..3] != b"ID3" {
+        return 0;
+    }
+    // The size is synchsafe, 7 bits in each byte.
+    let size = b[6..10]
+        .iter()
+        .fold(0usize, |s, &x| s << 7 | (x & 0x7F) as usize);
+    let footer = if b[5] & ID3V2_FOOTER != 0 {
+        ID3V2_HEADER_SIZE
+    } else {
+        0
+    };
+    ID3V2_HEADER_SIZE + size + footer
+}
+
+/// Where the first frame is in b, skipping any ID3v2 tag and junk before it.
+/// A sync found in the junk is only taken for a frame if another frame
+/// follows it, or it's too near the end of b to tell.
+pub fn find_frame(b: &[u8]) -> Option<(usize, FrameHeader)> {
+    let start = id3v2_size(b);
+    (start..b.len()).find_map(|i| {
+        let h = FrameHeader::parse(&b[i..])?;
+        match b.get(i + h.length()..).and_then(FrameHeader::parse) {
+            Some(next) if next.version == h.version && next.layer == h.layer => Some((i, h)),
+            None if i + h.length() + 4 > b.len() => Some((i, h)),
+            _ => None,
+        }
+    })
+}
+
+/// A VBRI header, written by the Fraunhofer encoder in place of a Xing header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vbri {
+    /// Encoder delay in samples.
+    pub delay: u16,
+    /// Number of bytes of audio.
+    pub bytes: u32,
+    /// Number of audio frames.
+    pub frames: u32,
+}
+
+impl Vbri {
+    /// Read a VBRI header from the frame starting b, it's always 32 bytes after the header.
+    pub fn parse(b: &[u8]) -> Option<Vbri> {
+        let b = b.get(VBRI_OFFSET..VBRI_OFFSET + 18)?;
+        if &b[..4] != b"VBRI" {
+            return None;
+        }
+        Some(Vbri {
+            delay: u16::from_be_bytes([b[6], b[7]]),
+            bytes: be_u32(&b[10..14]),
+            frames: be_u32(&b[14..18]),
+        })
+    }
+}
+
+/// The audio in an MP3 file, from its first frame and the size of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    /// Header of the first frame.
+    pub header: FrameHeader,
+    /// File offset of the first frame.
+    pub offset: u64,
+    pub xing: Option<Xing>,
+    pub vbri: Option<Vbri>,
+    /// Bytes of audio, between any tags at the start and the end of the file.
+    pub bytes: u64,
+}
+
+impl Stream {
+    /// Number of audio frames, if there was a Xing or VBRI header to say.
+    pub fn frames(&self) -> Option<u64> {
+        match (&self.xing, &self.vbri) {
+            (
+                Some(Xing {
+                    frames: Some(n), ..
+                }),
+                _,
+            ) => Some(*n as u64),
+            (_, Some(v)) => Some(v.frames as u64),
+            _ => None,
+        }
+    }
+
+    /// Whether the bitrate varies, which it does if there's a Xing or VBRI header
+    /// (rather than Info).
+    pub fn vbr(&self) -> bool {
+        self.vbri.is_some() || matches!(self.xing, Some(Xing { vbr: true, .. }))
+    }
+
+    /// Length of the audio, exact from the frame count if there is one, otherwise
+    /// worked out from the bitrate of the first frame, which is right for constant bitrate.
+    pub fn duration(&self) -> Duration {
+        let h = &self.header;
+        let ns = match self.frames() {
+            Some(n) => n as u128 * h.samples() as u128 * BILLION / h.sample_rate as u128,
+            None => self.bytes as u128 * 8 * BILLION / h.bitrate as u128,
+        };
+        Duration::from_nanos(ns as u64)
+    }
+
+    /// Average bits per second.
+    pub fn bitrate(&self) -> u32 {
+        let ns = self.duration().as_nanos();
+        if self.frames().is_none() || ns == 0 {
+            return self.header.bitrate;
+        }
+        (self.bytes as u128 * 8 * BILLION / ns) as u32
+    }
+
+    /// Encoder delay and padding from the LAME extension of the Xing header.
+    pub fn gapless(&self) -> Option<Gapless> {
+        self.xing.as_ref()?.gapless(&self.header)
+    }
+}
+
+/// Read the first frame of an MP3 file and what it says about the audio.
+/// Returns None if no frame is found near the start of the file.
+pub fn read_stream(mut r: impl Read + Seek) -> Result<Option<Stream>, Box<dyn Error>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let end = audio_end(&mut r, len)?;
+
+    // Skip the ID3v2 tag without reading it, it may have big pictures in it.
+    let mut header = [0; ID3V2_HEADER_SIZE];
+    r.seek(SeekFrom::Start(0))?;
+    let n = read_up_to(&mut r, &mut header)?;
+    let start = id3v2_size(&header[..n]) as u64;
+
+    let mut buf = vec![0; SCAN_SIZE];
+    r.seek(SeekFrom::Start(start))?;
+    let n = read_up_to(&mut r, &mut buf)?;
+    buf.truncate(n);
+    let (i, h) = match find_frame(&buf) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+
+    let frame = &buf[i..];
+    let xing = Xing::parse(frame, &h);
+    let vbri = Vbri::parse(frame);
+    let offset = start + i as u64;
+    // The Xing or VBRI frame has no audio in it.
+    let audio = if xing.is_some() || vbri.is_some() {
+        offset + h.length() as u64
+    } else {
+        offset
+    };
+    let bytes = match (&xing, &vbri) {
+        (Some(Xing { bytes: Some(b), .. }), _) => *b as u64,
+        (_, Some(v)) => v.bytes as u64,
+        _ => end.saturating_sub(audio),
+    };
+    Ok(Some(Stream {
+        header: h,
+        offset,
+        xing,
+        vbri,
+        bytes,
+    }))
+}
+
+// Where the audio ends, before an ID3v1 tag or APEv2 tag at the end of the file.
+fn audio_end(mut r: impl Read + Seek, len: u64) -> Result<u64, Box<dyn Error>> {
+    let mut end = len;
+    if id3v1::read(&mut r)?.is_some() {
+        end -= id3v1::SIZE;
+    }
+    if let Some(tag) = ape::read(&mut r, end)? {
+        end = tag.offset;
+    }
+    Ok(end)
+}
+
+// Fill as much of buf as there is to read, returning how much that was.
+fn read_up_to(mut r: impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // An MPEG-1 Layer 3 128kbit/s 44.1kHz joint stereo frame header.
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x44];
+
+    // A first frame with a Xing header for frames and the LAME extension.
+    fn xing_frame(tag: &[u8], frames: u32, delay: u32, padding: u32) -> Vec<u8> {
+        let mut f = HEADER.to_vec();
+        f.resize(4 + 32, 0);
+        f.extend_from_slice(tag);
+        f.extend_from_slice(&(XING_FRAMES | XING_TOC).to_be_bytes());
+        f.extend_from_slice(&frames.to_be_bytes());
+        f.extend_from_slice(&[0; XING_TOC_SIZE]);
+        let mut lame = b"LAME3.100".to_vec();
+        lame.resize(LAME_DELAY_OFFSET, 0);
+        lame.extend_from_slice(&[
+            (delay >> 4) as u8,
+            ((delay & 0x0F) << 4 | padding >> 8) as u8,
+            padding as u8,
+        ]);
+        f.extend_from_slice(&lame);
+        f.resize(417, 0);
+        f
+    }
+
+    #[test]
+    fn test_frame_header() {
+        let h = FrameHeader::parse(&HEADER).unwrap();
+        assert_eq!(h.version, MPVersion::MPEG1);
+        assert_eq!(h.layer, MP3Layer::Layer3);
+        assert_eq!((h.bitrate, h.sample_rate), (128_000, 44_100));
+        assert_eq!(h.samples(), 1152);
+        assert_eq!(h.length(), 417);
+
+        // MPEG-2 Layer 3 64kbit/s 22.05kHz mono, with padding.
+        let h = FrameHeader::parse(&[0xFF, 0xF3, 0x82, 0xC0]).unwrap();
+        assert_eq!(h.version, MPVersion::MPEG2);
+        assert_eq!((h.bitrate, h.sample_rate), (64_000, 22_050));
+        assert_eq!(h.samples(), 576);
+        assert_eq!(h.length(), 72 * 64_000 / 22_050 + 1);
+
+        assert_eq!(FrameHeader::parse(b"ID3\x04"), None);
+        // Reserved version.
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xEB, 0x90, 0x44]), None);
+    }
+
+    // A frame with nothing in it after the header.
+    fn frame() -> Vec<u8> {
+        let mut f = HEADER.to_vec();
+        f.resize(417, 0);
+        f
+    }
+
+    fn stream(file: Vec<u8>) -> Stream {
+        read_stream(Cursor::new(file)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_lame_gapless() {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x05".to_vec();
+        file.extend_from_slice(&[0; 5]);
+        file.extend_from_slice(&xing_frame(b"Info", 1000, 576, 1234));
+        assert_eq!(id3v2_size(&file), 15);
+
+        let s = stream(file);
+        assert_eq!(s.offset, 15);
+        assert_eq!(s.frames(), Some(1000));
+        assert!(!s.vbr());
+        assert_eq!(
+            s.duration(),
+            Duration::from_nanos(1000 * 1152 * 1_000_000_000 / 44_100)
+        );
+        let g = s.gapless().unwrap();
+        assert_eq!((g.delay, g.padding), (576, 1234));
+        assert_eq!(g.valid_samples, 1000 * 1152 - 576 - 1234);
+        assert_eq!(g.source, GaplessSource::Lame);
+
+        let frame = xing_frame(b"Xing", 10, 0, 0);
+        let h = FrameHeader::parse(&frame).unwrap();
+        let x = Xing::parse(&frame, &h).unwrap();
+        assert!(x.vbr);
+        assert_eq!(x.frames, Some(10));
+        assert_eq!(x.lame.unwrap().encoder, "LAME3.100");
+    }
+
+    #[test]
+    fn test_find_frame() {
+        // Junk with something that looks like a frame header in it,
+        // but no frame where the next one should be.
+        let mut file = vec![1, 2, 3];
+        file.extend_from_slice(&HEADER);
+        file.extend_from_slice(&[0; 10]);
+        let junk = file.len();
+        for _ in 0..3 {
+            file.extend_from_slice(&frame());
+        }
+        assert_eq!(find_frame(&file).map(|(i, _)| i), Some(junk));
+        assert_eq!(find_frame(&[0; 100]), None);
+    }
+
+    #[test]
+    fn test_cbr() {
+        // No Xing header, and an ID3v1 tag at the end.
+        let mut file = [frame(), frame(), frame()].concat();
+        file.extend_from_slice(b"TAG");
+        file.resize(file.len() + 125, 0);
+        let s = stream(file);
+        assert_eq!((s.offset, s.bytes), (0, 3 * 417));
+        assert_eq!(s.frames(), None);
+        assert_eq!(s.bitrate(), 128_000);
+        assert_eq!(
+            s.duration(),
+            Duration::from_nanos(3 * 417 * 8 * 1_000_000_000 / 128_000)
+        );
+        assert_eq!(s.gapless(), None);
+    }
+
+    #[test]
+    fn test_vbri() {
+        let mut f = frame();
+        f[VBRI_OFFSET..VBRI_OFFSET + 4].copy_from_slice(b"VBRI");
+        f[VBRI_OFFSET + 6..VBRI_OFFSET + 8].copy_from_slice(&576u16.to_be_bytes());
+        f[VBRI_OFFSET + 10..VBRI_OFFSET + 14].copy_from_slice(&200_000u32.to_be_bytes());
+        f[VBRI_OFFSET + 14..VBRI_OFFSET + 18].copy_from_slice(&500u32.to_be_bytes());
+        let s = stream([f, frame()].concat());
+        assert!(s.vbr());
+        assert_eq!(s.vbri.as_ref().unwrap().delay, 576);
+        assert_eq!(s.frames(), Some(500));
+        assert_eq!(s.bytes, 200_000);
+        // 500 frames of 1152 samples at 44.1kHz is about 13 seconds.
+        assert_eq!(s.bitrate() / 1000, 122);
+    }
+}
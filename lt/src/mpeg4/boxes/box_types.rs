@@ -52,7 +52,7 @@ impl std::fmt::Debug for FourCC {
 #[derive(Debug, PartialEq, Eq)]
 pub enum ContainerType {
     Container,
-    Special(usize), // Sizes can only be u32 so we can't skip more than that.
+    Special(usize), // Bytes of data, after the header, before the contained boxes.
     NotContainer,
 }
 
@@ -220,6 +220,11 @@ define_boxes! {
     TKHD, b"tkhd",      ContainerType::NotContainer,   true,   "Track Header",                         "/movv/trak/tkhd";
     TRAK, b"trak",      ContainerType::Container,      false,  "Track Container",                      "/moov/trak";
     UDTA, b"udta",      ContainerType::Container,      false,  "User Data Container",                  "/moov/udta";
+    TREF, b"tref",      ContainerType::Container,      false,  "Track Reference Container",            "/moov/trak/tref";
+    CHAP, b"chap",      ContainerType::NotContainer,   false,  "Chapter Track Reference",              "/moov/trak/tref/chap";
+    CHPL, b"chpl",      ContainerType::NotContainer,   true,   "Nero Chapter List",                    "/moov/udta/chpl";
+    EDTS, b"edts",      ContainerType::Container,      false,  "Edit Container",                       "/moov/trak/edts";
+    ELST, b"elst",      ContainerType::NotContainer,   true,   "Edit List",                            "/moov/trak/edts/elst";
     UUID, b"UUID",      ContainerType::NotContainer,   false,  "UUID is the user special type",        "uuid";
     MDAT, b"mdat",      ContainerType::NotContainer,   false,  "Media Data Box",                       "/mdat";
     FREE, b"free",      ContainerType::NotContainer,   false,  "Free Space",                           "/free";
@@ -240,6 +245,7 @@ define_boxes! {
     SIGN, b"sign",      ContainerType::NotContainer,   false,  "Protection Scheme Information Box",    "/moov/track/mdia/minf/stbl/mp4a/pinf/sign";
     SINF, b"sinf",      ContainerType::Container,      false,  "Protection Scheme Information Box",    "/moov/track/mdia/minf/stbl/drms/sinf";
     STCO, b"stco",      ContainerType::NotContainer,   true,   "Chunk Offsets",                        "/moov/track/mdia/minf/stbl/stco";
+    CO64, b"co64",      ContainerType::NotContainer,   true,   "64-bit Chunk Offsets",                 "/moov/track/mdia/minf/stbl/co64";
     STSC, b"stsc",      ContainerType::NotContainer,   true,   "Sample to Chunk",                      "/moov/track/mdia/minf/stbl/stsc";
     STSD, b"stsd",      ContainerType::Special(4),     true,   "Sample Description",                   "/moov/track/mdia/minf/stbl/stsd";
     STTS, b"stts",      ContainerType::NotContainer,   true,   "Time to sample",                       "/movv/track/mdia/minf/stbl/stts";
@@ -1,5 +1,6 @@
 //! Reader functionality for Apple ilst generic metadata box and it's descendents.
-use crate::mpeg4::boxes::{MP4Box, FULL_BOX_HEADER_SIZE};
+use crate::mpeg4::boxes::MP4Box;
+use crate::mpeg4::error::Mpeg4Error;
 // use crate::mpeg4::util::dump_buffer;
 use bytes::buf::Buf;
 use std::fmt;
@@ -9,6 +10,8 @@ pub enum DataBoxContent<'a> {
     Byte(u32),
     Text(&'a [u8]),
     Data(&'a [u8]),
+    /// An image with its mime type.
+    Image(&'static str, &'a [u8]),
 }
 
 impl fmt::Debug for DataBoxContent<'_> {
@@ -16,6 +19,7 @@ impl fmt::Debug for DataBoxContent<'_> {
         match self {
             DataBoxContent::Byte(v) => write!(f, "Byte({:04x})", v),
             DataBoxContent::Text(v) => write!(f, "Text({:?})", String::from_utf8_lossy(v)),
+            DataBoxContent::Image(m, v) => write!(f, "Image({}, len = {})", m, v.len()),
             DataBoxContent::Data(v) => {
                 let l = v.len();
                 if l > 32 {
@@ -34,40 +38,46 @@ impl fmt::Debug for DataBoxContent<'_> {
     }
 }
 
-const IMPLICIT_FLAG: u32 = 0;
-const TEXT_FLAG: u32 = 1;
-const JPEG_FLAG: u32 = 13;
-const PNG_FLAG: u32 = 14;
+pub const IMPLICIT_FLAG: u32 = 0;
+pub const TEXT_FLAG: u32 = 1;
+pub const JPEG_FLAG: u32 = 13;
+pub const PNG_FLAG: u32 = 14;
 const BYTE_FLAG: u32 = 21;
 
+/// The string in the mean and name boxes of a freeform (----) item.
+pub fn read_string(bx: &mut MP4Box) -> Result<String, Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    Ok(String::from_utf8_lossy(bx.buf).to_string())
+}
+
 // TODO(jdr): Think about getting rid of the buf.get_XX() calls.
 // They modify the buffer point, which is probably not what we really
 // want.
-pub fn get_data_box<'a>(bx: &'a mut MP4Box) -> DataBoxContent<'a> {
-    // println!("box: {:?}", bx);
-    // dump_buffer(bx.buf);
-
+pub fn get_data_box<'a>(bx: &'a mut MP4Box) -> Result<DataBoxContent<'a>, Mpeg4Error> {
     // Read past the full box (size, type, flags/version)
-    bx.buf.advance(FULL_BOX_HEADER_SIZE);
+    bx.buf.advance(bx.header_size);
+    let flag = match &bx.version_flag {
+        Some(vf) => vf.flag,
+        None => return Err(Mpeg4Error::in_box(bx, "data box has no version and flags")),
+    };
 
     // data box has a predfeined 0
     // Though some seem to characterie this as lang.
+    bx.need(4, "the data box locale")?;
     bx.buf.get_u32(); //
-    if let Some(vf) = &bx.version_flag {
-        match vf.flag {
-            TEXT_FLAG => DataBoxContent::Text(&bx.buf),
-            IMPLICIT_FLAG | JPEG_FLAG | PNG_FLAG => DataBoxContent::Data(&bx.buf),
-            // This bit of shenanigens is really just from observation.
-            // It might be some other deciding.
-            BYTE_FLAG => DataBoxContent::Byte(if bx.buf.len() < 4 {
-                bx.buf.get_u8() as u32
-            } else {
-                bx.buf.get_u32()
-            }),
-            _ => DataBoxContent::Byte(0x0000), // The true cases here are errors.
-        }
-    } else {
-        // This branch of the if is an error, so maybe we should return one?
-        panic!("Read a data box that wasn't a BoxType::Full()\n{:?}", bx);
-    }
+    Ok(match flag {
+        TEXT_FLAG => DataBoxContent::Text(&bx.buf),
+        IMPLICIT_FLAG => DataBoxContent::Data(&bx.buf),
+        JPEG_FLAG => DataBoxContent::Image("image/jpeg", bx.buf),
+        PNG_FLAG => DataBoxContent::Image("image/png", bx.buf),
+        // This bit of shenanigens is really just from observation.
+        // It might be some other deciding.
+        BYTE_FLAG => DataBoxContent::Byte(if bx.buf.len() < 4 {
+            bx.need(1, "a byte value")?;
+            bx.buf.get_u8() as u32
+        } else {
+            bx.buf.get_u32()
+        }),
+        _ => DataBoxContent::Byte(0x0000), // The true cases here are errors.
+    })
 }
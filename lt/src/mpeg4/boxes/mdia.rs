@@ -1,6 +1,7 @@
 //! Reader functionality for Media Data and it's header.
 
-use crate::mpeg4::boxes::{MP4Box, FULL_BOX_HEADER_SIZE};
+use crate::mpeg4::boxes::MP4Box;
+use crate::mpeg4::error::Mpeg4Error;
 use bytes::buf::Buf;
 
 /// Media Header Box
@@ -20,23 +21,46 @@ pub fn get_mdhd<'a>(
     timescale: &'a mut u32,
     duration: &'a mut u64,
     language: &'a mut u16,
-) {
+) -> Result<(), Mpeg4Error> {
     // Move past the fullbox header.
-    bx.buf.advance(FULL_BOX_HEADER_SIZE);
-    if let Some(vf) = &bx.version_flag {
-        if vf.version == 1 {
-            *creation = bx.buf.get_u64();
-            *modification = bx.buf.get_u64();
-            *timescale = bx.buf.get_u32();
-            *duration = bx.buf.get_u64();
-        } else {
-            *creation = bx.buf.get_u32() as u64;
-            *modification = bx.buf.get_u32() as u64;
-            *timescale = bx.buf.get_u32();
-            *duration = bx.buf.get_u32() as u64;
-        }
-        *language = bx.buf.get_u16();
+    bx.buf.advance(bx.header_size);
+    let version = match &bx.version_flag {
+        Some(vf) => vf.version,
+        None => return Err(Mpeg4Error::in_box(bx, "mdhd has no version and flags")),
+    };
+    if version == 1 {
+        bx.need(30, "a version 1 media header")?;
+        *creation = bx.buf.get_u64();
+        *modification = bx.buf.get_u64();
+        *timescale = bx.buf.get_u32();
+        *duration = bx.buf.get_u64();
     } else {
-        panic!("mdhd didn't read a BoxType::Full so had no version flag.");
+        bx.need(18, "a media header")?;
+        *creation = bx.buf.get_u32() as u64;
+        *modification = bx.buf.get_u32() as u64;
+        *timescale = bx.buf.get_u32();
+        *duration = bx.buf.get_u32() as u64;
     }
+    *language = bx.buf.get_u16();
+    Ok(())
+}
+
+/// Handler Reference Box
+/// handler_type: four character code for the kind of media in the track,
+/// e.g. 'soun' for audio, 'vide' for video, 'text' for chapters.
+pub fn read_hdlr(bx: &mut MP4Box, handler_type: &mut u32) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(8, "the handler type")?;
+    let _pre_defined = bx.buf.get_u32();
+    *handler_type = bx.buf.get_u32();
+    Ok(())
+}
+
+/// The three lower case letters of an ISO 639-2/T code
+/// packed into an mdhd language, e.g. "eng".
+pub fn language_code(language: u16) -> String {
+    (0..3)
+        .rev()
+        .map(|i| (((language >> (i * 5)) & 0x1f) as u8 + 0x60) as char)
+        .collect()
 }
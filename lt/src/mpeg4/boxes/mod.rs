@@ -5,45 +5,185 @@ pub mod box_types;
 pub mod ilst;
 pub mod mdia;
 pub mod stbl;
+pub mod trak;
+pub mod udta;
 
-use box_types::{BoxType, ContainerType};
+use super::error::Mpeg4Error;
+use box_types::{BoxType, ContainerType, FourCC};
 use bytes::buf::Buf;
-// use std::error::Error;
+use std::error::Error;
 use std::fmt;
-// use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 
 /// Box header is [size] + [type (four character code)]
 pub const BOX_HEADER_SIZE: usize = 8;
 /// Full box [size] + [type] + [version/flags]
 pub const FULL_BOX_HEADER_SIZE: usize = BOX_HEADER_SIZE + 4;
+/// Box header with a 64 bit size is [size = 1] + [type] + [largesize]
+pub const LARGE_BOX_HEADER_SIZE: usize = BOX_HEADER_SIZE + 8;
 
 /// Holds the buffer and supports
 /// iteration over the MP4Boxes
 /// in the buffer.
+///
+/// Iteration stops after the first box that can't be read,
+/// since there's no way to know where the next one starts.
 // TODO(jdr): This probably wants a rethink.
 pub struct MP4Buffer<'a, 'b> {
     pub buf: &'b mut &'a [u8],
+    // Offset of the buffer in the file, and its length when we started,
+    // to work out box offsets.
+    start: u64,
+    len: usize,
+    // The end offset and four character code of each container we're in.
+    containers: Vec<(u64, String)>,
+}
+
+impl<'a, 'b> MP4Buffer<'a, 'b> {
+    pub fn new(buf: &'b mut &'a [u8]) -> MP4Buffer<'a, 'b> {
+        MP4Buffer::new_at(buf, 0)
+    }
+
+    /// For a buffer that was read from start bytes into the file.
+    pub fn new_at(buf: &'b mut &'a [u8], start: u64) -> MP4Buffer<'a, 'b> {
+        let len = buf.len();
+        MP4Buffer {
+            buf,
+            start,
+            len,
+            containers: Vec::new(),
+        }
+    }
+
+    // Path of the containers we're in.
+    fn path(&self) -> String {
+        self.containers
+            .iter()
+            .map(|(_, cc)| format!("/{}", cc))
+            .collect()
+    }
 }
 
 impl<'a> std::iter::Iterator for MP4Buffer<'a, '_> {
-    type Item = MP4Box<'a>;
+    type Item = Result<MP4Box<'a>, Mpeg4Error>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.buf.is_empty() {
             return None;
         }
-        // println!("Next: Buf len: {:#0x}", self.buf.len());
-        let b = read_box_header(self.buf);
-        // println!("Next end: Buff len = {:#0x}", self.buf.len());
-        Some(b)
+        let offset = self.start + (self.len - self.buf.len()) as u64;
+        while matches!(self.containers.last(), Some((end, _)) if *end <= offset) {
+            self.containers.pop();
+        }
+
+        let b = read_box_header(self.buf, offset).and_then(|b| match self.containers.last() {
+            Some((end, cc)) if offset + b.size > *end => Err(Mpeg4Error::in_box(
+                &b,
+                format!(
+                    "box size {} runs past the end of {} ({} bytes left)",
+                    b.size,
+                    cc,
+                    end - offset
+                ),
+            )),
+            _ => Ok(b),
+        });
+        match b {
+            Ok(b) => {
+                if b.box_type.is_container() {
+                    self.containers
+                        .push((offset + b.size, b.box_type.four_cc()));
+                }
+                Some(Ok(b))
+            }
+            Err(e) => {
+                *self.buf = &[];
+                Some(Err(e.within(&self.path())))
+            }
+        }
     }
 }
 
+/// Non-container boxes bigger than this are skipped rather than loaded.
+pub const MAX_LOADED_BOX_SIZE: u64 = 64 * 1024;
+
+/// Reads the boxes in a file, calling f with each of them in order.
+///
+/// Only the top level boxes we need are loaded: containers, like moov, and small boxes.
+/// The mdat box and other large boxes are seeked past, for these f gets a box
+/// whose buffer holds just the box header.
+pub fn read_boxes<R: Read + Seek>(
+    mut r: R,
+    mut f: impl FnMut(MP4Box) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let mut offset = 0;
+    let mut buf = Vec::new();
+    while offset < len {
+        r.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; LARGE_BOX_HEADER_SIZE];
+        let n = (LARGE_BOX_HEADER_SIZE as u64).min(len - offset) as usize;
+        r.read_exact(&mut header[0..n])?;
+
+        let (header_size, size, bt) = match read_box_size_type(&mut &header[0..n]) {
+            // A size of 0 is the rest of the file, not the rest of the header.
+            Some((hs, _, bt)) if header[0..4] == [0; 4] => (hs, len - offset, bt),
+            Some(h) => h,
+            None => {
+                return Err(Mpeg4Error::new(
+                    "",
+                    offset,
+                    format!("{} bytes left, too short for a box header", n),
+                )
+                .into())
+            }
+        };
+        let box_type = BoxType::from(bt);
+        if size > len - offset || size < header_size as u64 {
+            return Err(Mpeg4Error::new(
+                format!("/{}", FourCC(bt)),
+                offset,
+                format!(
+                    "box size {} doesn't fit in the file ({} bytes left)",
+                    size,
+                    len - offset
+                ),
+            )
+            .into());
+        }
+
+        if box_type == box_types::MDAT || (!box_type.is_container() && size > MAX_LOADED_BOX_SIZE) {
+            f(MP4Box {
+                offset,
+                size,
+                header_size,
+                box_type,
+                buf: &header[0..header_size],
+                version_flag: None,
+            })?;
+        } else {
+            buf.resize(size as usize, 0);
+            r.seek(SeekFrom::Start(offset))?;
+            r.read_exact(&mut buf)?;
+            for b in MP4Buffer::new_at(&mut buf.as_slice(), offset) {
+                f(b?)?;
+            }
+        }
+        offset += size;
+    }
+    Ok(())
+}
+
 /// Holds header information from the box
 /// and the buffer for the data assocaited
 /// with the box.
 #[derive(PartialEq, Eq)]
 pub struct MP4Box<'a> {
-    pub size: u32,
+    /// Offset of the box from the start of the buffer it was read from.
+    pub offset: u64,
+    pub size: u64,
+    /// Bytes in front of the box contents: size and type, the 64 bit size
+    /// if there is one, and the version/flags of a full box.
+    pub header_size: usize,
     pub box_type: BoxType,
     pub buf: &'a [u8],
     pub version_flag: Option<VersionFlag>,
@@ -54,6 +194,19 @@ impl<'a> MP4Box<'a> {
     pub fn read(&mut self, rf: &mut impl FnMut(&mut MP4Box<'a>)) {
         rf(self);
     }
+
+    /// Checks that there are at least n bytes left in the buffer
+    /// to read what from.
+    pub fn need(&self, n: usize, what: &str) -> Result<(), Mpeg4Error> {
+        if self.buf.len() < n {
+            Err(Mpeg4Error::in_box(
+                self,
+                format!("{} bytes left, too short for {}", self.buf.len(), what),
+            ))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 impl fmt::Debug for MP4Box<'_> {
@@ -96,30 +249,58 @@ pub struct VersionFlag {
 /// determine the details in the BoxSpec (Box/FullBox, Container/NotContainer.)
 /// In the caes that the type is not known an Unknown type is provided, which can at
 /// least be printed out with size and code.
-pub fn read_box_header<'i>(buf: &mut &'i [u8]) -> MP4Box<'i> {
-    // Read box header: [sssstttt]
+pub fn read_box_header<'i>(buf: &mut &'i [u8], offset: u64) -> Result<MP4Box<'i>, Mpeg4Error> {
+    // Read box header: [sssstttt] or [sssstttt llllllll] (s = 1)
     // s = 1 byte of size; 4 total.
     // t = 1 byte of box type; 4 total.
-    // println!("Bufferhead {:x?}", &buf[0..8]);
+    // l = 1 byte of the 64 bit largesize; 8 total.
     let rest = &buf[0..buf.len()];
-    let mut read: usize = 0;
-    let (r, s, bt) = read_box_size_type(buf);
-    read += r;
-
-    // println!(
-    //     "Creating rest. Buf.len() {}, Box size: {}, Bytes Read: {}",
-    //     buf.len(),
-    //     s,
-    //     read
-    // );
-    let rest = &rest[0..(s as usize)];
+    let (mut read, s, bt) = match read_box_size_type(buf) {
+        Some(h) => h,
+        None => {
+            let path = if rest.len() >= BOX_HEADER_SIZE {
+                format!(
+                    "/{}",
+                    FourCC(u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]))
+                )
+            } else {
+                String::new()
+            };
+            return Err(Mpeg4Error::new(
+                path,
+                offset,
+                format!("{} bytes left, too short for a box header", rest.len()),
+            ));
+        }
+    };
 
-    // println!("Read box header: {} [{}/0x{:0x?}]", FourCC(bt), s, s);
     let box_type = BoxType::from(bt);
     let box_spec = box_type.spec();
+    let err = |reason: String| Err(Mpeg4Error::new(format!("/{}", FourCC(bt)), offset, reason));
 
-    // println!("Read Box Type: {:?}", box_type);
-    // println!("\tSpec: {:?}", box_spec);
+    // Don't let a bad size take us out of the buffer, or keep us in place.
+    if s > rest.len() as u64 {
+        return err(format!(
+            "box size {} runs past the end of the file ({} bytes left)",
+            s,
+            rest.len()
+        ));
+    }
+    let mut contents = 0;
+    if box_spec.full {
+        contents += 4;
+    }
+    if let ContainerType::Special(skip) = box_spec.container {
+        contents += skip;
+    }
+    if s < (read + contents) as u64 {
+        return err(format!(
+            "box size {} is too small for its {} byte header",
+            s,
+            read + contents
+        ));
+    }
+    let rest = &rest[0..(s as usize)];
 
     // Read the VF
     let vf = if box_spec.full {
@@ -128,12 +309,11 @@ pub fn read_box_header<'i>(buf: &mut &'i [u8]) -> MP4Box<'i> {
     } else {
         None
     };
-
-    // println!("\tRest len: {}", rest.len());
+    let header_size = read;
 
     if let ContainerType::Special(skip) = box_spec.container {
         buf.advance(skip);
-        read += skip as usize;
+        read += skip;
     }
 
     // Move this buffer pointer along to the end of the box.
@@ -141,17 +321,16 @@ pub fn read_box_header<'i>(buf: &mut &'i [u8]) -> MP4Box<'i> {
     if box_spec.container == ContainerType::NotContainer {
         buf.advance(s as usize - read);
     }
-    // } else {
-    //     buf.advance(s as usize - read);
-    // }
 
     #[allow(clippy::redundant_field_names)]
-    MP4Box {
+    Ok(MP4Box {
+        offset: offset,
         size: s,
+        header_size: header_size,
         buf: rest,
         box_type: box_type,
         version_flag: vf,
-    }
+    })
 }
 
 fn get_version_flags(buf: &mut &[u8]) -> VersionFlag {
@@ -160,7 +339,7 @@ fn get_version_flags(buf: &mut &[u8]) -> VersionFlag {
         version: 0,
     };
 
-    vf.version = (vf.flag >> 28) as u8;
+    vf.version = (vf.flag >> 24) as u8;
     vf.flag &= 0x00_FF_FF_FF;
 
     vf
@@ -168,9 +347,23 @@ fn get_version_flags(buf: &mut &[u8]) -> VersionFlag {
 
 /// Read the MPEGBox Size and the type from a buffer.
 ///
-/// Returns the number of bytes read, should be 8, the size, the box type).
-pub fn read_box_size_type(buf: &mut &[u8]) -> (usize, u32, u32) {
-    (8, buf.get_u32(), buf.get_u32())
+/// Returns the number of bytes read (8, or 16 with a 64 bit size), the size, the box type),
+/// or None if the buffer is too short to hold the header.
+/// A size of 1 means the 64 bit size follows the type.
+/// A size of 0 means the box extends to the end of the buffer, so the size returned
+/// is what's left of the buffer.
+pub fn read_box_size_type(buf: &mut &[u8]) -> Option<(usize, u64, u32)> {
+    if buf.len() < BOX_HEADER_SIZE {
+        return None;
+    }
+    let s = buf.get_u32();
+    let bt = buf.get_u32();
+    match s {
+        1 if buf.len() >= 8 => Some((LARGE_BOX_HEADER_SIZE, buf.get_u64(), bt)),
+        1 => None,
+        0 => Some((BOX_HEADER_SIZE, (BOX_HEADER_SIZE + buf.len()) as u64, bt)),
+        s => Some((BOX_HEADER_SIZE, s as u64, bt)),
+    }
 }
 
 /// FTYP - file type box
@@ -229,8 +422,14 @@ pub fn get_ftyp_box_values<'a>(
     version: &mut u8,
     flags: &mut u32,
     compat_brands: &mut Vec<&'a [u8]>,
-) {
-    // let mut read = 0;
+) -> Result<(), Mpeg4Error> {
+    if buf.len() < 8 {
+        return Err(Mpeg4Error::new(
+            "/ftyp",
+            0,
+            format!("{} bytes left, too short for the brand", buf.len()),
+        ));
+    }
 
     *brand = &buf[0..4];
     buf.advance(4);
@@ -246,6 +445,7 @@ pub fn get_ftyp_box_values<'a>(
         buf.advance(4);
         // read += 4;
     }
+    Ok(())
 }
 
 impl fmt::Debug for FtypBox<'_> {
@@ -264,3 +464,95 @@ impl fmt::Debug for FtypBox<'_> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use box_types::{FREE, MDAT, MOOV};
+    use std::fs::File;
+
+    #[test]
+    fn test_read_box_sizes() {
+        let mut v = Vec::new();
+        // free with a 64 bit size.
+        v.extend_from_slice(&1u32.to_be_bytes());
+        v.extend_from_slice(b"free");
+        v.extend_from_slice(&20u64.to_be_bytes());
+        v.extend_from_slice(&[0; 4]);
+        // moov with a 32 bit size.
+        v.extend_from_slice(&8u32.to_be_bytes());
+        v.extend_from_slice(b"moov");
+        // mdat to the end of the file.
+        v.extend_from_slice(&0u32.to_be_bytes());
+        v.extend_from_slice(b"mdat");
+        v.extend_from_slice(&[0; 10]);
+
+        let mut b = v.as_slice();
+        let boxes = MP4Buffer::new(&mut b)
+            .collect::<Result<Vec<MP4Box>, _>>()
+            .unwrap();
+        assert_eq!(boxes.len(), 3);
+
+        assert_eq!(boxes[0].box_type, FREE);
+        assert_eq!(boxes[0].size, 20);
+        assert_eq!(boxes[0].header_size, LARGE_BOX_HEADER_SIZE);
+
+        assert_eq!(boxes[1].box_type, MOOV);
+        assert_eq!(boxes[1].size, 8);
+
+        assert_eq!(boxes[2].box_type, MDAT);
+        assert_eq!(boxes[2].size, 18);
+        assert_eq!(boxes[2].buf.len(), 18);
+    }
+
+    #[test]
+    fn test_read_box_errors() {
+        // A trak that runs past the end of its moov.
+        let mut v = Vec::new();
+        v.extend_from_slice(&24u32.to_be_bytes());
+        v.extend_from_slice(b"moov");
+        v.extend_from_slice(&100u32.to_be_bytes());
+        v.extend_from_slice(b"trak");
+        v.extend_from_slice(&[0; 8]);
+
+        let mut b = v.as_slice();
+        let mut boxes = MP4Buffer::new(&mut b);
+        assert_eq!(boxes.next().unwrap().unwrap().box_type, MOOV);
+        let e = boxes.next().unwrap().unwrap_err();
+        assert_eq!(e.path, "/moov/trak");
+        assert_eq!(e.offset, 8);
+        assert!(boxes.next().is_none());
+
+        // A file cut off before the end of the moov.
+        let mut b = &v[0..20];
+        let e = MP4Buffer::new(&mut b)
+            .collect::<Result<Vec<MP4Box>, _>>()
+            .unwrap_err();
+        assert_eq!(e.path, "/moov");
+        assert_eq!(e.offset, 0);
+    }
+
+    #[test]
+    fn test_read_boxes() {
+        let f = "test_audio/16 The End.m4a";
+        let v = std::fs::read(f).unwrap();
+        let mut b = v.as_slice();
+        let want: Vec<(u64, u64, String)> = MP4Buffer::new(&mut b)
+            .map(|b| b.unwrap())
+            .map(|b| (b.offset, b.size, b.box_type.four_cc()))
+            .collect();
+
+        let mut got = Vec::new();
+        read_boxes(File::open(f).unwrap(), |b| {
+            if b.box_type == MDAT {
+                // Skipped, so we only have the header.
+                assert_eq!(b.buf.len(), BOX_HEADER_SIZE);
+            }
+            got.push((b.offset, b.size, b.box_type.four_cc()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(got, want);
+        assert!(got.iter().any(|(_, _, cc)| cc == "mdat"));
+    }
+}
@@ -1,5 +1,6 @@
 //! Reader functionality for sample table and it's descendents.
-use crate::mpeg4::boxes::{MP4Box, BOX_HEADER_SIZE, FULL_BOX_HEADER_SIZE};
+use crate::mpeg4::boxes::{box_types, MP4Box};
+use crate::mpeg4::error::Mpeg4Error;
 use crate::mpeg4::formats::{AudioObjectTypes, ChannelConfig};
 // use crate::mpeg4::util::dump_buffer;
 use bytes::buf::Buf;
@@ -46,8 +47,9 @@ pub fn read_mp4a<'a>(
     channels: &'a mut u16,
     sample_size: &'a mut u16,
     sample_rate: &'a mut u32,
-) {
-    bx.buf.advance(BOX_HEADER_SIZE);
+) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(28, "an audio sample entry")?;
 
     // Next there are 6 bytes reserved as 0.
     bx.buf.advance(6);
@@ -138,6 +140,7 @@ pub fn read_mp4a<'a>(
 
     // This only implements the version 0 case at this point.
     // More work to to be done to finish this up.
+    Ok(())
 }
 
 ///
@@ -186,8 +189,8 @@ pub fn read_esds<'a>(
     // channels: &'a mut u16,
     // sample_size: &'a mut u16,
     // sample_rate: &'a mut u32,
-) {
-    bx.buf.advance(FULL_BOX_HEADER_SIZE);
+) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
 
     // println!("read_esds");
 
@@ -228,25 +231,27 @@ pub fn read_esds<'a>(
         const TAG6: u8 = 6;
 
         match tag {
-            ES_DESCIPTOR => read_es_descriptor(bx),
+            ES_DESCIPTOR => read_es_descriptor(bx)?,
             DECODER_CONFIG_DESCRIPTOR => {
                 // TODO(jdr): consider just advancing length here.
-                read_decoder_config_descriptor(bx, decoder, max_bitrate, avg_bitrate)
+                read_decoder_config_descriptor(bx, decoder, max_bitrate, avg_bitrate)?
             }
             DECODER_SPECIFIC_INFO => {
-                read_audio_specific_config(bx, length, codec, sample_frequency, channel_config)
+                read_audio_specific_config(bx, length, codec, sample_frequency, channel_config)?
             }
-            TAG6 => read_tag6(bx),
+            TAG6 => read_tag6(bx)?,
             _ => println!("Decoding: BAD TAG!"),
         }
         // println!("Bottom of loop.")
     }
+    Ok(())
 }
 
 /// ES Ddescriptor
 ///
-fn read_es_descriptor(bx: &mut MP4Box) {
+fn read_es_descriptor(bx: &mut MP4Box) -> Result<(), Mpeg4Error> {
     // println!("Decoding: Object Descriptor");
+    bx.need(3, "an ES descriptor")?;
 
     // ES ID bit(16)
     let _id = bx.buf.get_u16();
@@ -259,23 +264,28 @@ fn read_es_descriptor(bx: &mut MP4Box) {
     // Stream dependency flag.
     if flags & 0x80 > 0 {
         // let _depends_on_es_id = bx.buf.get_u16();
+        bx.need(2, "the ES dependency")?;
         bx.buf.advance(2);
     }
 
     // URL flag
     if flags & 0x40 > 0 {
         // length
+        bx.need(1, "the ES URL length")?;
         let length = bx.buf.get_u8() as usize;
         // URL String is stored in the length bits following
+        bx.need(length, "the ES URL")?;
         bx.buf.advance(length);
     }
 
     if flags & 0x20 > 0 {
         // let ocr_es_id = bx.buf.get_u16();
+        bx.need(2, "the ES OCR stream")?;
         bx.buf.advance(2);
     }
     // println!("ID: {}", id);
     // println!("Flags: {}", flags);
+    Ok(())
 }
 
 /// Get the decoding configuration
@@ -285,8 +295,9 @@ fn read_decoder_config_descriptor(
     decoder: &mut u8,
     max_bitrate: &mut u32,
     avg_bitrate: &mut u32,
-) {
+) -> Result<(), Mpeg4Error> {
     // println!("Decoding elemenary stream descriptor");
+    bx.need(13, "a decoder config descriptor")?;
     *decoder = bx.buf.get_u8();
     // println!("Type/Profile: {}[{:#04x}]", profile, profile);
 
@@ -314,6 +325,7 @@ fn read_decoder_config_descriptor(
     // println!("Decoder = {:?}", decoder);
     // println!("maxBitrate: {}", max_bitrate);
     // println!("avg_bitrate: {}", avg_bitrate);
+    Ok(())
 }
 
 /// Sampling Frequencies
@@ -366,8 +378,11 @@ fn read_audio_specific_config(
     object_type: &mut AudioObjectTypes,
     frequency: &mut u32,
     channel_config: &mut ChannelConfig,
-) {
+) -> Result<(), Mpeg4Error> {
     // println!("Decoding AUDIO_SPECIFIC_INFO");
+    if matches!(length, 2 | 3 | 5 | 6) {
+        bx.need(length as usize, "the audio specific config")?;
+    }
 
     // let object_type: u8;
     // let frequency: u32;
@@ -451,17 +466,102 @@ fn read_audio_specific_config(
     // println!("Object_type: {}", object_type);
     // println!("frequency: {}", frequency);
     // println!("Channel config: {}", channel_config);
+    Ok(())
 }
 
 /// Don't know what this is for.
-fn read_tag6(bx: &mut MP4Box) {
+fn read_tag6(bx: &mut MP4Box) -> Result<(), Mpeg4Error> {
     // println!("Reading tag id: 6");
+    bx.need(1, "the tag 6 descriptor")?;
     let _data = bx.buf.get_u8();
     // println!("Data is; {}", data);
+    Ok(())
 }
 
 /// Reads the scheme FourCC from the schm box.
-pub fn read_schm(bx: &mut MP4Box, scheme: &mut u32) {
-    bx.buf.advance(FULL_BOX_HEADER_SIZE);
+pub fn read_schm(bx: &mut MP4Box, scheme: &mut u32) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(4, "the scheme type")?;
     *scheme = bx.buf.get_u32();
+    Ok(())
+}
+
+/// Reads the Time to Sample Box [stts] into runs of
+/// (sample count, sample duration) with the durations in media timescale units.
+pub fn read_stts(bx: &mut MP4Box, runs: &mut Vec<(u32, u32)>) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(4, "the entry count")?;
+    let count = bx.buf.get_u32() as usize;
+    bx.need(count.saturating_mul(8), "the time to sample entries")?;
+    runs.clear();
+    for _ in 0..count {
+        runs.push((bx.buf.get_u32(), bx.buf.get_u32()));
+    }
+    Ok(())
+}
+
+/// Reads the Sample Size Box [stsz].
+///
+/// sample_size: the size of every sample if they're all the same, otherwise 0.
+///
+/// sample_count: the number of samples in the track.
+///
+/// sizes: the size of each sample, only read when sample_size is 0.
+pub fn read_stsz(
+    bx: &mut MP4Box,
+    sample_size: &mut u32,
+    sample_count: &mut u32,
+    sizes: &mut Vec<u32>,
+) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(8, "the sample size and count")?;
+    *sample_size = bx.buf.get_u32();
+    *sample_count = bx.buf.get_u32();
+    sizes.clear();
+    if *sample_size == 0 {
+        let count = *sample_count as usize;
+        bx.need(count.saturating_mul(4), "the sample sizes")?;
+        for _ in 0..count {
+            sizes.push(bx.buf.get_u32());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the Sample to Chunk Box [stsc] into runs of
+/// (first chunk, samples per chunk). Chunks are numbered from 1
+/// and a run lasts until the first chunk of the next one.
+pub fn read_stsc(bx: &mut MP4Box, runs: &mut Vec<(u32, u32)>) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    bx.need(4, "the entry count")?;
+    let count = bx.buf.get_u32() as usize;
+    bx.need(count.saturating_mul(12), "the sample to chunk entries")?;
+    runs.clear();
+    for _ in 0..count {
+        let first_chunk = bx.buf.get_u32();
+        let samples_per_chunk = bx.buf.get_u32();
+        let _description_index = bx.buf.get_u32();
+        runs.push((first_chunk, samples_per_chunk));
+    }
+    Ok(())
+}
+
+/// Reads the Chunk Offset Box, either 32 bit [stco] or 64 bit [co64],
+/// into the file offset of each chunk.
+pub fn read_stco(bx: &mut MP4Box, offsets: &mut Vec<u64>) -> Result<(), Mpeg4Error> {
+    let large = bx.box_type == box_types::CO64;
+    bx.buf.advance(bx.header_size);
+    bx.need(4, "the entry count")?;
+    let count = bx.buf.get_u32() as usize;
+    let entry_size = if large { 8 } else { 4 };
+    bx.need(count.saturating_mul(entry_size), "the chunk offsets")?;
+    offsets.clear();
+    for _ in 0..count {
+        offsets.push(if large {
+            bx.buf.get_u64()
+        } else {
+            bx.buf.get_u32() as u64
+        });
+    }
+    Ok(())
 }
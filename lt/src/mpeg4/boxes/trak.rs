@@ -0,0 +1,107 @@
+//! Reader functionality for the movie and track headers and the boxes in a trak.
+use crate::mpeg4::boxes::MP4Box;
+use crate::mpeg4::error::Mpeg4Error;
+use bytes::buf::Buf;
+
+/// Track is enabled, disabled tracks are treated as if they weren't there.
+pub const TRACK_ENABLED: u32 = 0x01;
+/// Track is used in the presentation.
+pub const TRACK_IN_MOVIE: u32 = 0x02;
+/// Track is used when previewing the presentation.
+pub const TRACK_IN_PREVIEW: u32 = 0x04;
+
+/// Track Header Box
+/// id: identifies the track within the file, never 0.
+/// flags: TRACK_ENABLED, TRACK_IN_MOVIE and TRACK_IN_PREVIEW
+/// duration: in units of the movie timescale (the mvhd's, not the mdhd's).
+pub fn read_tkhd(
+    bx: &mut MP4Box,
+    id: &mut u32,
+    flags: &mut u32,
+    duration: &mut u64,
+) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    let (version, flag) = match &bx.version_flag {
+        Some(vf) => (vf.version, vf.flag),
+        None => return Err(Mpeg4Error::in_box(bx, "tkhd has no version and flags")),
+    };
+    *flags = flag;
+    if version == 1 {
+        bx.need(32, "a version 1 track header")?;
+        let _creation = bx.buf.get_u64();
+        let _modification = bx.buf.get_u64();
+        *id = bx.buf.get_u32();
+        let _reserved = bx.buf.get_u32();
+        *duration = bx.buf.get_u64();
+    } else {
+        bx.need(20, "a track header")?;
+        let _creation = bx.buf.get_u32();
+        let _modification = bx.buf.get_u32();
+        *id = bx.buf.get_u32();
+        let _reserved = bx.buf.get_u32();
+        *duration = bx.buf.get_u32() as u64;
+    }
+    Ok(())
+}
+
+/// Chapter Track Reference Box [chap], in the trak's tref.
+/// ids: the track ids of the text tracks holding the chapters for this track.
+pub fn read_chap(bx: &mut MP4Box, ids: &mut Vec<u32>) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    let chunks = bx.buf.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return Err(Mpeg4Error::in_box(
+            bx,
+            format!("{} bytes isn't a whole number of track ids", bx.buf.len()),
+        ));
+    }
+    *ids = chunks
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    Ok(())
+}
+
+/// Movie Header Box
+/// timescale: units per second of the movie, used by the tkhd and elst durations.
+pub fn read_mvhd(bx: &mut MP4Box, timescale: &mut u32) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    let version = match &bx.version_flag {
+        Some(vf) => vf.version,
+        None => return Err(Mpeg4Error::in_box(bx, "mvhd has no version and flags")),
+    };
+    if version == 1 {
+        bx.need(20, "a version 1 movie header")?;
+        let _creation = bx.buf.get_u64();
+        let _modification = bx.buf.get_u64();
+    } else {
+        bx.need(12, "a movie header")?;
+        let _creation = bx.buf.get_u32();
+        let _modification = bx.buf.get_u32();
+    }
+    *timescale = bx.buf.get_u32();
+    Ok(())
+}
+
+/// Edit List Box [elst], in the trak's edts.
+/// edits: (segment duration in the movie timescale, media time in the trak's timescale)
+/// for each edit. A media time of -1 is an empty edit, a gap before the media starts.
+pub fn read_elst(bx: &mut MP4Box, edits: &mut Vec<(u64, i64)>) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    let version = match &bx.version_flag {
+        Some(vf) => vf.version,
+        None => return Err(Mpeg4Error::in_box(bx, "elst has no version and flags")),
+    };
+    bx.need(4, "an edit count")?;
+    let count = bx.buf.get_u32();
+    let entry_size = if version == 1 { 20 } else { 12 };
+    bx.need(count as usize * entry_size, "the edits")?;
+    for _ in 0..count {
+        if version == 1 {
+            edits.push((bx.buf.get_u64(), bx.buf.get_i64()));
+        } else {
+            edits.push((bx.buf.get_u32() as u64, bx.buf.get_i32() as i64));
+        }
+        let _rate = bx.buf.get_u32();
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! Reader functionality for boxes found in the user data box.
+use crate::mpeg4::boxes::MP4Box;
+use crate::mpeg4::error::Mpeg4Error;
+use bytes::buf::Buf;
+
+/// Nero Chapter List Box [chpl]
+/// chapters: (start, title) for each chapter, with start in 100ns units.
+pub fn read_chpl(bx: &mut MP4Box, chapters: &mut Vec<(u64, String)>) -> Result<(), Mpeg4Error> {
+    bx.buf.advance(bx.header_size);
+    if matches!(&bx.version_flag, Some(vf) if vf.version == 1) {
+        bx.need(4, "the reserved bytes")?;
+        bx.buf.advance(4);
+    }
+    bx.need(1, "the chapter count")?;
+    let count = bx.buf.get_u8();
+    chapters.clear();
+    for _ in 0..count {
+        bx.need(9, "a chapter start and title length")?;
+        let start = bx.buf.get_u64();
+        let len = bx.buf.get_u8() as usize;
+        bx.need(len, "a chapter title")?;
+        let title = String::from_utf8_lossy(&bx.buf[0..len]).to_string();
+        bx.buf.advance(len);
+        chapters.push((start, title));
+    }
+    Ok(())
+}
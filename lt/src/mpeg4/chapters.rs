@@ -0,0 +1,243 @@
+//! Chapter markers in MPEG4 (m4a/m4b) files.
+//!
+//! There are two ways these get written. Nero's chpl box in the moov/udta
+//! is a list of start times and titles. QuickTime's chapter tracks are text tracks,
+//! named by the audio track's tref/chap, with a title in each sample and the timing
+//! of the samples, and so the chapters, in the text track's stts.
+use super::samples::SampleIndex;
+use crate::track::Chapter;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Units per second of the chpl start times.
+pub const CHPL_TIMESCALE: u64 = 10_000_000;
+
+// Byte order mark at the start of UTF-16 titles.
+const UTF16_BOM: [u8; 2] = [0xFE, 0xFF];
+
+/// Chapters from the (start, title) pairs of a chpl box.
+/// Each chapter lasts until the next one starts, the last one until end.
+pub fn from_chpl(entries: &[(u64, String)], end: Duration) -> Vec<Chapter> {
+    let starts: Vec<Duration> = entries
+        .iter()
+        .map(|(s, _)| {
+            Duration::from_secs(s / CHPL_TIMESCALE)
+                + Duration::from_nanos(s % CHPL_TIMESCALE * (1_000_000_000 / CHPL_TIMESCALE))
+        })
+        .collect();
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, (_, title))| {
+            let next = starts.get(i + 1).copied().unwrap_or(end);
+            Chapter {
+                start: starts[i],
+                duration: next.checked_sub(starts[i]).unwrap_or_default(),
+                title: title.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Chapters from a chapter text track, reading the titles from its samples.
+pub fn from_text_track(
+    mut r: impl Read + Seek,
+    si: &SampleIndex,
+) -> Result<Vec<Chapter>, Box<dyn Error>> {
+    let mut chapters = Vec::new();
+    let mut start = 0u64;
+    let mut buf = Vec::new();
+    let len = r.seek(SeekFrom::End(0))?;
+    for (n, d) in si.sample_durations().enumerate() {
+        let n = n as u32;
+        let offset = match si.sample_offset(n) {
+            Some(o) => o,
+            None => break,
+        };
+        // Check the size before making room for it, the file could claim anything.
+        let size = si.size(n) as u64;
+        if offset.checked_add(size).filter(|&e| e <= len).is_none() {
+            return Err(format!(
+                "Chapter sample {} of {} bytes at {} runs past the end of the file.",
+                n, size, offset
+            )
+            .into());
+        }
+        buf.resize(size as usize, 0);
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut buf)?;
+        chapters.push(Chapter {
+            start: ticks(start, si.timescale),
+            duration: ticks(d as u64, si.timescale),
+            title: sample_title(&buf),
+        });
+        start += d as u64;
+    }
+    Ok(chapters)
+}
+
+fn ticks(t: u64, timescale: u32) -> Duration {
+    if timescale == 0 {
+        return Duration::default();
+    }
+    Duration::from_nanos((t as u128 * 1_000_000_000 / timescale as u128) as u64)
+}
+
+// A text sample is a 16 bit length then the text, which is UTF-8
+// or UTF-16 with a byte order mark. Anything after the text is styling.
+fn sample_title(b: &[u8]) -> String {
+    if b.len() < 2 {
+        return String::new();
+    }
+    let len = u16::from_be_bytes([b[0], b[1]]) as usize;
+    let text = &b[2..(2 + len).min(b.len())];
+    if text.starts_with(&UTF16_BOM) {
+        let units: Vec<u16> = text[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(text).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{new_track, read_track};
+    use super::*;
+    use std::io::Cursor;
+
+    fn bx(cc: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut v = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        v.extend_from_slice(cc);
+        v.extend_from_slice(body);
+        v
+    }
+
+    fn full(cc: &[u8], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+        let mut b = (flags | (version as u32) << 24).to_be_bytes().to_vec();
+        b.extend_from_slice(body);
+        bx(cc, &b)
+    }
+
+    fn u32s(vs: &[u32]) -> Vec<u8> {
+        vs.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect()
+    }
+
+    // A trak with one chunk of samples, each sample lasting the matching duration.
+    fn trak(id: u32, flags: u32, handler: &[u8], tref: &[u8], durations: &[u32]) -> Vec<u8> {
+        trak_at(id, flags, handler, tref, durations, &[], 0)
+    }
+
+    fn trak_at(
+        id: u32,
+        flags: u32,
+        handler: &[u8],
+        tref: &[u8],
+        durations: &[u32],
+        sizes: &[u32],
+        offset: u32,
+    ) -> Vec<u8> {
+        let tkhd = full(b"tkhd", 0, flags, &u32s(&[0, 0, id, 0, 0]));
+        let mut mdhd_body = u32s(&[0, 0, 1000, durations.iter().sum()]);
+        mdhd_body.extend_from_slice(&0x15c7u16.to_be_bytes()); // "eng"
+        mdhd_body.extend_from_slice(&[0, 0]);
+        let mdhd = full(b"mdhd", 0, 0, &mdhd_body);
+        let mut hdlr_body = u32s(&[0]);
+        hdlr_body.extend_from_slice(handler);
+        hdlr_body.extend_from_slice(&[0; 12]);
+        let hdlr = full(b"hdlr", 0, 0, &hdlr_body);
+
+        let mut stts = vec![durations.len() as u32];
+        for d in durations {
+            stts.extend_from_slice(&[1, *d]);
+        }
+        let mut stsz = vec![0, sizes.len() as u32];
+        stsz.extend_from_slice(sizes);
+        let stbl = [
+            full(b"stts", 0, 0, &u32s(&stts)),
+            full(b"stsz", 0, 0, &u32s(&stsz)),
+            full(b"stsc", 0, 0, &u32s(&[1, 1, sizes.len() as u32, 1])),
+            full(b"stco", 0, 0, &u32s(&[1, offset])),
+        ]
+        .concat();
+        let minf = bx(b"minf", &bx(b"stbl", &stbl));
+        let mdia = bx(b"mdia", &[mdhd, hdlr, minf].concat());
+        bx(b"trak", &[tkhd, tref.to_vec(), mdia].concat())
+    }
+
+    fn chapters(file: Vec<u8>) -> Vec<Chapter> {
+        let mut tk = new_track();
+        read_track(Cursor::new(file), &mut tk).unwrap();
+        tk.chapters
+    }
+
+    #[test]
+    fn test_chapter_track() {
+        let titles: Vec<Vec<u8>> = vec![
+            [&[0, 5][..], b"Intro"].concat(),
+            [&[0, 8][..], &UTF16_BOM[..], &[0, b'O', 0, b'n', 0, b'e']].concat(),
+        ];
+        let ftyp = bx(b"ftyp", b"M4A \0\0\0\0M4A ");
+        let tref = bx(b"tref", &bx(b"chap", &u32s(&[2])));
+        let audio = trak(1, 3, b"soun", &tref, &[60_000, 90_000]);
+
+        // The text samples go in the mdat after the moov, so build it twice
+        // to find out where that is.
+        let sizes: Vec<u32> = titles.iter().map(|t| t.len() as u32).collect();
+        let moov = |offset| {
+            let text = trak_at(2, 0, b"text", &[], &[1500, 2500], &sizes, offset);
+            bx(b"moov", &[audio.clone(), text].concat())
+        };
+        let offset = (ftyp.len() + moov(0).len() + 8) as u32;
+        let file = [ftyp.clone(), moov(offset), bx(b"mdat", &titles.concat())].concat();
+
+        let chs = chapters(file);
+        assert_eq!(
+            chs,
+            vec![
+                Chapter {
+                    start: Duration::from_secs(0),
+                    duration: Duration::from_millis(1500),
+                    title: "Intro".to_string()
+                },
+                Chapter {
+                    start: Duration::from_millis(1500),
+                    duration: Duration::from_millis(2500),
+                    title: "One".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chpl() {
+        let mut chpl = vec![0, 0, 0, 0, 2];
+        for (start, title) in &[(0u64, "Intro"), (30 * CHPL_TIMESCALE, "One")] {
+            chpl.extend_from_slice(&start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let udta = bx(b"udta", &full(b"chpl", 1, 0, &chpl));
+        let audio = trak(1, 1, b"soun", &[], &[45_000]);
+        let file = [
+            bx(b"ftyp", b"M4A \0\0\0\0M4A "),
+            bx(b"moov", &[audio, udta].concat()),
+        ]
+        .concat();
+
+        let chs = chapters(file);
+        assert_eq!(chs.len(), 2);
+        assert_eq!(chs[0].title, "Intro");
+        assert_eq!(chs[0].duration, Duration::from_secs(30));
+        assert_eq!(chs[1].start, Duration::from_secs(30));
+        assert_eq!(chs[1].duration, Duration::from_secs(15));
+
+        // A start too big for nanoseconds.
+        let chs = from_chpl(&[(u64::MAX, "Late".to_string())], Duration::from_secs(1));
+        assert_eq!(chs[0].start.as_secs(), u64::MAX / CHPL_TIMESCALE);
+        assert_eq!(chs[0].duration, Duration::default());
+    }
+}
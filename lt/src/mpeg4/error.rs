@@ -0,0 +1,49 @@
+//! Errors found while parsing MPEG4 boxes.
+use super::boxes::MP4Box;
+use std::error::Error;
+use std::fmt;
+
+/// A box that couldn't be parsed, where it was found and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mpeg4Error {
+    /// Path to the box, e.g. /moov/trak/mdia/mdhd, as much of it as is known.
+    pub path: String,
+    /// Offset of the box from the start of the file.
+    pub offset: u64,
+    pub reason: String,
+}
+
+impl Mpeg4Error {
+    pub fn new(path: impl Into<String>, offset: u64, reason: impl Into<String>) -> Mpeg4Error {
+        Mpeg4Error {
+            path: path.into(),
+            offset,
+            reason: reason.into(),
+        }
+    }
+
+    /// An error reading the contents of bx.
+    /// The path is just the box itself, use `within` to add its containers.
+    pub fn in_box(bx: &MP4Box, reason: impl Into<String>) -> Mpeg4Error {
+        Mpeg4Error::new(format!("/{}", bx.box_type.four_cc()), bx.offset, reason)
+    }
+
+    /// Puts the path of the containers holding the box in front of the path.
+    pub fn within(mut self, containers: &str) -> Mpeg4Error {
+        self.path = format!("{}{}", containers.trim_end_matches('/'), self.path);
+        self
+    }
+}
+
+impl fmt::Display for Mpeg4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{} at offset {}: {}", path, self.offset, self.reason)
+    }
+}
+
+impl Error for Mpeg4Error {}
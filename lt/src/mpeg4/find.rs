@@ -1,12 +1,16 @@
 //! Find a specific type of box, in a specific hierarchical place in an MPEG4 stream.
 use crate::mpeg4::boxes;
+use crate::mpeg4::Mpeg4Error;
 use boxes::box_types;
 use boxes::{MP4Box, MP4Buffer};
+use std::error::Error;
+use std::io::{Read, Seek};
 
 /// Searches for a box in an MPG4 buffer.
 ///
 /// Retruns `None` if no such box is found, other wise returns the Box
-/// which matches the last type in the path.
+/// which matches the last type in the path. Returns an error if a box
+/// can't be read before the one we're looking for is found.
 ///
 ///
 /// # Examples
@@ -25,15 +29,15 @@ use boxes::{MP4Box, MP4Buffer};
 /// let buf = vbuf.as_slice();
 ///
 /// // Return the moov box.
-/// let mut bx = find_box("/moov", buf).unwrap();
+/// let mut bx = find_box("/moov", buf).unwrap().unwrap();
 ///  assert_eq!(bx.box_type, box_types::MOOV);
 ///
 /// // Find a specific path to a data box
-/// bx = find_box("/moov/udta/meta/ilst/trkn/data", buf).unwrap();
+/// bx = find_box("/moov/udta/meta/ilst/trkn/data", buf).unwrap().unwrap();
 /// assert_eq!(bx.box_type, box_types::DATA);
 ///
 /// // Find the first data box in the stream.
-/// let bx2 = find_box("data", buf).unwrap();
+/// let bx2 = find_box("data", buf).unwrap().unwrap();
 /// assert_eq!(bx.box_type, box_types::DATA);
 /// assert_ne!(bx, bx2);
 ///
@@ -42,11 +46,11 @@ use boxes::{MP4Box, MP4Buffer};
 /// // boxes in the path. No real structural
 /// // relationship to MPEG and it's conained
 /// // boxes model is implied.
-/// bx = find_box("/moov/mdia/stts/udata/ilst", buf).unwrap();
+/// bx = find_box("/moov/mdia/stts/udata/ilst", buf).unwrap().unwrap();
 /// assert_eq!(bx.box_type, box_types::ILST);
 // / ```
 ///
-pub fn find_box<'a>(path: &str, mut b: &'a [u8]) -> Option<MP4Box<'a>> {
+pub fn find_box<'a>(path: &str, mut b: &'a [u8]) -> Result<Option<MP4Box<'a>>, Mpeg4Error> {
     // create a stack of BoxTypes to searc for from the path
     // Create an MP4Buffer from the buf
     // Get boxes wait for the first box to match the top of the
@@ -56,7 +60,56 @@ pub fn find_box<'a>(path: &str, mut b: &'a [u8]) -> Option<MP4Box<'a>> {
     // pop it and get the next box if it is.
 
     // println!("Staring with path: {:?}", path);
+    let mut bts = match path_stack(path) {
+        Some(bts) => bts,
+        None => return Ok(None),
+    };
+
+    let boxes = MP4Buffer::new(&mut b);
+    for b in boxes {
+        let b = b?;
+        // eprintln!("Looking at {:?}", b);
+        if b.box_type == *bts.last().unwrap() {
+            bts.pop();
+            if bts.is_empty() {
+                return Ok(Some(b));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Searches for a box in an MPEG4 file, the same way as `find_box`,
+/// but only loads the boxes it needs from the file.
+///
+/// Calls f with the box if it's found and returns whether it was found.
+pub fn find_box_in(
+    path: &str,
+    r: impl Read + Seek,
+    mut f: impl FnMut(&MP4Box),
+) -> Result<bool, Box<dyn Error>> {
+    let mut bts = match path_stack(path) {
+        Some(bts) => bts,
+        None => return Ok(false),
+    };
 
+    let mut found = false;
+    boxes::read_boxes(r, |b| {
+        if !found && b.box_type == *bts.last().unwrap() {
+            bts.pop();
+            if bts.is_empty() {
+                found = true;
+                f(&b);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(found)
+}
+
+// The stack of BoxTypes to search for, last first,
+// or None if the path has no box types or any that aren't FourCC.
+fn path_stack(path: &str) -> Option<Vec<box_types::BoxType>> {
     let mut bts = Vec::new();
     let mut v: Vec<&str> = path.rsplit('/').collect();
 
@@ -79,37 +132,24 @@ pub fn find_box<'a>(path: &str, mut b: &'a [u8]) -> Option<MP4Box<'a>> {
         bts.push(bt);
     }
     // eprintln!("Stack: {:?}", bts);
-
-    let boxes = MP4Buffer { buf: &mut b };
-    for b in boxes {
-        // eprintln!("Looking at {:?}", b);
-        if b.box_type == *bts.last().unwrap() {
-            bts.pop();
-            if bts.is_empty() {
-                return Some(b);
-            }
-        }
-    }
-    None
+    Some(bts)
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use std::fs::File;
+
     #[test]
     fn test_find_box() {
-        use std::fs::File;
-        use std::io::Read;
-
-        let f =
-            "/Volumes/London Backups/Itunes_Library/The Beatles/Abbey Road/01 Come Together.m4a";
+        let f = "test_audio/16 The End.m4a";
         let mut file = File::open(f).unwrap();
         let mut vbuf = Vec::<u8>::new();
         let _n = file.read_to_end(&mut vbuf);
         let buf = vbuf.as_slice();
 
-        let mut bx = find_box("/ftyp", buf).unwrap();
+        let mut bx = find_box("/ftyp", buf).unwrap().unwrap();
         assert_eq!(bx.box_type, box_types::FTYP);
 
         // Note, that at least currrently, the
@@ -118,16 +158,16 @@ mod tests {
         // and that find just looks for that
         // sequence of boxes with any number of
         // other boxes in between.
-        bx = find_box("/ftyp/moov", buf).unwrap();
+        bx = find_box("/ftyp/moov", buf).unwrap().unwrap();
         assert_eq!(bx.box_type, box_types::MOOV);
 
-        bx = find_box("/moov/trak", buf).unwrap();
+        bx = find_box("/moov/trak", buf).unwrap().unwrap();
         assert_eq!(bx.box_type, box_types::TRAK);
 
-        bx = find_box("trak/tkhd", buf).unwrap();
+        bx = find_box("trak/tkhd", buf).unwrap().unwrap();
         assert_eq!(bx.box_type, box_types::TKHD);
 
-        bx = find_box("tkhd", buf).unwrap();
+        bx = find_box("tkhd", buf).unwrap().unwrap();
         assert_eq!(bx.box_type, box_types::TKHD);
 
         // TODO(jdr): This may not be the behavior we like.
@@ -136,7 +176,22 @@ mod tests {
         // Couple of things we could do:
         // - Reject any strings < 4 chars long.
         // - Pad anything with < 4 chars to 4 chars with spaces.
-        assert_eq!(find_box("/", buf), None);
-        assert_eq!(find_box("/key", buf), None);
+        assert_eq!(find_box("/", buf), Ok(None));
+        assert_eq!(find_box("/key", buf), Ok(None));
+    }
+
+    #[test]
+    fn test_find_box_in() {
+        let f = "test_audio/16 The End.m4a";
+        let mut size = 0;
+        let found = find_box_in("/moov/trak/mdhd", File::open(f).unwrap(), |bx| {
+            assert_eq!(bx.box_type, box_types::MDHD);
+            size = bx.size;
+        })
+        .unwrap();
+        assert!(found);
+        assert!(size > 0);
+
+        assert!(!find_box_in("/moov/abcd", File::open(f).unwrap(), |_| ()).unwrap());
     }
 }
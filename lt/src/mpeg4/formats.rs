@@ -48,7 +48,7 @@ extern crate num_traits;
 /// 43: SAOC (Spatial Audio Object Coding)
 /// 44: LD MPEG Surround
 /// 45: USAC
-#[derive(FromPrimitive, ToPrimitive)]
+#[derive(Clone, Copy, FromPrimitive, ToPrimitive)]
 pub enum AudioObjectTypes {
     Null = 0,
     AAC,
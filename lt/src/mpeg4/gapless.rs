@@ -0,0 +1,105 @@
+//! Gapless playback information for AAC in MPEG4 (m4a) files.
+//!
+//! There are two places encoders put this. iTunes writes an iTunSMPB
+//! freeform item, a row of hex numbers holding the encoder delay, the padding
+//! and the number of samples of actual audio. Others, and iTunes as well, write
+//! an edit list in the audio trak whose first edit starts after the delay
+//! and lasts as long as the actual audio.
+use super::trak::Trak;
+use crate::track::{Gapless, GaplessSource};
+
+/// Name of the iTunes freeform item holding the gapless information.
+pub const ITUNSMPB: &str = "iTunSMPB";
+
+/// Gapless information from the value of an iTunSMPB item,
+/// e.g. " 00000000 00000840 000001C0 0000000000A1B2C3 ...".
+/// The second, third and fourth numbers are the delay, padding and sample count.
+pub fn from_itunsmpb(s: &str) -> Option<Gapless> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    Some(Gapless {
+        delay: u32::from_str_radix(fields[1], 16).ok()?,
+        padding: u32::from_str_radix(fields[2], 16).ok()?,
+        valid_samples: u64::from_str_radix(fields[3], 16).ok()?,
+        source: GaplessSource::ITunSMPB,
+    })
+}
+
+/// Gapless information from the edit list of an audio trak.
+/// The first edit that isn't empty starts after the delay, and lasts for the
+/// actual audio in the movie's timescale; what's left of the trak is padding.
+/// Returns None if the edits don't trim anything.
+pub fn from_edits(trak: &Trak, movie_timescale: u32, sample_rate: u32) -> Option<Gapless> {
+    let &(segment, media_time) = trak.edits.iter().find(|(_, t)| *t >= 0)?;
+    if movie_timescale == 0 || trak.timescale == 0 || segment == 0 {
+        return None;
+    }
+    let delay = media_time as u64;
+    let valid = (segment as u128 * trak.timescale as u128 / movie_timescale as u128) as u64;
+    let padding = trak.duration.saturating_sub(delay.saturating_add(valid));
+    if delay == 0 && padding == 0 {
+        return None;
+    }
+
+    // Trak times are in the trak's timescale, which is almost always the sample rate.
+    let samples = |t: u64| match sample_rate {
+        0 => t,
+        sr => (t as u128 * sr as u128 / trak.timescale as u128) as u64,
+    };
+    Some(Gapless {
+        delay: samples(delay) as u32,
+        padding: samples(padding) as u32,
+        valid_samples: samples(valid),
+        source: GaplessSource::EditList,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_itunsmpb() {
+        let g = from_itunsmpb(
+            " 00000000 00000840 000001C0 0000000000A1B2C3 00000000 00000000 00000000",
+        )
+        .unwrap();
+        assert_eq!(g.delay, 2112);
+        assert_eq!(g.padding, 448);
+        assert_eq!(g.valid_samples, 0xA1B2C3);
+        assert_eq!(g.source, GaplessSource::ITunSMPB);
+
+        assert_eq!(from_itunsmpb(" 00000000 00000840"), None);
+        assert_eq!(from_itunsmpb(" 00000000 0000084Z 000001C0 00A1B2C3"), None);
+    }
+
+    #[test]
+    fn test_edits() {
+        // 23 seconds at 44,100, with the movie in the same timescale.
+        let mut trak = Trak {
+            timescale: 44_100,
+            duration: 1_014_300,
+            edits: vec![(0, -1), (1_011_740, 2112)],
+            ..Default::default()
+        };
+        let g = from_edits(&trak, 44_100, 44_100).unwrap();
+        assert_eq!(
+            (g.delay, g.padding, g.valid_samples),
+            (2112, 448, 1_011_740)
+        );
+        assert_eq!(g.source, GaplessSource::EditList);
+
+        // An edit of the whole trak trims nothing.
+        trak.edits = vec![(23 * 600, 0)];
+        assert_eq!(from_edits(&trak, 600, 44_100), None);
+        trak.edits.clear();
+        assert_eq!(from_edits(&trak, 600, 44_100), None);
+
+        // A media time that would overflow the end of the edit.
+        trak.edits = vec![(1, i64::MAX)];
+        let g = from_edits(&trak, 44_100, 44_100).unwrap();
+        assert_eq!((g.padding, g.valid_samples), (0, 1));
+    }
+}
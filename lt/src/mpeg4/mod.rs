@@ -1,22 +1,32 @@
-//! Implementation of MPEG4 metadata reading.
+//! Implementation of MPEG4 metadata reading and writing.
 extern crate chrono;
 // use chrono::prelude::DateTime;
 use chrono::{DateTime, Duration, NaiveDate, Utc};
 // use std::time::{Duration, SystemTime, UNIX_EPOCH};
 pub mod boxes;
+pub mod chapters;
+pub mod error;
 pub mod find;
 pub mod formats;
+pub mod gapless;
 // pub mod boxes::box_types;
+pub mod samples;
+pub mod trak;
 pub mod util;
+pub mod write;
 use util::LevelStack;
 
+use crate::art::{self, Artwork};
 use crate::file;
 use crate::file::FileFormat;
 use crate::track;
 use boxes::box_types;
 use boxes::box_types::BoxType;
-use boxes::{ilst, mdia, read_box_size_type, stbl, MP4Buffer};
+use boxes::{ilst, mdia, read_box_size_type, stbl, udta};
+pub use error::Mpeg4Error;
 use formats::DRMSchemes;
+use samples::SampleIndex;
+use trak::Trak;
 
 use std::error::Error;
 use std::io::{Read, Seek};
@@ -43,10 +53,11 @@ pub fn identify(mut b: &[u8]) -> Option<FileFormat> {
     };
 
     // Read the box type.
-    let (_, _, bt) = read_box_size_type(buf);
+    let (_, _, bt) = read_box_size_type(buf)?;
     let bt: BoxType = From::from(bt);
     if bt == box_types::FTYP {
-        boxes::get_ftyp_box_values(&mut buf, &mut br, &mut mp4.version, &mut mp4.flags, &mut cb);
+        boxes::get_ftyp_box_values(&mut buf, &mut br, &mut mp4.version, &mut mp4.flags, &mut cb)
+            .ok()?;
         mp4.brand = String::from_utf8_lossy(br).to_string();
         for s in cb {
             mp4.compatible_brands
@@ -66,15 +77,7 @@ impl file::Decoder for Mpeg4 {
 
     /// Fill a track assumed to be in MPEG4 format from the provider Reed + Seek.
     fn get_track(&mut self, r: impl Read + Seek) -> Result<Option<track::Track>, Box<dyn Error>> {
-        let mut tk = track::Track {
-            ..Default::default()
-        };
-        tk.metadata = Some(track::FormatMetadata::MP4(track::MPEG4Metadata {
-            ..Default::default()
-        }));
-        tk.format = Some(track::CodecFormat::MPEG4(track::MPEG4AudioFormat {
-            ..Default::default()
-        }));
+        let mut tk = new_track();
 
         // println!("File type: {:?}", self);
         read_track(r, &mut tk)?;
@@ -93,19 +96,11 @@ impl Mpeg4 {
     /// as well the path to the particular box.
     /// Finally, this prints structure out using indentation
     /// to indicate conatiners.
-    pub fn display_structure(&self, mut r: impl Read + Seek) -> Result<(), Box<dyn Error>> {
-        let mut vbuf = Vec::<u8>::new();
-        let _n = r.read_to_end(&mut vbuf)?;
-        let boxes = MP4Buffer {
-            buf: &mut vbuf.as_slice(),
-        };
-
-        // let boxes = MP4Buffer::read_from(r)?;
-
+    pub fn display_structure(&self, r: impl Read + Seek) -> Result<(), Box<dyn Error>> {
         // let mut l = LevelStack::new(boxes.buf.len());
         let mut l = LevelStack::new();
         let mut tabs = String::new();
-        for b in boxes {
+        boxes::read_boxes(r, |b| {
             println!(
                 "{}{} [{:?}]    {:?} - Path: {:?}",
                 tabs,
@@ -125,52 +120,196 @@ impl Mpeg4 {
                 if l.len() > 1 {
                     println!("{}<{}>", tabs, l.top().unwrap().box_type.four_cc());
                 }
-            })
-        }
-        Ok(())
+            });
+            Ok(())
+        })
     }
 }
 
-fn read_track(mut r: impl Read + Seek, mut tk: &mut track::Track) -> Result<(), Box<dyn Error>> {
-    let mut vbuf = Vec::<u8>::new();
-    let _n = r.read_to_end(&mut vbuf)?;
-    let buf = vbuf.as_slice();
-    let b: &mut &[u8] = &mut &(*buf);
-    let boxes = MP4Buffer { buf: b };
+/// Reads the sample index of the audio track in an MPEG4 file.
+/// Use `SampleIndex::offset_at` with it to find where in the file a time is.
+pub fn sample_index(r: impl Read + Seek) -> Result<Option<SampleIndex>, Box<dyn Error>> {
+    let mut tk = new_track();
+    read_track(r, &mut tk)?;
+    match tk.format {
+        Some(track::CodecFormat::MPEG4(f)) => Ok(f.samples),
+        _ => Ok(None),
+    }
+}
 
+// A track with empty MPEG4 format and metadata to read into.
+fn new_track() -> track::Track {
+    let mut tk = track::Track {
+        ..Default::default()
+    };
+    tk.metadata = Some(track::FormatMetadata::MP4(track::MPEG4Metadata {
+        ..Default::default()
+    }));
+    tk.format = Some(track::CodecFormat::MPEG4(track::MPEG4AudioFormat {
+        ..Default::default()
+    }));
+    tk
+}
+
+fn read_track(mut r: impl Read + Seek, tk: &mut track::Track) -> Result<(), Box<dyn Error>> {
     // Visiting each box will read the header of the box
     // and if it's a full box the version/flags.
     // Box data itself is only read by calling funtions
     // in the read_box_for_track function.
     let mut ls = LevelStack::new();
-    for b in boxes {
-        read_box_for_track(&mut tk, &mut ls, b);
+    let mut st = ReadState::default();
+    boxes::read_boxes(&mut r, |b| Ok(read_box_for_track(tk, &mut ls, &mut st, b)?))?;
+    let chpl = st.chpl;
+    let movie_timescale = st.movie_timescale;
+
+    // The audio format comes from the audio trak, there may be others.
+    let (traks, mut formats): (Vec<Trak>, Vec<track::MPEG4AudioFormat>) =
+        st.traks.into_iter().unzip();
+    for (t, f) in traks.iter().zip(formats.iter_mut()) {
+        if let Some(si) = f.samples.as_mut() {
+            si.timescale = t.timescale;
+        }
+    }
+    let audio = trak::audio_trak(&traks);
+
+    // Chapters come from the chapter text track if the audio trak names one,
+    // otherwise from a Nero chapter list.
+    if let Some(i) = audio {
+        let text = traks[i]
+            .chapter_tracks
+            .iter()
+            .find_map(|id| traks.iter().position(|t| t.id == *id));
+        if let Some(si) = text.and_then(|t| formats[t].samples.as_ref()) {
+            tk.chapters = chapters::from_text_track(&mut r, si)?;
+        } else if !chpl.is_empty() {
+            tk.chapters = chapters::from_chpl(&chpl, traks[i].duration());
+        }
+    }
+
+    if let Some(track::FormatMetadata::MP4(md)) = tk.metadata.as_mut() {
+        if let Some(i) = audio {
+            md.creation = mp4_time(traks[i].creation);
+            md.modification = mp4_time(traks[i].modification);
+        }
+        md.traks = traks.clone();
+    }
+    if let Some(i) = audio {
+        // Gapless information from iTunSMPB if it's there, otherwise the edit list.
+        let smpb = match tk.metadata.as_ref() {
+            Some(track::FormatMetadata::MP4(md)) => {
+                md.freeform_value(write::ITUNES_MEAN, gapless::ITUNSMPB)
+            }
+            _ => None,
+        };
+        let mut format = formats.swap_remove(i);
+        format.gapless = smpb.and_then(gapless::from_itunsmpb).or_else(|| {
+            gapless::from_edits(&traks[i], movie_timescale, format.sample_rate() as u32)
+        });
+        tk.format = Some(track::CodecFormat::MPEG4(format));
     }
     Ok(())
 }
 
-fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b: boxes::MP4Box) {
-    let format = if let track::CodecFormat::MPEG4(f) = tk.format.as_mut().unwrap() {
-        f
-    } else {
-        panic!("CodecFormat not attached to track.");
+// What's been read that doesn't go straight into the track.
+#[derive(Default)]
+struct ReadState {
+    // Each trak with the audio format read from it.
+    traks: Vec<(Trak, track::MPEG4AudioFormat)>,
+    // Nero chapters, (start, title).
+    chpl: Vec<(u64, String)>,
+    // Mean and name of the freeform item being read.
+    mean: String,
+    name: String,
+    // Units per second of the movie, from the mvhd.
+    movie_timescale: u32,
+}
+
+// The sample index of a format being read.
+fn samples(format: &mut track::MPEG4AudioFormat) -> &mut SampleIndex {
+    format.samples.get_or_insert_with(SampleIndex::default)
+}
+
+// MPEG4 times are seconds since midnight 1/1/1904 in UTC.
+fn mp4_time(secs: u64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_utc(
+        NaiveDate::from_ymd(1904, 1, 1).and_hms(0, 0, 0) + Duration::seconds(secs as i64),
+        Utc,
+    )
+}
+
+fn read_box_for_track<'a>(
+    tk: &mut track::Track,
+    path: &'a mut LevelStack,
+    st: &mut ReadState,
+    mut b: boxes::MP4Box,
+) -> Result<(), Mpeg4Error> {
+    let ReadState {
+        traks,
+        chpl,
+        mean,
+        name,
+        movie_timescale,
+    } = st;
+
+    // Each trak gets its own description and audio format,
+    // boxes that belong in a trak but aren't in one are read and dropped.
+    if b.box_type == box_types::TRAK {
+        traks.push(Default::default());
+    }
+    let mut outside = Default::default();
+    let (trak, format) = match traks.last_mut() {
+        Some(t) => t,
+        None => &mut outside,
     };
+    if matches!(path.top(), Some(c) if c.box_type == box_types::STSD)
+        && trak.sample_format.is_none()
+    {
+        trak.sample_format = Some(b.box_type.four_cc());
+    }
 
-    let md = if let track::FormatMetadata::MP4(f) = tk.metadata.as_mut().unwrap() {
+    let md = if let Some(track::FormatMetadata::MP4(f)) = tk.metadata.as_mut() {
         f
     } else {
-        panic!("Metadata not attached to track.")
+        return Err(
+            Mpeg4Error::in_box(&b, "no MPEG4 metadata attached to the track")
+                .within(&path.path_string()),
+        );
     };
 
     match &b.box_type {
+        // Freeform items are keyed by their mean and name rather than the box type.
+        &box_types::____ => {
+            mean.clear();
+            name.clear();
+        }
+        &box_types::MEAN => {
+            *mean = ilst::read_string(&mut b).map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::NAME => {
+            *name = ilst::read_string(&mut b).map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::DATA if matches!(path.top(), Some(c) if c.box_type == box_types::____) => {
+            let db = ilst::get_data_box(&mut b).map_err(|e| e.within(&path.path_string()))?;
+            let value = match db {
+                ilst::DataBoxContent::Text(v) | ilst::DataBoxContent::Data(v) => {
+                    String::from_utf8_lossy(v).to_string()
+                }
+                ilst::DataBoxContent::Byte(v) => v.to_string(),
+                ilst::DataBoxContent::Image(m, _) => m.to_string(),
+            };
+            md.add_freeform(mean, name, &value);
+        }
         &box_types::DATA => {
-            let db = ilst::get_data_box(&mut b);
-
             // This is used to determine where the data goes.
             // It's the previous box type that determines
             // the key for the metadata. eg. ilist/trkn/data implies that the
             // data is a track number.
-            let bt = &path.top().unwrap().box_type;
+            let bt = match path.top() {
+                Some(c) => &c.box_type,
+                None => return Err(Mpeg4Error::in_box(&b, "data box outside of an item")),
+            };
+            let offset = b.offset;
+            let db = ilst::get_data_box(&mut b).map_err(|e| e.within(&path.path_string()))?;
             match db {
                 // if let DataBoxContent::Text(v) = db {
                 ilst::DataBoxContent::Text(v) => {
@@ -208,7 +347,14 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
                         _ => (),
                     }
                 }
-                ilst::DataBoxContent::Data(v) => match path.top().unwrap().box_type {
+                ilst::DataBoxContent::Data(v) => match *bt {
+                    box_types::TRKN | box_types::DISK if v.len() < 6 => {
+                        return Err(Mpeg4Error::new(
+                            format!("{}/data", path.path_string()),
+                            offset,
+                            format!("{} bytes left, too short for a number and total", v.len()),
+                        ));
+                    }
                     box_types::TRKN => {
                         tk.track_number = Some(u16::from_be_bytes([v[2], v[3]]) as u32);
                         tk.track_total = Some(u16::from_be_bytes([v[4], v[5]]) as u32);
@@ -217,8 +363,19 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
                         tk.disk_number = Some(u16::from_be_bytes([v[2], v[3]]) as u32);
                         tk.disk_total = Some(u16::from_be_bytes([v[4], v[5]]) as u32);
                     }
+                    // Some taggers leave the image type implicit.
+                    box_types::COVR => {
+                        tk.artwork
+                            .push(Artwork::new(art::FRONT_COVER, "", "", v.to_vec()));
+                    }
                     _ => (),
                 },
+                ilst::DataBoxContent::Image(mime_type, v) => {
+                    if *bt == box_types::COVR {
+                        tk.artwork
+                            .push(Artwork::new(art::FRONT_COVER, mime_type, "", v.to_vec()));
+                    }
+                }
                 ilst::DataBoxContent::Byte(v) => {
                     // TODO(jdr): Consider adding a text translation.
                     md.byte.insert(
@@ -246,7 +403,8 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
                 &mut channels,
                 &mut format.bits_per_sample,
                 &mut format.sr,
-            );
+            )
+            .map_err(|e| e.within(&path.path_string()))?;
             format.channels = channels as u8;
             // TODO(jdr): Change this to pull out the DRM protection
             // from the schm box.
@@ -267,7 +425,7 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
         // TODO(jdr): possibly we move the whole protection thing into here.
         &box_types::SCHM => {
             let mut v = 0;
-            stbl::read_schm(&mut b, &mut v);
+            stbl::read_schm(&mut b, &mut v).map_err(|e| e.within(&path.path_string()))?;
             format.protection_scheme = Some(DRMSchemes::from(v));
         }
             ,
@@ -283,32 +441,64 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
                 &mut format.codec,
                 &mut sampling_frequency,
                 &mut format.channel_config,
-            );
+            )
+            .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::TKHD => {
+            let mut duration = 0;
+            boxes::trak::read_tkhd(&mut b, &mut trak.id, &mut trak.flags, &mut duration)
+                .map_err(|e| e.within(&path.path_string()))?;
         }
         &box_types::MDHD => {
-            let mut creation: u64 = 0;
-            let mut modification: u64 = 0;
-            let mut timescale: u32 = 0;
             let mut language: u16 = 0;
             mdia::get_mdhd(
                 &mut b,
-                &mut creation,
-                &mut modification,
-                &mut timescale,
-                &mut format.total_samples,
+                &mut trak.creation,
+                &mut trak.modification,
+                &mut trak.timescale,
+                &mut trak.duration,
                 &mut language,
-            );
-
-            md.modification = DateTime::<Utc>::from_utc(
-                NaiveDate::from_ymd(1904, 1, 1).and_hms(0, 0, 0)
-                    + Duration::seconds(modification as i64),
-                Utc,
-            );
-            md.creation = DateTime::<Utc>::from_utc(
-                NaiveDate::from_ymd(1904, 1, 1).and_hms(0, 0, 0)
-                    + Duration::seconds(creation as i64),
-                Utc,
-            );
+            )
+            .map_err(|e| e.within(&path.path_string()))?;
+            trak.language = mdia::language_code(language);
+            format.total_samples = trak.duration;
+        }
+        &box_types::MVHD => {
+            boxes::trak::read_mvhd(&mut b, movie_timescale)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::ELST => {
+            boxes::trak::read_elst(&mut b, &mut trak.edits)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::CHAP if matches!(path.top(), Some(c) if c.box_type == box_types::TREF) => {
+            boxes::trak::read_chap(&mut b, &mut trak.chapter_tracks)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::CHPL => {
+            udta::read_chpl(&mut b, chpl).map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::HDLR if matches!(path.top(), Some(c) if c.box_type == box_types::MDIA) => {
+            mdia::read_hdlr(&mut b, &mut trak.handler)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        // The sample tables.
+        &box_types::STTS => {
+            stbl::read_stts(&mut b, &mut samples(format).durations)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::STSZ => {
+            let si = samples(format);
+            stbl::read_stsz(&mut b, &mut si.sample_size, &mut si.sample_count, &mut si.sizes)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::STSC => {
+            stbl::read_stsc(&mut b, &mut samples(format).chunks)
+                .map_err(|e| e.within(&path.path_string()))?;
+        }
+        &box_types::STCO | &box_types::CO64 => {
+            stbl::read_stco(&mut b, &mut samples(format).chunk_offsets)
+                .map_err(|e| e.within(&path.path_string()))?;
         }
         &box_types::MDAT => md.media_size = b.size,
         // TODO(jdr): This should require a flag to turn on the printing.
@@ -317,4 +507,104 @@ fn read_box_for_track<'a>(tk: &mut track::Track, path: &'a mut LevelStack, mut b
     }
 
     path.update(b);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_sample_index() {
+        let f = "test_audio/16 The End.m4a";
+        let si = sample_index(File::open(f).unwrap()).unwrap().unwrap();
+        assert_eq!(si.sample_count as usize, si.sizes.len());
+        assert!(si.avg_bitrate() > 0);
+
+        // The first sample is at the start of the first chunk, in the mdat.
+        let first = si.offset_at(std::time::Duration::from_secs(0)).unwrap();
+        assert_eq!(first, si.chunk_offsets[0]);
+        let mut mdat = (0, 0);
+        find::find_box_in("mdat", File::open(f).unwrap(), |bx| {
+            mdat = (bx.offset, bx.offset + bx.size)
+        })
+        .unwrap();
+        assert!(mdat.0 < first && first < mdat.1);
+        let past_end = si.duration() + std::time::Duration::from_secs(1);
+        assert_eq!(si.offset_at(past_end), None);
+
+        // Agrees with the duration in the mdhd.
+        let mut tk = new_track();
+        read_track(File::open(f).unwrap(), &mut tk).unwrap();
+        if let Some(track::CodecFormat::MPEG4(f)) = tk.format {
+            let mdhd = f.total_samples as f64 / si.timescale as f64;
+            assert!((si.duration().as_secs_f64() - mdhd).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_traks() {
+        let f = "test_audio/16 The End.m4a";
+        let mut tk = new_track();
+        read_track(File::open(f).unwrap(), &mut tk).unwrap();
+        let md = match tk.metadata {
+            Some(track::FormatMetadata::MP4(md)) => md,
+            _ => panic!("no MPEG4 metadata"),
+        };
+        assert_eq!(md.traks.len(), 1);
+        let t = &md.traks[0];
+        assert!(t.is_audio() && t.enabled());
+        assert_eq!(t.sample_format.as_deref(), Some("mp4a"));
+        assert_eq!(t.language.len(), 3);
+
+        if let Some(track::CodecFormat::MPEG4(f)) = tk.format {
+            assert_eq!(f.samples.unwrap().timescale, t.timescale);
+            assert!(f.channels > 0);
+        }
+    }
+
+    #[test]
+    fn test_artwork() {
+        let mut tk = new_track();
+        read_track(File::open("test_audio/16 The End.m4a").unwrap(), &mut tk).unwrap();
+        assert_eq!(tk.artwork.len(), 1);
+        let a = &tk.artwork[0];
+        assert_eq!(a.picture_type, art::FRONT_COVER);
+        assert_eq!(a.mime_type, "image/png");
+        assert_eq!((a.width, a.height), (500, 500));
+        assert!(a.data.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_freeform() {
+        let f = "test_audio/16 The End.m4a";
+        let buf = std::fs::read(f).unwrap();
+        let items = [
+            write::IlstItem::freeform("iTunSMPB", " 00000000 00000840 000001C0 0000000000A1B2C3"),
+            write::IlstItem::freeform("MusicBrainz Track Id", "1234"),
+        ];
+        let out = write::update_items(&buf, &items).unwrap();
+        let mut tk = new_track();
+        read_track(std::io::Cursor::new(out), &mut tk).unwrap();
+        let md = match &tk.metadata {
+            Some(track::FormatMetadata::MP4(md)) => md,
+            _ => panic!("no MPEG4 metadata"),
+        };
+
+        // The one already in the file and the two new ones, each under its own key.
+        assert_eq!(md.freeform.len(), 3);
+        assert_eq!(
+            md.freeform_value(write::ITUNES_MEAN, "MusicBrainz Track Id"),
+            Some("1234")
+        );
+        let smpb = md.text.get("com.apple.iTunes:iTunSMPB").unwrap();
+        assert_eq!(smpb.value, " 00000000 00000840 000001C0 0000000000A1B2C3");
+        assert!(!md.text.contains_key("----"));
+
+        // The gapless information comes from the iTunSMPB.
+        let g = tk.gapless().unwrap();
+        assert_eq!((g.delay, g.padding), (2112, 448));
+        assert_eq!(g.source, track::GaplessSource::ITunSMPB);
+    }
 }
@@ -0,0 +1,214 @@
+//! Index of the samples in a track, built from its sample table boxes
+//! (stts, stsz, stsc and stco/co64).
+//!
+//! With it we can get the exact duration and bitrate of a track,
+//! rather than relying on the values an encoder chose to write, and find where
+//! in the file the sample playing at any time is.
+use std::time::Duration;
+
+const BILLION: u64 = 1_000_000_000;
+
+/// Where and how long each sample in a track is.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct SampleIndex {
+    /// Units per second of the sample durations, from the mdhd.
+    pub timescale: u32,
+    /// Runs of (sample count, sample duration), from the stts.
+    pub durations: Vec<(u32, u32)>,
+    /// Size of every sample if they're all the same, otherwise 0, from the stsz.
+    pub sample_size: u32,
+    pub sample_count: u32,
+    /// Size of each sample when they're not all the same, from the stsz.
+    pub sizes: Vec<u32>,
+    /// Runs of (first chunk, samples per chunk), from the stsc.
+    /// Chunks are numbered from 1.
+    pub chunks: Vec<(u32, u32)>,
+    /// File offset of each chunk, from the stco or co64.
+    pub chunk_offsets: Vec<u64>,
+}
+
+impl SampleIndex {
+    /// Size in bytes of sample n (from 0).
+    pub fn size(&self, n: u32) -> u32 {
+        if self.sample_size == 0 {
+            self.sizes.get(n as usize).copied().unwrap_or(0)
+        } else {
+            self.sample_size
+        }
+    }
+
+    /// Total size of the samples in bytes.
+    pub fn total_size(&self) -> u64 {
+        if self.sample_size == 0 {
+            self.sizes.iter().map(|&s| s as u64).sum()
+        } else {
+            self.sample_size as u64 * self.sample_count as u64
+        }
+    }
+
+    /// Duration of the track in timescale units.
+    pub fn total_duration(&self) -> u64 {
+        self.durations
+            .iter()
+            .map(|&(c, d)| c as u64 * d as u64)
+            .sum()
+    }
+
+    /// Length of the track.
+    pub fn duration(&self) -> Duration {
+        if self.timescale == 0 {
+            return Duration::from_nanos(0);
+        }
+        let ns = self.total_duration() as u128 * BILLION as u128 / self.timescale as u128;
+        Duration::from_nanos(ns as u64)
+    }
+
+    /// Average bitrate in bits per second over the whole track.
+    pub fn avg_bitrate(&self) -> u32 {
+        let bits = self.total_size() as u128 * 8 * self.timescale as u128;
+        bits.checked_div(self.total_duration() as u128).unwrap_or(0) as u32
+    }
+
+    /// Bitrate in bits per second of each second or so of the track.
+    pub fn bitrates(&self) -> Vec<u32> {
+        let mut rates = Vec::new();
+        let (mut bytes, mut ticks) = (0u64, 0u64);
+        for (n, d) in self.sample_durations().enumerate() {
+            bytes += self.size(n as u32) as u64;
+            ticks += d as u64;
+            if ticks >= self.timescale as u64 && ticks > 0 {
+                rates.push((bytes * 8 * self.timescale as u64 / ticks) as u32);
+                bytes = 0;
+                ticks = 0;
+            }
+        }
+        // What's left over at the end.
+        if let Some(r) = (bytes * 8 * self.timescale as u64).checked_div(ticks) {
+            rates.push(r as u32);
+        }
+        rates
+    }
+
+    /// How the bitrate varies over the track.
+    /// Returns the low end of each step bits per second wide band that the
+    /// bitrate was in, and the fraction of the track that it was in it, lowest first.
+    pub fn bitrate_distribution(&self, step: u32) -> Vec<(u32, f64)> {
+        let rates = self.bitrates();
+        let mut bands: Vec<(u32, f64)> = Vec::new();
+        if rates.is_empty() || step == 0 {
+            return bands;
+        }
+        let share = 1.0 / rates.len() as f64;
+        for r in rates {
+            let low = r / step * step;
+            match bands.binary_search_by_key(&low, |b| b.0) {
+                Ok(i) => bands[i].1 += share,
+                Err(i) => bands.insert(i, (low, share)),
+            }
+        }
+        bands
+    }
+
+    /// File offset of the sample playing at time t, or None if t is past
+    /// the end of the track.
+    pub fn offset_at(&self, t: Duration) -> Option<u64> {
+        let target = t.as_nanos() * self.timescale as u128 / BILLION as u128;
+        let mut end = 0u128;
+        for (n, d) in self.sample_durations().enumerate() {
+            end += d as u128;
+            if target < end {
+                return self.sample_offset(n as u32);
+            }
+        }
+        None
+    }
+
+    /// File offset of sample n (from 0).
+    /// None if there isn't one, or the sample table doesn't add up,
+    /// e.g. its counts overflow or a chunk is numbered 0.
+    pub fn sample_offset(&self, n: u32) -> Option<u64> {
+        // Find the chunk holding the sample and the first sample in that chunk.
+        let mut first_sample = 0u32;
+        for (i, &(first_chunk, per_chunk)) in self.chunks.iter().enumerate() {
+            let next_chunk = match self.chunks.get(i + 1) {
+                Some(&(c, _)) => c,
+                None => (self.chunk_offsets.len() as u32).checked_add(1)?,
+            };
+            let run_samples = next_chunk
+                .saturating_sub(first_chunk)
+                .checked_mul(per_chunk)?;
+            let run_end = first_sample.checked_add(run_samples)?;
+            if per_chunk > 0 && n < run_end {
+                let chunk = first_chunk.checked_add((n - first_sample) / per_chunk)?;
+                let chunk_first = first_sample + (chunk - first_chunk) * per_chunk;
+                let index = chunk.checked_sub(1)? as usize;
+                let offset = *self.chunk_offsets.get(index)?;
+                let before: u64 = (chunk_first..n).map(|s| self.size(s) as u64).sum();
+                return offset.checked_add(before);
+            }
+            first_sample = run_end;
+        }
+        None
+    }
+
+    /// The duration of each sample in order, in timescale units.
+    pub fn sample_durations(&self) -> impl Iterator<Item = u32> + '_ {
+        self.durations
+            .iter()
+            .flat_map(|&(c, d)| (0..c).map(move |_| d))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 samples of 1024 at 44,100 per second, in chunks of 4, 4 and 2.
+    fn index() -> SampleIndex {
+        SampleIndex {
+            timescale: 44_100,
+            durations: vec![(9, 1024), (1, 500)],
+            sample_size: 0,
+            sample_count: 10,
+            sizes: vec![100, 200, 300, 400, 500, 600, 700, 800, 900, 1000],
+            chunks: vec![(1, 4), (3, 2)],
+            chunk_offsets: vec![1000, 5000, 9000],
+        }
+    }
+
+    #[test]
+    fn test_sample_index() {
+        let si = index();
+        assert_eq!(si.total_duration(), 9 * 1024 + 500);
+        assert_eq!(si.total_size(), 5500);
+        assert_eq!(si.duration(), Duration::from_nanos(220_317_460));
+        assert_eq!(si.avg_bitrate(), 5500 * 8 * 44_100 / (9 * 1024 + 500));
+
+        assert_eq!(si.sample_offset(0), Some(1000));
+        assert_eq!(si.sample_offset(3), Some(1000 + 100 + 200 + 300));
+        assert_eq!(si.sample_offset(4), Some(5000));
+        assert_eq!(si.sample_offset(9), Some(9000 + 900));
+        assert_eq!(si.sample_offset(10), None);
+
+        // A bad stsc doesn't panic.
+        let bad = SampleIndex {
+            chunks: vec![(0, 4)],
+            ..index()
+        };
+        assert_eq!(bad.sample_offset(0), None);
+        let bad = SampleIndex {
+            chunks: vec![(1, u32::MAX), (3, 2)],
+            ..index()
+        };
+        assert_eq!(bad.sample_offset(9), None);
+
+        assert_eq!(si.offset_at(Duration::from_secs(0)), Some(1000));
+        // 1024/44,100 is about 23.2ms, so 50ms is in the third sample.
+        assert_eq!(si.offset_at(Duration::from_millis(50)), Some(1300));
+        assert_eq!(si.offset_at(Duration::from_secs(1)), None);
+
+        // The whole track is less than a second.
+        assert_eq!(si.bitrates(), vec![si.avg_bitrate()]);
+        assert_eq!(si.bitrate_distribution(32_000), vec![(192_000, 1.0)]);
+    }
+}
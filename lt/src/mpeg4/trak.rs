@@ -0,0 +1,123 @@
+//! Description of each track (trak box) in an MPEG4 file.
+//!
+//! A file can have more than one: audio along with a chapter text track,
+//! or a music video with video and audio tracks.
+use super::boxes::box_types::FourCC;
+use super::boxes::trak::TRACK_ENABLED;
+use std::fmt;
+use std::time::Duration;
+
+const BILLION: u64 = 1_000_000_000;
+
+/// Handler type of an audio track.
+pub const SOUND_HANDLER: u32 = u32::from_be_bytes(*b"soun");
+
+/// What's known about a track from its headers.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Trak {
+    /// Track id from the tkhd.
+    pub id: u32,
+    /// Flags from the tkhd, see `boxes::trak::TRACK_ENABLED` etc.
+    pub flags: u32,
+    /// Handler type from the hdlr, e.g. 'soun', 'text' or 'vide'.
+    pub handler: u32,
+    /// Four character code of the first sample entry in the stsd, e.g. "mp4a".
+    pub sample_format: Option<String>,
+    /// Units per second, from the mdhd.
+    pub timescale: u32,
+    /// Duration in timescale units, from the mdhd.
+    pub duration: u64,
+    /// ISO 639-2/T language code, from the mdhd.
+    pub language: String,
+    /// Seconds since midnight 1/1/1904 in UTC, from the mdhd.
+    pub creation: u64,
+    pub modification: u64,
+    /// Ids of the text tracks holding this track's chapters, from the tref/chap.
+    pub chapter_tracks: Vec<u32>,
+    /// (segment duration, media time) of each edit, from the edts/elst,
+    /// see `boxes::trak::read_elst`.
+    pub edits: Vec<(u64, i64)>,
+}
+
+impl Trak {
+    pub fn enabled(&self) -> bool {
+        self.flags & TRACK_ENABLED != 0
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.handler == SOUND_HANDLER
+    }
+
+    /// Length of the track.
+    pub fn duration(&self) -> Duration {
+        if self.timescale == 0 {
+            return Duration::from_nanos(0);
+        }
+        let ns = self.duration as u128 * BILLION as u128 / self.timescale as u128;
+        Duration::from_nanos(ns as u64)
+    }
+}
+
+impl fmt::Display for Trak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.duration().as_secs();
+        write!(
+            f,
+            "{}: {} ({}) {} {}:{:02}{}",
+            self.id,
+            FourCC(self.handler),
+            self.sample_format.as_deref().unwrap_or("-"),
+            self.language,
+            secs / 60,
+            secs % 60,
+            if self.enabled() { "" } else { " disabled" }
+        )
+    }
+}
+
+/// Index of the track whose audio format describes the file:
+/// the first enabled audio track, or the first audio track if none are enabled.
+pub fn audio_trak(traks: &[Trak]) -> Option<usize> {
+    traks
+        .iter()
+        .position(|t| t.is_audio() && t.enabled())
+        .or_else(|| traks.iter().position(|t| t.is_audio()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_trak() {
+        let text = Trak {
+            id: 1,
+            flags: TRACK_ENABLED,
+            handler: u32::from_be_bytes(*b"text"),
+            ..Default::default()
+        };
+        let disabled = Trak {
+            id: 2,
+            handler: SOUND_HANDLER,
+            ..Default::default()
+        };
+        let audio = Trak {
+            id: 3,
+            flags: TRACK_ENABLED,
+            handler: SOUND_HANDLER,
+            sample_format: Some("mp4a".to_string()),
+            timescale: 44_100,
+            duration: 44_100 * 95,
+            language: "eng".to_string(),
+            ..Default::default()
+        };
+
+        let traks = vec![text.clone(), disabled.clone(), audio.clone()];
+        assert_eq!(audio_trak(&traks), Some(2));
+        assert_eq!(audio_trak(&traks[0..2]), Some(1));
+        assert_eq!(audio_trak(&traks[0..1]), None);
+
+        assert_eq!(audio.to_string(), "3: soun (mp4a) eng 1:35");
+        assert_eq!(disabled.to_string(), "2: soun (-)  0:00 disabled");
+    }
+}
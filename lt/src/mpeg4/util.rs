@@ -9,8 +9,8 @@ use std::fmt;
 /// So I just decieded that this was an easier path.
 // #[derive(Clone, Copy)]
 pub struct BoxCounter {
-    pub size: usize,
-    pub count: usize,
+    pub size: u64,
+    pub count: u64,
     pub box_type: BoxType, // TODO(jdr): this should probably be a reference.
 }
 
@@ -55,7 +55,7 @@ impl LevelStack {
             // println!("Adding box: {:?}", b);
             // println!("Stack: {:?}", self);
             let mut last = self.levels.last_mut().unwrap();
-            last.count += b.size as usize;
+            last.count += b.size;
 
             // // Don't forget to count the goofy special boxes that are both
             // // containers boxes that have things in them that count against
@@ -70,7 +70,7 @@ impl LevelStack {
         }
 
         if b.box_type.is_container() {
-            self.push_new(b.size as usize, b.box_type);
+            self.push_new(b.size, b.header_size, b.box_type);
         }
     }
 
@@ -86,13 +86,13 @@ impl LevelStack {
     //     f(self, &b);
     // }
 
-    fn push_new(&mut self, sz: usize, bt: BoxType) {
+    fn push_new(&mut self, sz: u64, header_size: usize, bt: BoxType) {
         // act as if you've read in the header
-        let mut c = bt.header_size();
+        let mut c = header_size as u64;
         // ... and any additional amount that's in a "Special Container".
         if let ContainerType::Special(v) = bt.spec().container {
             // println!("Adding a special conatiner with size: {}", v);
-            c += v;
+            c += v as u64;
         }
         self.levels.push(BoxCounter {
             size: sz,
@@ -157,7 +157,7 @@ impl LevelStack {
             if last.size < last.count {
                 eprintln!(
                     "Just grew past current container! Diff = {}",
-                    last.size as i32 - last.count as i32
+                    last.size as i64 - last.count as i64
                 );
             }
             last.size == last.count
@@ -346,17 +346,16 @@ mod tests {
 
     #[test]
     fn test_structure() {
-        let f = "/Volumes/London Backups/Itunes_Library/The Beatles/Abbey Road/16 The End.m4a";
+        let f = "test_audio/16 The End.m4a";
         let mut file = File::open(f).unwrap();
         let mut vbuf = Vec::<u8>::new();
         let _n = file.read_to_end(&mut vbuf);
-        let boxes = MP4Buffer {
-            buf: &mut vbuf.as_slice(),
-        };
+        let mut buf = vbuf.as_slice();
+        let boxes = MP4Buffer::new(&mut buf);
 
         let mut l = LevelStack::new();
         for b in boxes {
-            l.add_box(b);
+            l.add_box(b.unwrap());
             while l.complete() {
                 l.pop();
                 if l.is_empty() {
@@ -386,7 +385,7 @@ mod tests {
             l.add_box(t.remove(0));
             println!("After Box 1 stack: {:?}", l);
             assert_eq!(l.top().unwrap().size, 100);
-            assert_eq!(l.top().unwrap().count, BOX_HEADER_SIZE);
+            assert_eq!(l.top().unwrap().count, BOX_HEADER_SIZE as u64);
             assert_eq!(l.len(), 1);
 
             l.add_box(t.remove(0));
@@ -451,7 +450,9 @@ mod tests {
     // Box builders
     fn new_defined_box(s: usize, bt: BoxType) -> MP4Box<'static> {
         MP4Box {
-            size: s as u32,
+            offset: 0,
+            size: s as u64,
+            header_size: bt.header_size(),
             buf: &[0],
             box_type: bt,
             version_flag: None,
@@ -471,17 +472,21 @@ mod tests {
     }
 
     fn new_special_container(s: u32, id: u32, v: usize, fl: bool) -> MP4Box<'static> {
-        new_empty_box(s, id, ContainerType::Special(v), false)
+        new_empty_box(s, id, ContainerType::Special(v), fl)
     }
 
     fn new_empty_box(s: u32, id: u32, ct: ContainerType, fl: bool) -> MP4Box<'static> {
+        let bt = BoxType::Unknown(BoxSpec {
+            bt_id: id,
+            container: ct,
+            full: fl,
+            description: "Unknown",
+        });
         MP4Box {
-            size: s,
-            box_type: BoxType::Unknown(BoxSpec {
-                bt_id: id,
-                container: ct,
-                full: fl,
-            }),
+            offset: 0,
+            size: s as u64,
+            header_size: bt.header_size(),
+            box_type: bt,
             buf: &[0],
             version_flag: None,
         }
@@ -0,0 +1,598 @@
+//! Writer for Apple ilst metadata, found at /moov/udta/meta/ilst.
+//!
+//! The ilst box is rebuilt with the new items, replacing existing items of the same type,
+//! and everything else in the file is kept as is. If the new moov box fits in the space of
+//! the old one, along with any free boxes directly after ilst and after moov, the media data
+//! doesn't move and the space left over is kept as a free box. Otherwise the media data
+//! moves and the stco/co64 chunk offsets are updated to point to its new position.
+use super::boxes::box_types;
+use super::boxes::box_types::{BoxType, ContainerType};
+use super::boxes::ilst::{IMPLICIT_FLAG, JPEG_FLAG, PNG_FLAG, TEXT_FLAG};
+use super::boxes::{read_box_header, read_boxes, MP4Box, BOX_HEADER_SIZE};
+use crate::art;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Free space left after ilst when the media data has to move anyway,
+/// so that small changes later don't have to move it again.
+pub const DEFAULT_PADDING: usize = 2048;
+
+/// The mean used by iTunes for its freeform items.
+pub const ITUNES_MEAN: &str = "com.apple.iTunes";
+
+// Version and flags of a full box.
+const VERSION_FLAG_SIZE: usize = 4;
+
+/// An item to write to ilst.
+#[derive(Debug)]
+pub enum IlstItem {
+    /// A text item, identified by its box type e.g. `box_types::XNAM` for the title.
+    Text(BoxType, String),
+    /// Track number and total tracks (trkn).
+    Track(u16, u16),
+    /// Disk number and total disks (disk).
+    Disk(u16, u16),
+    /// A freeform (----) item with its mean, name and value.
+    Freeform(String, String, String),
+    /// Cover art (covr), a JPEG or PNG image.
+    Cover(Vec<u8>),
+}
+
+impl IlstItem {
+    pub fn title(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::XNAM, s.to_string())
+    }
+
+    pub fn artist(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::XARTC, s.to_string())
+    }
+
+    pub fn album(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::XALB, s.to_string())
+    }
+
+    pub fn album_artist(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::AARTC, s.to_string())
+    }
+
+    pub fn genre(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::XGEN, s.to_string())
+    }
+
+    pub fn year(s: &str) -> IlstItem {
+        IlstItem::Text(box_types::XDAY, s.to_string())
+    }
+
+    /// A freeform item with the iTunes mean.
+    pub fn freeform(name: &str, value: &str) -> IlstItem {
+        IlstItem::Freeform(ITUNES_MEAN.to_string(), name.to_string(), value.to_string())
+    }
+
+    fn box_type(&self) -> BoxType {
+        match self {
+            IlstItem::Text(bt, _) => BoxType::from(bt.spec().bt_id),
+            IlstItem::Track(_, _) => box_types::TRKN,
+            IlstItem::Disk(_, _) => box_types::DISK,
+            IlstItem::Freeform(_, _, _) => box_types::____,
+            IlstItem::Cover(_) => box_types::COVR,
+        }
+    }
+
+    /// The item as an ilst child box.
+    pub fn to_box(&self) -> Vec<u8> {
+        let content = match self {
+            IlstItem::Text(_, s) => data_box(TEXT_FLAG, s.as_bytes()),
+            IlstItem::Track(n, t) => data_box(
+                IMPLICIT_FLAG,
+                &[&[0, 0], &n.to_be_bytes()[..], &t.to_be_bytes(), &[0, 0]].concat(),
+            ),
+            IlstItem::Disk(n, t) => data_box(
+                IMPLICIT_FLAG,
+                &[&[0, 0], &n.to_be_bytes()[..], &t.to_be_bytes()].concat(),
+            ),
+            IlstItem::Freeform(mean, name, value) => [
+                full_box(&box_types::MEAN, 0, mean.as_bytes()),
+                full_box(&box_types::NAME, 0, name.as_bytes()),
+                data_box(TEXT_FLAG, value.as_bytes()),
+            ]
+            .concat(),
+            IlstItem::Cover(image) => {
+                let flag = match art::sniff_mime_type(image) {
+                    Some(art::PNG_MIME) => PNG_FLAG,
+                    _ => JPEG_FLAG,
+                };
+                data_box(flag, image)
+            }
+        };
+        make_box(&self.box_type(), &content)
+    }
+
+    // True if this item replaces the existing ilst child box b.
+    fn replaces(&self, b: &[u8]) -> bool {
+        if BoxType::from(&b[4..8]) != self.box_type() {
+            return false;
+        }
+        match self {
+            IlstItem::Freeform(mean, name, _) => {
+                matches!(freeform_key(b), Some((m, n)) if m == *mean && n == *name)
+            }
+            _ => true,
+        }
+    }
+}
+
+// The mean and name of a freeform item.
+fn freeform_key(b: &[u8]) -> Option<(String, String)> {
+    let bx = read_box_header(&mut &b[..], 0).ok()?;
+    let mut mean = None;
+    let mut name = None;
+    for c in children(b, &bx).ok()? {
+        let text = || String::from_utf8_lossy(&b[content_start(&c)..end(&c)]).to_string();
+        match c.box_type {
+            box_types::MEAN => mean = Some(text()),
+            box_types::NAME => name = Some(text()),
+            _ => (),
+        }
+    }
+    Some((mean?, name?))
+}
+
+/// Set ilst items in an MPEG-4 file.
+///
+/// Only moov is read. If the new moov fits in the old one and the free box after it,
+/// just that range of the file is written, in place. Otherwise the media data moves,
+/// and the whole file is copied with the new moov.
+pub fn write_items(p: &Path, items: &[IlstItem]) -> Result<(), Box<dyn Error>> {
+    let mut f = OpenOptions::new().read(true).write(true).open(p)?;
+
+    // The first moov and the size of a free box directly after it.
+    let mut moov: Option<(u64, Vec<u8>)> = None;
+    let mut outer_free = 0;
+    let mut after_moov = false;
+    // Offset of the next top level box, the reader also visits the boxes inside them.
+    let mut next = 0;
+    read_boxes(&mut f, |b| {
+        if b.offset != next {
+            return Ok(());
+        }
+        next += b.size;
+        if after_moov && b.box_type == box_types::FREE {
+            outer_free = b.size;
+        }
+        after_moov = false;
+        if b.box_type == box_types::MOOV && moov.is_none() {
+            moov = Some((b.offset, b.buf.to_vec()));
+            after_moov = true;
+        }
+        Ok(())
+    })?;
+    let (start, moov) = moov.ok_or_else(|| invalid("No moov box."))?;
+    let available = moov.len() as u64 + outer_free;
+    let new_moov = new_moov(&moov, start, outer_free as usize, items)?;
+
+    if new_moov.len() as u64 == available {
+        f.seek(SeekFrom::Start(start))?;
+        f.write_all(&new_moov)?;
+        return Ok(());
+    }
+
+    // Write to the side and move into place so a failure doesn't leave half a file.
+    let mut tmp = p.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut out = io::BufWriter::new(File::create(&tmp)?);
+    f.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut f).take(start), &mut out)?;
+    out.write_all(&new_moov)?;
+    f.seek(SeekFrom::Start(start + available))?;
+    io::copy(&mut f, &mut out)?;
+    out.flush()?;
+    drop(out);
+    fs::rename(&tmp, p)?;
+    Ok(())
+}
+
+/// Set ilst items in an MPEG-4 file held in buf, returning the new file.
+pub fn update_items(buf: &[u8], items: &[IlstItem]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let top = boxes(buf, 0, buf.len())?;
+    let mi = top
+        .iter()
+        .position(|b| b.box_type == box_types::MOOV)
+        .ok_or_else(|| invalid("No moov box."))?;
+    let moov = &top[mi];
+    let outer_free = match top.get(mi + 1) {
+        Some(b) if b.box_type == box_types::FREE => b.size as usize,
+        _ => 0,
+    };
+    let new_moov = new_moov(moov.buf, moov.offset, outer_free, items)?;
+    Ok([
+        &buf[..start(moov)],
+        &new_moov,
+        &buf[end(moov) + outer_free..],
+    ]
+    .concat())
+}
+
+// The new moov box, with the items, for the old moov at moov_start in the file
+// and outer_free bytes of free box after it.
+// If it fits in that space, a free box after ilst fills the space left over.
+// If it doesn't, the media data after it will move, so its chunk offsets are updated.
+fn new_moov(
+    moov: &[u8],
+    moov_start: u64,
+    outer_free: usize,
+    items: &[IlstItem],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mb = read_box_header(&mut &moov[..], 0)?;
+    let ilst_path = [box_types::UDTA, box_types::META, box_types::ILST];
+
+    let ilst = build_ilst(moov, find(moov, &mb, &ilst_path)?.as_ref(), items)?;
+
+    // The space we can use without moving anything after moov.
+    let available = moov.len() + outer_free;
+
+    let needed = rebuild(moov, &mb, &ilst_path, &ilst)?.len();
+    let padding = if needed == available {
+        0
+    } else if needed + BOX_HEADER_SIZE <= available {
+        available - needed
+    } else {
+        DEFAULT_PADDING
+    };
+    let leaf = if padding > 0 {
+        [ilst, free_box(padding)].concat()
+    } else {
+        ilst
+    };
+    let mut new_moov = rebuild(moov, &mb, &ilst_path, &leaf)?;
+
+    let delta = new_moov.len() as i64 - available as i64;
+    if delta != 0 {
+        patch_offsets(&mut new_moov, moov_start, delta)?;
+    }
+    Ok(new_moov)
+}
+
+// The new ilst: the children of the old one, if any, with the items replaced or added.
+fn build_ilst(
+    buf: &[u8],
+    old: Option<&MP4Box>,
+    items: &[IlstItem],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut kids = Vec::new();
+    if let Some(o) = old {
+        for c in children(buf, o)? {
+            kids.push(c.buf.to_vec());
+        }
+    }
+
+    for it in items {
+        let b = it.to_box();
+        match kids.iter().position(|k| it.replaces(k)) {
+            Some(i) => {
+                kids[i] = b;
+                // Drop any duplicates.
+                let mut j = i + 1;
+                while j < kids.len() {
+                    if it.replaces(&kids[j]) {
+                        kids.remove(j);
+                    } else {
+                        j += 1;
+                    }
+                }
+            }
+            None => kids.push(b),
+        }
+    }
+
+    Ok(make_box(&box_types::ILST, &kids.concat()))
+}
+
+// Rebuild the container b with the box at path replaced by leaf, creating any
+// boxes missing from the path. A free box directly after the replaced box is dropped,
+// leaf is expected to bring its own padding.
+fn rebuild(
+    buf: &[u8],
+    b: &MP4Box,
+    path: &[BoxType],
+    leaf: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Keep the version and flags of full boxes, and the data of special containers.
+    let size_type = if b.box_type.spec().full {
+        b.header_size - VERSION_FLAG_SIZE
+    } else {
+        b.header_size
+    };
+    let mut content = buf[start(b) + size_type..content_start(b)].to_vec();
+    let mut found = false;
+    let mut drop_free = false;
+    for c in children(buf, b)? {
+        let bt = &c.box_type;
+        if drop_free {
+            drop_free = false;
+            if *bt == box_types::FREE {
+                continue;
+            }
+        }
+        if !found && *bt == path[0] {
+            found = true;
+            if path.len() == 1 {
+                content.extend_from_slice(leaf);
+                drop_free = true;
+            } else {
+                content.extend(rebuild(buf, &c, &path[1..], leaf)?);
+            }
+        } else {
+            content.extend_from_slice(c.buf);
+        }
+    }
+    if !found {
+        content.extend(new_boxes(path, leaf));
+    }
+    Ok(make_box(&b.box_type, &content))
+}
+
+// Create the boxes for path around leaf.
+fn new_boxes(path: &[BoxType], leaf: &[u8]) -> Vec<u8> {
+    if path.len() == 1 {
+        return leaf.to_vec();
+    }
+    let inner = new_boxes(&path[1..], leaf);
+    if path[0] == box_types::META {
+        // meta needs a handler to say what kind of metadata it holds.
+        let hdlr = full_box(
+            &box_types::HDLR,
+            0,
+            &[&[0; 4][..], b"mdir", b"appl", &[0; 9]].concat(),
+        );
+        full_box(&box_types::META, 0, &[hdlr, inner].concat())
+    } else {
+        make_box(&path[0], &inner)
+    }
+}
+
+// Add delta to every chunk offset, in the stco and co64 boxes of moov,
+// that points past the start of the old moov box.
+fn patch_offsets(moov: &mut [u8], moov_start: u64, delta: i64) -> Result<(), Box<dyn Error>> {
+    // Find the tables, as (entry width, start, end), then patch them.
+    let mut tables = Vec::new();
+    let mut stack = boxes(moov, 0, moov.len())?;
+    while let Some(b) = stack.pop() {
+        if b.box_type == box_types::STCO || b.box_type == box_types::CO64 {
+            let width = if b.box_type == box_types::STCO { 4 } else { 8 };
+            tables.push((width, content_start(&b), end(&b)));
+        } else if b.box_type.spec().container == ContainerType::Container {
+            stack.extend(children(moov, &b)?);
+        }
+    }
+
+    for (width, cs, end) in tables {
+        if cs + 4 > end {
+            return Err(invalid("Chunk offset table is truncated."));
+        }
+        let count = u32::from_be_bytes(moov[cs..cs + 4].try_into()?) as usize;
+        for i in 0..count {
+            let o = cs + 4 + i * width;
+            if o + width > end {
+                return Err(invalid("Chunk offset table is truncated."));
+            }
+            let e = &mut moov[o..o + width];
+            let v = if width == 4 {
+                u32::from_be_bytes((&*e).try_into()?) as u64
+            } else {
+                u64::from_be_bytes((&*e).try_into()?)
+            };
+            if v < moov_start {
+                continue;
+            }
+            let v = (v as i64 + delta) as u64;
+            if width == 4 {
+                let v: u32 = v
+                    .try_into()
+                    .map_err(|_| invalid("Chunk offset no longer fits in stco."))?;
+                e.copy_from_slice(&v.to_be_bytes());
+            } else {
+                e.copy_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Find the box at path under parent.
+fn find<'a>(
+    buf: &'a [u8],
+    parent: &MP4Box,
+    path: &[BoxType],
+) -> Result<Option<MP4Box<'a>>, Box<dyn Error>> {
+    for c in children(buf, parent)? {
+        if c.box_type == path[0] {
+            return if path.len() == 1 {
+                Ok(Some(c))
+            } else {
+                find(buf, &c, &path[1..])
+            };
+        }
+    }
+    Ok(None)
+}
+
+// The boxes in buf[start..end], with offsets from the start of buf.
+fn boxes(buf: &[u8], start: usize, end: usize) -> Result<Vec<MP4Box<'_>>, Box<dyn Error>> {
+    let mut bs = Vec::new();
+    let mut s = start;
+    while s < end {
+        let b = read_box_header(&mut &buf[s..end], s as u64)?;
+        s += b.size as usize;
+        bs.push(b);
+    }
+    Ok(bs)
+}
+
+fn children<'a>(buf: &'a [u8], b: &MP4Box) -> Result<Vec<MP4Box<'a>>, Box<dyn Error>> {
+    boxes(buf, content_start(b), end(b))
+}
+
+fn start(b: &MP4Box) -> usize {
+    b.offset as usize
+}
+
+fn end(b: &MP4Box) -> usize {
+    (b.offset + b.size) as usize
+}
+
+// Where the children or data start, past the version and flags of full boxes
+// and the data of special containers.
+fn content_start(b: &MP4Box) -> usize {
+    let skip = match b.box_type.spec().container {
+        ContainerType::Special(n) => n,
+        _ => 0,
+    };
+    start(b) + b.header_size + skip
+}
+
+fn make_box(bt: &BoxType, content: &[u8]) -> Vec<u8> {
+    let size = (BOX_HEADER_SIZE + content.len()) as u32;
+    [
+        &size.to_be_bytes()[..],
+        &bt.spec().bt_id.to_be_bytes(),
+        content,
+    ]
+    .concat()
+}
+
+fn full_box(bt: &BoxType, flags: u32, content: &[u8]) -> Vec<u8> {
+    make_box(bt, &[&flags.to_be_bytes()[..], content].concat())
+}
+
+// A data box: the type flag, a 0 locale, and the value.
+fn data_box(flag: u32, value: &[u8]) -> Vec<u8> {
+    full_box(&box_types::DATA, flag, &[&[0; 4][..], value].concat())
+}
+
+// A free box that's size bytes in all.
+fn free_box(size: usize) -> Vec<u8> {
+    make_box(&box_types::FREE, &vec![0; size - BOX_HEADER_SIZE])
+}
+
+fn invalid(msg: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::Decoder;
+    use crate::mpeg4::Mpeg4;
+    use crate::track;
+
+    const TEST_FILE: &str = "test_audio/16 The End.m4a";
+
+    fn read(buf: &[u8]) -> track::Track {
+        Mpeg4::default()
+            .get_track(io::Cursor::new(buf))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn first_chunk_offset(buf: &[u8]) -> usize {
+        let top = boxes(buf, 0, buf.len()).unwrap();
+        let moov = top.iter().find(|b| b.box_type == box_types::MOOV).unwrap();
+        let path = [
+            box_types::TRAK,
+            box_types::MDIA,
+            box_types::MINF,
+            box_types::STBL,
+            box_types::STCO,
+        ];
+        let stco = find(buf, moov, &path).unwrap().unwrap();
+        let o = content_start(&stco) + 4;
+        u32::from_be_bytes(buf[o..o + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn test_update_in_padding() {
+        let buf = fs::read(TEST_FILE).unwrap();
+        let items = [
+            IlstItem::title("New Title"),
+            IlstItem::Track(3, 12),
+            IlstItem::freeform("Lineage", "SB MASTER > FLAC"),
+        ];
+        let out = update_items(&buf, &items).unwrap();
+
+        // Fits in the free space, so the media doesn't move.
+        assert_eq!(out.len(), buf.len());
+        assert_eq!(first_chunk_offset(&out), first_chunk_offset(&buf));
+
+        let tk = read(&out);
+        assert_eq!(tk.title.as_deref(), Some("New Title"));
+        assert_eq!(tk.track_number, Some(3));
+        assert_eq!(tk.track_total, Some(12));
+        assert_eq!(tk.artist, read(&buf).artist);
+
+        // Writing the same item again replaces it.
+        let again = update_items(&out, &[IlstItem::freeform("Lineage", "WAVE")]).unwrap();
+        assert_eq!(again.len(), buf.len());
+        let top = boxes(&again, 0, again.len()).unwrap();
+        let ilst = find(
+            &again,
+            &top[1],
+            &[box_types::UDTA, box_types::META, box_types::ILST],
+        )
+        .unwrap()
+        .unwrap();
+        let lineage: Vec<_> = children(&again, &ilst)
+            .unwrap()
+            .iter()
+            .filter_map(|c| freeform_key(c.buf))
+            .filter(|(_, n)| n == "Lineage")
+            .collect();
+        assert_eq!(lineage.len(), 1);
+    }
+
+    #[test]
+    fn test_write_in_place() {
+        let buf = fs::read(TEST_FILE).unwrap();
+        let p = std::env::temp_dir().join(format!("lt-write-{}.m4a", std::process::id()));
+        fs::write(&p, &buf).unwrap();
+        write_items(&p, &[IlstItem::title("New Title")]).unwrap();
+        let out = fs::read(&p).unwrap();
+
+        // Same as writing to the buffer, and nothing after moov and its free space changed.
+        assert_eq!(
+            out,
+            update_items(&buf, &[IlstItem::title("New Title")]).unwrap()
+        );
+        let top = boxes(&buf, 0, buf.len()).unwrap();
+        let mdat = top.iter().find(|b| b.box_type == box_types::MDAT).unwrap();
+        assert_eq!(&out[start(mdat)..], &buf[start(mdat)..]);
+
+        // Growing moov rewrites the file.
+        let title = "x".repeat(40000);
+        write_items(&p, &[IlstItem::title(&title)]).unwrap();
+        let grown = fs::read(&p).unwrap();
+        fs::remove_file(&p).unwrap();
+        assert_eq!(
+            grown,
+            update_items(&out, &[IlstItem::title(&title)]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_moves_media() {
+        let buf = fs::read(TEST_FILE).unwrap();
+        let title = "x".repeat(40000);
+        let out = update_items(&buf, &[IlstItem::title(&title)]).unwrap();
+
+        assert!(out.len() > buf.len());
+        assert_eq!(read(&out).title, Some(title));
+
+        // The chunk offsets follow the media.
+        let old = first_chunk_offset(&buf);
+        let new = first_chunk_offset(&out);
+        assert_eq!(new - old, out.len() - buf.len());
+        assert_eq!(&out[new..new + 64], &buf[old..old + 64]);
+    }
+}
@@ -0,0 +1,506 @@
+//! RIFF chunks, the structure of WAV files, and the metadata chunks found in them.
+//!
+//! A RIFF file is a "RIFF" chunk holding a form type, e.g. "WAVE", and a list of chunks.
+//! Each chunk is a four character id, a little endian 32 bit size and the data,
+//! padded to an even size. Besides the fmt and data chunks, recorders write:
+//! - LIST chunks of type INFO, whose sub-chunks are text, e.g. INAM for the name.
+//! - bext, the Broadcast Wave extension: who made the recording, when, and
+//!   the time of day of its first sample as a count of samples since midnight.
+//! - iXML, an XML document with production details like the scene and take.
+//!
+//! Files over 4 GiB, which 32 bit sizes can't describe, are RF64 (or BW64) rather than
+//! RIFF files. Their first chunk is ds64, which has the 64 bit sizes of the file and the
+//! data chunk, and of any other chunk whose size is 0xFFFFFFFF.
+//!
+//! References:
+//!
+//! [https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/WAVE.html](https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/WAVE.html)
+//!
+//! [https://tech.ebu.ch/docs/tech/tech3285.pdf](https://tech.ebu.ch/docs/tech/tech3285.pdf)
+//!
+//! [http://www.gallery.co.uk/ixml/](http://www.gallery.co.uk/ixml/)
+//!
+//! [https://tech.ebu.ch/docs/tech/tech3306v1_1.pdf](https://tech.ebu.ch/docs/tech/tech3306v1_1.pdf)
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Id of the chunk that holds the whole file.
+pub const RIFF: &str = "RIFF";
+/// Id of the chunk that holds the whole file, when it's over 4 GiB.
+pub const RF64: &str = "RF64";
+/// The ITU's name for RF64.
+pub const BW64: &str = "BW64";
+/// Id of the chunk with the 64 bit sizes of an RF64 file.
+pub const DS64: &str = "ds64";
+/// Id of the chunk with the audio.
+pub const DATA: &str = "data";
+/// Id of a list chunk, whose data is a list type and sub-chunks.
+pub const LIST: &str = "LIST";
+/// List type of the text metadata.
+pub const INFO: &str = "INFO";
+/// Id of the Broadcast Wave extension chunk.
+pub const BEXT: &str = "bext";
+/// Id of the iXML chunk.
+pub const IXML: &str = "iXML";
+// Size of a chunk's id and size.
+const CHUNK_HEADER_SIZE: u64 = 8;
+// Size in a chunk header that means the size is in the ds64 chunk.
+const SIZE_IN_DS64: u32 = 0xFFFF_FFFF;
+// Size of the fixed fields of the ds64 chunk, before the table.
+const DS64_SIZE: usize = 28;
+// Size of the fixed fields of the bext chunk, before the coding history.
+const BEXT_SIZE: usize = 602;
+
+/// Names of the common INFO ids.
+pub const INFO_NAMES: [(&str, &str); 14] = [
+    ("IARL", "Archival Location"),
+    ("IART", "Artist"),
+    ("ICMT", "Comment"),
+    ("ICOP", "Copyright"),
+    ("ICRD", "Creation Date"),
+    ("IENG", "Engineer"),
+    ("IGNR", "Genre"),
+    ("IKEY", "Keywords"),
+    ("INAM", "Name"),
+    ("IPRD", "Product"),
+    ("ISFT", "Software"),
+    ("ISRC", "Source"),
+    ("ITCH", "Technician"),
+    ("ITRK", "Track"),
+];
+
+/// iXML elements shown as fields, found anywhere in the document.
+pub const IXML_FIELDS: [&str; 8] = [
+    "PROJECT",
+    "SCENE",
+    "TAKE",
+    "TAPE",
+    "CIRCLED",
+    "NOTE",
+    "FILE_UID",
+    "TIMECODE_RATE",
+];
+
+/// Where a chunk is in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: String,
+    /// File offset of the chunk's header.
+    pub offset: u64,
+    /// Size of the data, not including the header or padding.
+    pub size: u64,
+}
+
+impl Chunk {
+    /// File offset of the chunk's data.
+    pub fn data_offset(&self) -> u64 {
+        self.offset + CHUNK_HEADER_SIZE
+    }
+}
+
+/// The fields of a bext chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bext {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// yyyy-mm-dd
+    pub origination_date: String,
+    /// hh:mm:ss
+    pub origination_time: String,
+    /// Samples from midnight to the first sample of the recording.
+    pub time_reference: u64,
+    pub version: u16,
+    /// SMPTE UMID, as hex, empty if it's all zeros.
+    pub umid: String,
+    /// One line for each process the audio went through, e.g. "A=PCM,F=48000,W=24,M=stereo".
+    pub coding_history: String,
+}
+
+impl Bext {
+    /// Parse the data of a bext chunk. Returns None if it's too short.
+    pub fn parse(b: &[u8]) -> Option<Bext> {
+        if b.len() < BEXT_SIZE {
+            return None;
+        }
+        let umid = &b[348..412];
+        Some(Bext {
+            description: text(&b[0..256]),
+            originator: text(&b[256..288]),
+            originator_reference: text(&b[288..320]),
+            origination_date: text(&b[320..330]),
+            origination_time: text(&b[330..338]),
+            time_reference: le_u64(&b[338..346]),
+            version: u16::from_le_bytes([b[346], b[347]]),
+            umid: if umid.iter().any(|&c| c != 0) {
+                umid.iter().map(|c| format!("{:02x}", c)).collect()
+            } else {
+                String::new()
+            },
+            coding_history: text(&b[BEXT_SIZE..]).trim().to_string(),
+        })
+    }
+
+    /// Time of day of the first sample, from the time reference.
+    pub fn time_of_day(&self, sample_rate: u32) -> Option<Duration> {
+        if sample_rate == 0 {
+            return None;
+        }
+        let sr = u64::from(sample_rate);
+        Some(Duration::from_secs(self.time_reference / sr))
+            .map(|d| d + Duration::from_nanos(self.time_reference % sr * 1_000_000_000 / sr))
+    }
+
+    /// When the first sample was recorded: the origination date at the time of day
+    /// of the time reference. Recorders that don't set the time reference get the
+    /// origination time. None if the date can't be read.
+    pub fn timestamp(&self, sample_rate: u32) -> Option<NaiveDateTime> {
+        // Some recorders separate the fields with something other than "-".
+        let date: String = self
+            .origination_date
+            .chars()
+            .map(|c| if c.is_ascii_digit() { c } else { '-' })
+            .collect();
+        let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok()?;
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+        match self.time_of_day(sample_rate) {
+            Some(d) if self.time_reference > 0 => {
+                Some(midnight + chrono::Duration::from_std(d).ok()?)
+            }
+            _ => {
+                let time: String = self
+                    .origination_time
+                    .chars()
+                    .map(|c| if c.is_ascii_digit() { c } else { ':' })
+                    .collect();
+                let t = NaiveTime::parse_from_str(&time, "%H:%M:%S").ok()?;
+                Some(date.and_time(t))
+            }
+        }
+    }
+}
+
+/// The fields of a ds64 chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ds64 {
+    /// Size of the RF64 chunk, that is the file less 8 bytes.
+    pub riff_size: u64,
+    pub data_size: u64,
+    /// Samples in the data chunk, 0 if the file doesn't say.
+    pub sample_count: u64,
+    /// Sizes of other chunks with 0xFFFFFFFF in their header.
+    pub table: Vec<(String, u64)>,
+}
+
+impl Ds64 {
+    /// Parse the data of a ds64 chunk. Returns None if it's too short.
+    pub fn parse(b: &[u8]) -> Option<Ds64> {
+        if b.len() < DS64_SIZE {
+            return None;
+        }
+        let n = le_u32(&b[24..28]) as usize;
+        Some(Ds64 {
+            riff_size: le_u64(&b[0..8]),
+            data_size: le_u64(&b[8..16]),
+            sample_count: le_u64(&b[16..24]),
+            table: b[DS64_SIZE..]
+                .chunks_exact(12)
+                .take(n)
+                .map(|e| {
+                    (
+                        String::from_utf8_lossy(&e[0..4]).to_string(),
+                        le_u64(&e[4..12]),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// The 64 bit size of the chunk with this id.
+    pub fn size(&self, id: &str) -> Option<u64> {
+        if id == DATA {
+            return Some(self.data_size);
+        }
+        self.table.iter().find(|(i, _)| i == id).map(|(_, s)| *s)
+    }
+}
+
+/// Is this the id of a chunk that holds a whole file?
+pub fn is_form(id: &[u8]) -> bool {
+    [RIFF, RF64, BW64].iter().any(|f| f.as_bytes() == id)
+}
+
+/// An iXML document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IXml {
+    pub xml: String,
+}
+
+impl IXml {
+    /// The text of the first element named name, without looking at the structure
+    /// around it. None if there isn't one or it's empty.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        let open = format!("<{}>", name);
+        let close = format!("</{}>", name);
+        let start = self.xml.find(&open)? + open.len();
+        let end = start + self.xml[start..].find(&close)?;
+        Some(self.xml[start..end].trim()).filter(|v| !v.is_empty())
+    }
+
+    /// The values of `IXML_FIELDS` that the document has.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        IXML_FIELDS
+            .iter()
+            .filter_map(|f| Some((*f, self.value(f)?)))
+            .collect()
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] at offset {}", self.id, self.size, self.offset)
+    }
+}
+
+/// Read the form type, e.g. "WAVE", and the chunks of a RIFF, RF64 or BW64 file.
+/// LIST chunks are listed once, their sub-chunks aren't.
+/// Sizes that are in the ds64 chunk are taken from there.
+pub fn read_chunks(mut r: impl Read + Seek) -> Result<(String, Vec<Chunk>), Box<dyn Error>> {
+    let mut header = [0; 12];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header)?;
+    if !is_form(&header[0..4]) {
+        return Err("Not a RIFF file.".into());
+    }
+    let form = String::from_utf8_lossy(&header[8..12]).to_string();
+    let len = r.seek(SeekFrom::End(0))?;
+    let mut chunks = Vec::new();
+    let mut offset = header.len() as u64;
+    let mut b = [0; CHUNK_HEADER_SIZE as usize];
+    let mut ds64: Option<Ds64> = None;
+    while offset + CHUNK_HEADER_SIZE <= len {
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut b)?;
+        let mut c = Chunk {
+            id: String::from_utf8_lossy(&b[0..4]).to_string(),
+            offset,
+            size: u64::from(le_u32(&b[4..8])),
+        };
+        if le_u32(&b[4..8]) == SIZE_IN_DS64 {
+            match ds64.as_ref().and_then(|d| d.size(&c.id)) {
+                Some(size) => c.size = size,
+                None => return Err(format!("No ds64 size for the {} chunk.", c.id).into()),
+            }
+        } else if c.id == DS64 && chunks.is_empty() {
+            ds64 = Ds64::parse(&read_data(&mut r, &c)?);
+        }
+        // Chunks are padded to an even size.
+        offset = c
+            .data_offset()
+            .checked_add(c.size)
+            .and_then(|o| o.checked_add(c.size & 1))
+            .ok_or_else(|| format!("The {} chunk is too big: {} bytes.", c.id, c.size))?;
+        chunks.push(c);
+    }
+    Ok((form, chunks))
+}
+
+/// Read the data of a chunk.
+pub fn read_data(mut r: impl Read + Seek, c: &Chunk) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut b = Vec::new();
+    r.seek(SeekFrom::Start(c.data_offset()))?;
+    r.take(c.size).read_to_end(&mut b)?;
+    Ok(b)
+}
+
+/// The type of a LIST chunk's data, e.g. "INFO".
+pub fn list_type(b: &[u8]) -> &[u8] {
+    b.get(0..4).unwrap_or_default()
+}
+
+/// Parse the (id, text) sub-chunks of a LIST INFO chunk's data.
+pub fn parse_info(b: &[u8]) -> Vec<(String, String)> {
+    let mut items = Vec::new();
+    let mut b = b.get(4..).unwrap_or_default();
+    while b.len() >= CHUNK_HEADER_SIZE as usize {
+        let size = le_u32(&b[4..8]) as usize;
+        let end = (CHUNK_HEADER_SIZE as usize + size).min(b.len());
+        let id = String::from_utf8_lossy(&b[0..4]).to_string();
+        let value = text(&b[CHUNK_HEADER_SIZE as usize..end]);
+        if !value.is_empty() {
+            items.push((id, value));
+        }
+        b = b.get(end + (size & 1)..).unwrap_or_default();
+    }
+    items
+}
+
+/// Name of an INFO id, or the id if it isn't a common one.
+pub fn info_name(id: &str) -> &str {
+    INFO_NAMES
+        .iter()
+        .find(|(i, _)| *i == id)
+        .map_or(id, |(_, n)| n)
+}
+
+// Text padded with zeros, which is ASCII or UTF-8 in newer files.
+fn text(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).trim_end().to_string()
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le_u64(b: &[u8]) -> u64 {
+    u64::from(le_u32(&b[0..4])) | u64::from(le_u32(&b[4..8])) << 32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut b = id.as_bytes().to_vec();
+        b.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        b.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            b.push(0);
+        }
+        b
+    }
+
+    fn bext(date: &str, time: &str, time_reference: u64) -> Vec<u8> {
+        let mut b = vec![0; BEXT_SIZE];
+        b[..11].copy_from_slice(b"Field recor");
+        b[256..262].copy_from_slice(b"Zoom F");
+        b[320..320 + date.len()].copy_from_slice(date.as_bytes());
+        b[330..330 + time.len()].copy_from_slice(time.as_bytes());
+        b[338..346].copy_from_slice(&time_reference.to_le_bytes());
+        b[346] = 1;
+        b.extend_from_slice(b"A=PCM,F=48000,W=24,M=stereo\r\n");
+        b
+    }
+
+    #[test]
+    fn test_chunks() {
+        let info = [
+            &b"INFO"[..],
+            &chunk("INAM", b"Dawn Chorus\0"),
+            &chunk("IART", b"Me\0"),
+            &chunk("ICMT", b"odd\0"),
+        ]
+        .concat();
+        let body = [
+            &b"WAVE"[..],
+            &chunk("fmt ", &[0; 16]),
+            &chunk(LIST, &info),
+            &chunk(
+                IXML,
+                b"<BWFXML><PROJECT>Birds</PROJECT><SCENE>1</SCENE><NOTE></NOTE></BWFXML>",
+            ),
+            &chunk("data", &[0; 7]),
+        ]
+        .concat();
+        let file = [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat();
+
+        let (form, chunks) = read_chunks(Cursor::new(&file)).unwrap();
+        assert_eq!(form, "WAVE");
+        let ids: Vec<&str> = chunks.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["fmt ", "LIST", "iXML", "data"]);
+        assert_eq!(chunks[3].size, 7);
+
+        let b = read_data(Cursor::new(&file), &chunks[1]).unwrap();
+        assert_eq!(list_type(&b), INFO.as_bytes());
+        let items = parse_info(&b);
+        assert_eq!(
+            items,
+            vec![
+                ("INAM".to_string(), "Dawn Chorus".to_string()),
+                ("IART".to_string(), "Me".to_string()),
+                ("ICMT".to_string(), "odd".to_string()),
+            ]
+        );
+        assert_eq!(info_name("INAM"), "Name");
+        assert_eq!(info_name("IXYZ"), "IXYZ");
+
+        let ixml = IXml {
+            xml: String::from_utf8(read_data(Cursor::new(&file), &chunks[2]).unwrap()).unwrap(),
+        };
+        assert_eq!(ixml.fields(), vec![("PROJECT", "Birds"), ("SCENE", "1")]);
+        assert!(read_chunks(Cursor::new(&file[4..])).is_err());
+    }
+
+    #[test]
+    fn test_rf64() {
+        let mut ds64 = Vec::new();
+        for n in &[5_000_000_000u64, 4_999_999_000, 0] {
+            ds64.extend_from_slice(&n.to_le_bytes());
+        }
+        ds64.extend_from_slice(&1u32.to_le_bytes());
+        ds64.extend_from_slice(b"JUNK");
+        ds64.extend_from_slice(&4u64.to_le_bytes());
+        let d = Ds64::parse(&ds64).unwrap();
+        assert_eq!(d.size(DATA), Some(4_999_999_000));
+        assert_eq!(d.size("JUNK"), Some(4));
+        assert_eq!(d.size("fmt "), None);
+
+        let mut file = [&b"RF64"[..], &[0xff; 4], b"WAVE", &chunk(DS64, &ds64)].concat();
+        file.extend_from_slice(b"JUNK\xff\xff\xff\xff\0\0\0\0");
+        file.extend_from_slice(b"data\xff\xff\xff\xff");
+        let (form, chunks) = read_chunks(Cursor::new(&file)).unwrap();
+        assert_eq!(form, "WAVE");
+        let sizes: Vec<(&str, u64)> = chunks.iter().map(|c| (c.id.as_str(), c.size)).collect();
+        assert_eq!(sizes, vec![(DS64, 40), ("JUNK", 4), (DATA, 4_999_999_000)]);
+
+        // Without a ds64 chunk the sizes are missing.
+        let file = [&b"BW64"[..], &[0xff; 4], b"WAVE", b"data\xff\xff\xff\xff"].concat();
+        assert!(read_chunks(Cursor::new(&file)).is_err());
+
+        // A ds64 size that runs past the largest offset.
+        let mut ds64 = vec![0; 8];
+        ds64.extend_from_slice(&u64::MAX.to_le_bytes());
+        ds64.extend_from_slice(&[0; 12]);
+        let file = [
+            &b"RF64"[..],
+            &[0xff; 4],
+            b"WAVE",
+            &chunk(DS64, &ds64),
+            b"data\xff\xff\xff\xff",
+        ]
+        .concat();
+        assert!(read_chunks(Cursor::new(&file)).is_err());
+    }
+
+    #[test]
+    fn test_bext() {
+        // 10:30:00.5 at 48kHz.
+        let b = Bext::parse(&bext("2021-06-01", "10:29:59", 1_814_424_000)).unwrap();
+        assert_eq!(b.description, "Field recor");
+        assert_eq!(b.originator, "Zoom F");
+        assert_eq!(b.version, 1);
+        assert_eq!(b.umid, "");
+        assert_eq!(b.coding_history, "A=PCM,F=48000,W=24,M=stereo");
+        assert_eq!(
+            b.time_of_day(48_000),
+            Some(Duration::from_millis(37_800_500))
+        );
+        assert_eq!(
+            b.timestamp(48_000).unwrap().to_string(),
+            "2021-06-01 10:30:00.500"
+        );
+
+        // No time reference, and a date with other separators.
+        let b = Bext::parse(&bext("2021:06:01", "10-29-59", 0)).unwrap();
+        assert_eq!(
+            b.timestamp(48_000).unwrap().to_string(),
+            "2021-06-01 10:29:59"
+        );
+        assert_eq!(Bext::parse(&[0; 100]), None);
+    }
+}
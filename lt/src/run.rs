@@ -9,7 +9,7 @@ extern crate structopt;
 use structopt::StructOpt;
 
 use crate::cmd::{
-    parse_app, parse_interactive, AppCmds, FilePath, ICmds, InteractiveCommands, ParseResult,
+    parse_app, parse_interactive, AppCmds, ICmds, InteractiveCommands, ListPath, ParseResult,
     RootSubcommand,
 };
 use crate::completion::PathCompleter;
@@ -53,7 +53,8 @@ pub fn run(c: Config) -> Result<(), Box<dyn Error>> {
                         history_path: None,
                         config: "".to_string(),
                         subcmd: RootSubcommand::InteractiveSubcommand(InteractiveCommands::List(
-                            FilePath {
+                            ListPath {
+                                write_titles: false,
                                 path: vec![p.as_path().to_str().unwrap().to_string()],
                             },
                         )),
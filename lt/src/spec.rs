@@ -0,0 +1,656 @@
+//! Album tag specifications, the `album.yaml` and `tracklist.yaml` files used to
+//! describe the tags a folder of tracks should have.
+//!
+//! An `album.yaml` looks like:
+//! ```yaml
+//! Album:
+//!   ALBUM: Album Title
+//!   ARTIST: Artist Name
+//! Control:
+//!   UseCapitalKeys: "true"
+//!   UseTitleCaps: "false"
+//! Tracks:
+//!   01 First Track.flac:
+//!     TITLE: First Track
+//!     TRACKNUMBER: "1"
+//! ```
+//! `Album` values are applied to every track, `Tracks` values to the named file only
+//! and take precedence over the `Album` values. Keys are Vorbis comment names.
+//! A `tracklist.yaml` uses the same layout, usually with only an `album` section.
+//!
+//! A template can be made from the tags already on a folder of tracks with `spec_from_album`.
+use crate::album::Album;
+use crate::file;
+use crate::file::FileFormat;
+use crate::flac;
+use crate::track::{FormatMetadata, ID3Metadata, Track};
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Default name for an album specification.
+pub const ALBUM_SPEC_FILE: &str = "album.yaml";
+/// Default name for a track list specification.
+pub const TRACKLIST_SPEC_FILE: &str = "tracklist.yaml";
+
+const ALBUM_SECTION: &str = "Album";
+const CONTROL_SECTION: &str = "Control";
+const TRACKS_SECTION: &str = "Tracks";
+
+/// Write keys in upper case.
+pub const USE_CAPITAL_KEYS: &str = "UseCapitalKeys";
+/// Capitalize each word of titles.
+pub const USE_TITLE_CAPS: &str = "UseTitleCaps";
+
+// Values that get title caps.
+const TITLE_KEYS: [&str; 2] = ["TITLE", "ALBUM"];
+
+// Spellings we've seen in hand written specs, and the Vorbis key they mean.
+const KEY_ALIASES: [(&str, &str); 5] = [
+    ("ALBUM ARTIST", "ALBUMARTIST"),
+    ("ALBUM_ARTIST", "ALBUMARTIST"),
+    ("ALBUMARIST", "ALBUMARTIST"),
+    ("TRACKUMBER", "TRACKNUMBER"),
+    ("TRACK", "TRACKNUMBER"),
+];
+
+/// The contents of an album specification.
+/// Entries are kept in the order they appear in the file.
+#[derive(Debug, Default)]
+pub struct AlbumSpec {
+    /// Tags for every track.
+    pub album: Vec<(String, String)>,
+    /// Options controlling how the tags are written.
+    pub control: Vec<(String, String)>,
+    /// Tags for each track, by file name.
+    pub tracks: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl AlbumSpec {
+    /// True if the control option is set to "true".
+    pub fn control_flag(&self, name: &str) -> bool {
+        self.control
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case(name) && v.trim().eq_ignore_ascii_case("true"))
+    }
+
+    /// Add the values of another spec to this one, the other's values win when both have a key.
+    pub fn merge(&mut self, other: AlbumSpec) {
+        merge_pairs(&mut self.album, other.album);
+        merge_pairs(&mut self.control, other.control);
+        for (f, tags) in other.tracks {
+            match self.tracks.iter_mut().find(|(n, _)| *n == f) {
+                Some((_, t)) => merge_pairs(t, tags),
+                None => self.tracks.push((f, tags)),
+            }
+        }
+    }
+
+    /// The tags for a track as Vorbis comments: album values overridden by track values,
+    /// with the control options applied.
+    pub fn comments_for(&self, track_tags: &[(String, String)]) -> Vec<(String, String)> {
+        let capital_keys = self.control_flag(USE_CAPITAL_KEYS);
+        let title_caps = self.control_flag(USE_TITLE_CAPS);
+
+        let mut cs = Vec::new();
+        for (k, v) in self.album.iter().chain(track_tags.iter()) {
+            // Misspelled keys are always fixed, otherwise keep the spelling from the spec
+            // unless capital keys were asked for.
+            let vk = vorbis_key(k);
+            let k = if capital_keys || vk != k.trim().to_uppercase() {
+                vk
+            } else {
+                k.trim().to_string()
+            };
+            let v = if title_caps && TITLE_KEYS.iter().any(|t| k.eq_ignore_ascii_case(t)) {
+                title_case(v)
+            } else {
+                v.clone()
+            };
+            cs.retain(|(ck, _): &(String, String)| !ck.eq_ignore_ascii_case(&k));
+            cs.push((k, v));
+        }
+        cs
+    }
+
+    /// The specification as YAML, in the same layout that's read.
+    pub fn to_yaml(&self) -> Result<String, Box<dyn Error>> {
+        let mut tracks = Mapping::new();
+        for (f, tags) in &self.tracks {
+            tracks.insert(Value::from(f.as_str()), mapping(tags));
+        }
+        let mut doc = Mapping::new();
+        doc.insert(Value::from(ALBUM_SECTION), mapping(&self.album));
+        doc.insert(Value::from(CONTROL_SECTION), mapping(&self.control));
+        doc.insert(Value::from(TRACKS_SECTION), Value::Mapping(tracks));
+
+        let s = serde_yaml::to_string(&doc)?;
+        Ok(format!("{}\n", s.trim_start_matches("---\n").trim_end()))
+    }
+}
+
+fn mapping(ps: &[(String, String)]) -> Value {
+    let mut m = Mapping::new();
+    for (k, v) in ps {
+        m.insert(Value::from(k.as_str()), Value::from(v.as_str()));
+    }
+    Value::Mapping(m)
+}
+
+fn merge_pairs(to: &mut Vec<(String, String)>, from: Vec<(String, String)>) {
+    for (k, v) in from {
+        match to.iter_mut().find(|(tk, _)| tk.eq_ignore_ascii_case(&k)) {
+            Some((_, tv)) => *tv = v,
+            None => to.push((k, v)),
+        }
+    }
+}
+
+/// Turn a spec key into a Vorbis comment key: upper case with known misspellings
+/// and CamelCase names (e.g. "AlbumArtist") fixed.
+pub fn vorbis_key(k: &str) -> String {
+    let k = k.trim().to_uppercase();
+    for (alias, key) in &KEY_ALIASES {
+        if k == *alias {
+            return key.to_string();
+        }
+    }
+    k
+}
+
+/// Capitalize the first letter of each word, lower casing the rest.
+pub fn title_case(s: &str) -> String {
+    let mut t = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            word_start = true;
+            t.push(c);
+        } else if word_start && c.is_alphanumeric() {
+            word_start = false;
+            t.extend(c.to_uppercase());
+        } else {
+            t.extend(c.to_lowercase());
+        }
+    }
+    t
+}
+
+/// Read a specification file.
+pub fn read_spec(p: &Path) -> Result<AlbumSpec, Box<dyn Error>> {
+    spec_from_str(&fs::read_to_string(p)?)
+}
+
+/// Parse a specification from its YAML text.
+pub fn spec_from_str(s: &str) -> Result<AlbumSpec, Box<dyn Error>> {
+    let doc: Value = serde_yaml::from_str(s)?;
+    let mut spec = AlbumSpec {
+        ..Default::default()
+    };
+
+    if let Value::Mapping(m) = doc {
+        for (k, v) in m {
+            let k = scalar_string(&k).unwrap_or_default();
+            match k {
+                k if k.eq_ignore_ascii_case(ALBUM_SECTION) => spec.album = pairs(&v),
+                k if k.eq_ignore_ascii_case(CONTROL_SECTION) => spec.control = pairs(&v),
+                k if k.eq_ignore_ascii_case(TRACKS_SECTION) => {
+                    if let Value::Mapping(ts) = v {
+                        for (f, tags) in ts {
+                            if let Some(f) = scalar_string(&f) {
+                                spec.tracks.push((f, pairs(&tags)));
+                            }
+                        }
+                    }
+                }
+                _ => eprintln!("Ignoring unknown specification section: {}", k),
+            }
+        }
+    }
+    Ok(spec)
+}
+
+/// Read the specifications for a directory: album.yaml, then tracklist.yaml,
+/// with values in tracklist.yaml overriding those in album.yaml.
+/// A path to a single specification file reads just that file.
+pub fn read_specs(p: &Path) -> Result<AlbumSpec, Box<dyn Error>> {
+    if p.is_file() {
+        return read_spec(p);
+    }
+
+    let mut spec = AlbumSpec {
+        ..Default::default()
+    };
+    let mut found = false;
+    for n in &[ALBUM_SPEC_FILE, TRACKLIST_SPEC_FILE] {
+        let sp = p.join(n);
+        if sp.is_file() {
+            spec.merge(read_spec(&sp)?);
+            found = true;
+        }
+    }
+    if !found {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No {} or {} in: {}",
+                ALBUM_SPEC_FILE,
+                TRACKLIST_SPEC_FILE,
+                p.display()
+            ),
+        )));
+    }
+    Ok(spec)
+}
+
+// Values can be written as strings, numbers or booleans.
+fn scalar_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn pairs(v: &Value) -> Vec<(String, String)> {
+    let mut ps = Vec::new();
+    if let Value::Mapping(m) = v {
+        for (k, v) in m {
+            if let (Some(k), Some(v)) = (scalar_string(k), scalar_string(v)) {
+                ps.push((k, v));
+            }
+        }
+    }
+    ps
+}
+
+/// A change to a single tag.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TagChange {
+    pub key: String,
+    /// Current value(s), joined with "/", None if the tag isn't set.
+    pub old: Option<String>,
+    pub new: String,
+}
+
+/// The changes a specification makes to a single track.
+#[derive(Debug)]
+pub struct TrackUpdate {
+    pub path: PathBuf,
+    /// All of the comments from the spec for this track.
+    pub comments: Vec<(String, String)>,
+    /// Just the comments that differ from what's in the file.
+    pub changes: Vec<TagChange>,
+}
+
+/// Work out the changes a specification makes to the FLAC files named in its
+/// `Tracks` section, relative to the directory dir.
+/// Files that are missing or aren't FLAC files are reported and skipped.
+pub fn plan(spec: &AlbumSpec, dir: &Path) -> Result<Vec<TrackUpdate>, Box<dyn Error>> {
+    let mut us = Vec::new();
+    for (f, tags) in &spec.tracks {
+        let p = dir.join(f);
+        if !p.is_file() {
+            eprintln!("File not found: {}", p.display());
+            continue;
+        }
+        match file::identify(File::open(&p)?)? {
+            Some(FileFormat::Flac(_)) => (),
+            _ => {
+                eprintln!("Tags can only be applied to FLAC files: {}", p.display());
+                continue;
+            }
+        }
+
+        let current = flac::read_comments(&p)?;
+        let comments = spec.comments_for(tags);
+        let mut changes = Vec::new();
+        for (k, v) in &comments {
+            let old = current
+                .iter()
+                .find(|(ck, _)| ck.eq_ignore_ascii_case(k))
+                .map(|(_, cv)| cv.join("/"));
+            if old.as_ref() != Some(v) {
+                changes.push(TagChange {
+                    key: k.clone(),
+                    old,
+                    new: v.clone(),
+                });
+            }
+        }
+        us.push(TrackUpdate {
+            path: p,
+            comments,
+            changes,
+        });
+    }
+    Ok(us)
+}
+
+/// Write the changed comments of each update to its file.
+pub fn apply(us: &[TrackUpdate]) -> Result<(), Box<dyn Error>> {
+    for u in us {
+        if u.changes.is_empty() {
+            continue;
+        }
+        let cs: Vec<(String, Vec<String>)> = u
+            .changes
+            .iter()
+            .map(|c| (c.key.clone(), vec![c.new.clone()]))
+            .collect();
+        flac::write_comments(&u.path, &cs)?;
+    }
+    Ok(())
+}
+
+//
+// Export
+//
+
+/// Vorbis keys for ID3 text frames.
+/// TRCK and TPOS are left out, they're taken from the track as number and total.
+const ID3_KEYS: [(&str, &str); 19] = [
+    ("TALB", "ALBUM"),
+    ("TBPM", "BPM"),
+    ("TCOM", "COMPOSER"),
+    ("TCON", "GENRE"),
+    ("TCOP", "COPYRIGHT"),
+    ("TDRC", "DATE"),
+    ("TENC", "ENCODED-BY"),
+    ("TIT1", "GROUPING"),
+    ("TIT2", "TITLE"),
+    ("TIT3", "SUBTITLE"),
+    ("TPE1", "ARTIST"),
+    ("TPE2", "ALBUMARTIST"),
+    ("TPE3", "CONDUCTOR"),
+    ("TPUB", "ORGANIZATION"),
+    ("TSOA", "ALBUMSORT"),
+    ("TSOP", "ARTISTSORT"),
+    ("TSOT", "TITLESORT"),
+    ("TSRC", "ISRC"),
+    ("TYER", "DATE"),
+];
+
+/// Vorbis keys for RIFF INFO ids.
+/// ITRK is left out, it's taken from the track as number.
+const RIFF_KEYS: [(&str, &str); 8] = [
+    ("IART", "ARTIST"),
+    ("ICMT", "COMMENT"),
+    ("ICOP", "COPYRIGHT"),
+    ("ICRD", "DATE"),
+    ("IGNR", "GENRE"),
+    ("INAM", "TITLE"),
+    ("IPRD", "ALBUM"),
+    ("ISFT", "ENCODER"),
+];
+
+/// Vorbis keys for AIFF text chunks, after those of the ID3 chunk.
+const AIFF_KEYS: [(&str, &str); 4] = [
+    ("NAME", "TITLE"),
+    ("AUTH", "ARTIST"),
+    ("ANNO", "COMMENT"),
+    ("(c) ", "COPYRIGHT"),
+];
+
+/// Vorbis keys for MPEG-4 ilst text items.
+/// trkn and disk are binary, they're taken from the track as number and total.
+const MP4_KEYS: [(&str, &str); 17] = [
+    ("©alb", "ALBUM"),
+    ("©ART", "ARTIST"),
+    ("©art", "ARTIST"),
+    ("aART", "ALBUMARTIST"),
+    ("©cmt", "COMMENT"),
+    ("©day", "DATE"),
+    ("©gen", "GENRE"),
+    ("©grp", "GROUPING"),
+    ("©lyr", "LYRICS"),
+    ("©nam", "TITLE"),
+    ("©too", "ENCODER"),
+    ("©wrt", "COMPOSER"),
+    ("cprt", "COPYRIGHT"),
+    ("soaa", "ALBUMARTISTSORT"),
+    ("soal", "ALBUMSORT"),
+    ("soar", "ARTISTSORT"),
+    ("sonm", "TITLESORT"),
+];
+
+// Keys that are always written per track, even when every track has the same value.
+const TRACK_KEYS: [&str; 2] = ["TITLE", "TRACKNUMBER"];
+
+// Key order for the template, anything else follows alphabetically.
+const KEY_ORDER: [&str; 10] = [
+    "TITLE",
+    "TRACKNUMBER",
+    "ALBUM",
+    "ARTIST",
+    "ALBUMARTIST",
+    "DATE",
+    "GENRE",
+    "TOTALTRACKS",
+    "DISCNUMBER",
+    "DISCTOTAL",
+];
+
+// Add the ID3 frames that have a Vorbis key.
+fn id3_comments(md: &ID3Metadata, add: &mut impl FnMut(&str, String)) {
+    for (id, k) in &ID3_KEYS {
+        if let Some(v) = md.text.get(*id) {
+            add(k, v.join("/"));
+        }
+    }
+    if let Some(v) = md.comments.get("COMM") {
+        add("COMMENT", v[0].2.clone());
+    }
+}
+
+/// The tags of a track as Vorbis comments.
+/// FLAC comments are used as is, ID3, MPEG-4, RIFF and AIFF keys are translated to their Vorbis
+/// equivalent and dropped if there isn't one.
+/// The general track values fill in anything the format metadata didn't have.
+pub fn track_comments(tk: &Track) -> Vec<(String, String)> {
+    let mut cs: Vec<(String, String)> = Vec::new();
+    let mut add = |k: &str, v: String| {
+        if !cs.iter().any(|(ck, _)| ck == k) {
+            cs.push((k.to_string(), v));
+        }
+    };
+
+    match &tk.metadata {
+        Some(FormatMetadata::Flac(md)) => {
+            for (k, v) in &md.comments {
+                add(&vorbis_key(k), v.join("/"));
+            }
+        }
+        Some(FormatMetadata::ID3(md)) => id3_comments(md, &mut add),
+        Some(FormatMetadata::MP4(md)) => {
+            for (id, k) in &MP4_KEYS {
+                if let Some(e) = md.text.get(*id) {
+                    add(k, e.value.clone());
+                }
+            }
+        }
+        Some(FormatMetadata::Riff(md)) => {
+            for (id, k) in &RIFF_KEYS {
+                if let Some(v) = md.info(id) {
+                    add(k, v.to_string());
+                }
+            }
+        }
+        Some(FormatMetadata::Aiff(md)) => {
+            if let Some(id3) = &md.id3 {
+                id3_comments(id3, &mut add);
+            }
+            for (id, k) in &AIFF_KEYS {
+                if let Some(v) = md.text(id) {
+                    add(k, v.to_string());
+                }
+            }
+        }
+        None => (),
+    }
+
+    let fields = [
+        ("TITLE", tk.title.clone()),
+        ("ARTIST", tk.artist.clone()),
+        ("ALBUM", tk.album.clone()),
+        ("ALBUMARTIST", tk.album_artist.clone()),
+        ("TRACKNUMBER", tk.track_number.map(|n| n.to_string())),
+        ("TOTALTRACKS", tk.track_total.map(|n| n.to_string())),
+        ("DISCNUMBER", tk.disk_number.map(|n| n.to_string())),
+        ("DISCTOTAL", tk.disk_total.map(|n| n.to_string())),
+    ];
+    for (k, v) in fields.iter() {
+        if let Some(v) = v {
+            add(k, v.clone());
+        }
+    }
+
+    cs.sort_by_key(|(k, _)| {
+        let i = KEY_ORDER.iter().position(|o| o == k);
+        (i.unwrap_or(KEY_ORDER.len()), k.clone())
+    });
+    cs
+}
+
+/// Create a specification from the tags of an album's tracks.
+/// Tags that have the same value on every track go in the Album section,
+/// the rest, along with titles and track numbers, go in the Tracks section.
+pub fn spec_from_album(a: &Album) -> AlbumSpec {
+    let tcs: Vec<(String, Vec<(String, String)>)> = a
+        .tracks
+        .iter()
+        .map(|tk| {
+            let f = tk
+                .path
+                .file_name()
+                .map_or_else(String::new, |f| f.to_string_lossy().to_string());
+            (f, track_comments(tk))
+        })
+        .collect();
+
+    let mut album = Vec::new();
+    if let Some((_, first)) = tcs.first() {
+        for (k, v) in first {
+            if !TRACK_KEYS.contains(&k.as_str())
+                && tcs
+                    .iter()
+                    .all(|(_, cs)| cs.contains(&(k.clone(), v.clone())))
+            {
+                album.push((k.clone(), v.clone()));
+            }
+        }
+    }
+
+    let tracks = tcs
+        .into_iter()
+        .map(|(f, cs)| {
+            let cs = cs.into_iter().filter(|c| !album.contains(c)).collect();
+            (f, cs)
+        })
+        .collect();
+
+    AlbumSpec {
+        album,
+        control: vec![
+            (USE_CAPITAL_KEYS.to_string(), "true".to_string()),
+            (USE_TITLE_CAPS.to_string(), "false".to_string()),
+        ],
+        tracks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album;
+    use crate::track;
+
+    const TEST_DIR: &str = "test_audio/LEON RUSSELL FILLMORE EAST NOV 20 1970 SB";
+
+    #[test]
+    fn test_read_specs() {
+        let spec = read_spec(&Path::new(TEST_DIR).join(ALBUM_SPEC_FILE)).unwrap();
+        assert_eq!(spec.tracks.len(), 20);
+        assert!(spec.control_flag(USE_CAPITAL_KEYS));
+        assert!(!spec.control_flag(USE_TITLE_CAPS));
+
+        let cs = spec.comments_for(&spec.tracks[0].1);
+        assert!(cs.contains(&("TRACKNUMBER".to_string(), "1".to_string())));
+        assert!(cs.contains(&("ALBUM".to_string(), "Album Title".to_string())));
+
+        // tracklist.yaml wins over album.yaml.
+        let spec = read_specs(Path::new(TEST_DIR)).unwrap();
+        assert_eq!(spec.tracks.len(), 20);
+        let cs = spec.comments_for(&spec.tracks[0].1);
+        assert!(cs.contains(&("ARTIST".to_string(), "Leon Russell".to_string())));
+        assert!(cs.contains(&("ALBUMARTIST".to_string(), "Leon Russell".to_string())));
+    }
+
+    #[test]
+    fn test_title_case() {
+        assert_eq!(title_case("SLIPPIN' AND SLIDIN'"), "Slippin' And Slidin'");
+        assert_eq!(
+            title_case("sweet little angel (don preston-vocals)"),
+            "Sweet Little Angel (Don Preston-vocals)"
+        );
+    }
+
+    #[test]
+    fn test_spec_from_album() {
+        let mut tks = Vec::new();
+        for (i, t) in ["One", "Two"].iter().enumerate() {
+            let mut md = track::FlacMetadata {
+                ..Default::default()
+            };
+            md.comments.insert("title".to_string(), vec![t.to_string()]);
+            md.comments
+                .insert("ARTIST".to_string(), vec!["Artist Name".to_string()]);
+            tks.push(Track {
+                path: PathBuf::from(format!("0{} {}.flac", i + 1, t)),
+                track_number: Some(i as u32 + 1),
+                metadata: Some(FormatMetadata::Flac(md)),
+                ..Default::default()
+            });
+        }
+        let mut md = track::ID3Metadata {
+            ..Default::default()
+        };
+        md.text
+            .insert("TIT2".to_string(), vec!["Three".to_string()]);
+        md.text
+            .insert("TPE1".to_string(), vec!["Artist Name".to_string()]);
+        md.text.insert("TRCK".to_string(), vec!["3/3".to_string()]);
+        tks.push(Track {
+            path: PathBuf::from("03 Three.mp3"),
+            track_number: Some(3),
+            track_total: Some(3),
+            metadata: Some(FormatMetadata::ID3(md)),
+            ..Default::default()
+        });
+
+        let spec = spec_from_album(&album::album_from_tracks(tks));
+        assert_eq!(
+            spec.album,
+            vec![("ARTIST".to_string(), "Artist Name".to_string())]
+        );
+        assert_eq!(spec.tracks.len(), 3);
+        assert_eq!(
+            spec.tracks[2].1,
+            vec![
+                ("TITLE".to_string(), "Three".to_string()),
+                ("TRACKNUMBER".to_string(), "3".to_string()),
+                ("TOTALTRACKS".to_string(), "3".to_string()),
+            ]
+        );
+
+        // What's written can be read back.
+        let rs = spec_from_str(&spec.to_yaml().unwrap()).unwrap();
+        assert_eq!(rs.album, spec.album);
+        assert_eq!(rs.control, spec.control);
+        assert_eq!(rs.tracks, spec.tracks);
+    }
+}
@@ -3,7 +3,14 @@
 extern crate chrono;
 use chrono::{DateTime, NaiveDateTime, Utc};
 
+use crate::aiff;
+use crate::ape::ApeTag;
+use crate::art::Artwork;
+use crate::cue;
+use crate::id3v1::{self, Id3v1};
+use crate::id3v2;
 use crate::file;
+use crate::flac;
 use crate::file::{Decoder, FileFormat};
 
 use std::cmp::Ordering;
@@ -19,9 +26,11 @@ use std::time::Duration;
 use std::vec::Vec;
 
 use crate::mpeg4;
+use crate::riff;
 use crate::mpeg4::formats::DRMSchemes;
 use format::consts::FORMAT_CLEAN;
 use mpeg4::formats::{AudioObjectTypes, ChannelConfig};
+use mpeg4::samples::SampleIndex;
 use prettytable::{format, Table};
 
 //
@@ -46,17 +55,25 @@ pub enum CodecFormat {
 
 /// PCM Codec Format
 /// Basic PCM sample data.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct PCMFormat {
   /// Sample rate in hertz.
   pub sample_rate: u32,
   /// Channels of audio (eg. 1 for mono, 2 for stereo etc.).
   pub channels: u8,
   /// Sample size (e.g. 16, 24, 48).
+  /// These are the valid bits, which may be fewer than the container bits.
   pub bits_per_sample: u16,
   /// Numnber of samples for this track.
   /// Used to compute duraiton.
   pub total_samples: u64,
+  /// Integer or floating point samples.
+  pub sample_format: SampleFormat,
+  /// Bits each sample is stored in (e.g. 32 for 24 bit samples in 4 bytes).
+  /// 0 if the format doesn't say, in which case it's bits_per_sample.
+  pub container_bits: u16,
+  /// Which speakers the channels are for, one bit for each of `SPEAKERS`.
+  pub channel_mask: Option<u32>,
 }
 
 const BILLION: u64 = 1_000_000_000;
@@ -68,6 +85,109 @@ impl PCMFormat {
     ns *= BILLION as f64;
     Duration::from_nanos(ns as u64)
   }
+
+  /// Names of the speakers in the channel mask, in channel order.
+  pub fn speakers(&self) -> Vec<&'static str> {
+    let mask = self.channel_mask.unwrap_or(0);
+    SPEAKERS
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| mask & (1 << i) != 0)
+      .map(|(_, s)| *s)
+      .collect()
+  }
+}
+
+/// How PCM samples are encoded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+  /// Signed integers (unsigned for 8 bits).
+  #[default]
+  Int,
+  /// IEEE floating point.
+  Float,
+  /// Compressed, e.g. the μ-law of an AIFF-C file. The bits are those of the decoded samples.
+  Compressed,
+}
+
+impl fmt::Display for SampleFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SampleFormat::Int => f.write_str("Integer"),
+      SampleFormat::Float => f.write_str("Float"),
+      SampleFormat::Compressed => f.write_str("Compressed"),
+    }
+  }
+}
+
+/// Speaker positions of the bits of a WAVE_FORMAT_EXTENSIBLE channel mask, lowest bit first.
+pub const SPEAKERS: [&str; 18] = [
+  "Front Left",
+  "Front Right",
+  "Front Center",
+  "LFE",
+  "Back Left",
+  "Back Right",
+  "Front Left of Center",
+  "Front Right of Center",
+  "Back Center",
+  "Side Left",
+  "Side Right",
+  "Top Center",
+  "Top Front Left",
+  "Top Front Center",
+  "Top Front Right",
+  "Top Back Left",
+  "Top Back Center",
+  "Top Back Right",
+];
+
+//
+// Gapless
+//
+
+/// Where gapless playback information came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GaplessSource {
+  /// The iTunes iTunSMPB freeform item of an MPEG4 file.
+  ITunSMPB,
+  /// The edit list (elst) of the audio trak of an MPEG4 file.
+  EditList,
+  /// The LAME extension of the Xing/Info header of an MP3 file.
+  Lame,
+}
+
+impl fmt::Display for GaplessSource {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      GaplessSource::ITunSMPB => "iTunSMPB",
+      GaplessSource::EditList => "Edit List",
+      GaplessSource::Lame => "LAME",
+    })
+  }
+}
+
+/// Encoder delay and padding, the samples a gapless player drops from the start
+/// and end of the decoded audio so that tracks run into each other without a gap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gapless {
+  /// Samples of silence the encoder added at the start.
+  pub delay: u32,
+  /// Samples the encoder added at the end to fill the last frame.
+  pub padding: u32,
+  /// Samples of actual audio, once delay and padding are dropped.
+  pub valid_samples: u64,
+  pub source: GaplessSource,
+}
+
+impl Gapless {
+  /// Length of the audio once delay and padding are dropped.
+  pub fn playable_duration(&self, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+      return Duration::from_nanos(0);
+    }
+    Duration::from_nanos((self.valid_samples as u128 * BILLION as u128 / sample_rate as u128) as u64)
+  }
 }
 
 //
@@ -87,6 +207,12 @@ pub struct MPEG3Format {
   pub layer: MP3Layer,
   /// Track duration.
   pub duration: Duration,
+  /// Number of audio frames, from the Xing or VBRI header.
+  pub frames: Option<u64>,
+  /// Whether the bitrate varies, in which case bitrate is the average.
+  pub vbr: bool,
+  /// Encoder delay and padding, from the LAME header.
+  pub gapless: Option<Gapless>,
 }
 
 /// Return a printable string for MPEG-3 version.
@@ -102,6 +228,14 @@ impl MPEG3Format {
     }
   }
 
+  /// Length of the audio a gapless player plays, without the encoder delay and padding.
+  pub fn playable_duration(&self) -> Duration {
+    match &self.gapless {
+      Some(g) => g.playable_duration(self.sample_rate as u32),
+      None => self.duration,
+    }
+  }
+
   ///  Printable string for MPEG audio layer (e.g. Layer 3).
   pub fn layer_string(&self) -> String {
     match &self.layer {
@@ -122,7 +256,7 @@ impl fmt::Debug for MPEG3Format {
 }
 
 /// MPEG audio version.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MPVersion {
   Reserved,
   MPEG1,
@@ -132,7 +266,7 @@ pub enum MPVersion {
 }
 
 /// MPEG audio layer.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MP3Layer {
   Reserved,
   Layer1,
@@ -170,6 +304,10 @@ pub struct MPEG4AudioFormat {
   pub protected: bool,
   /// Four character code used to identify the protection scheme
   pub protection_scheme: Option<DRMSchemes>,
+  /// Where and how long each sample is, from the sample tables.
+  pub samples: Option<SampleIndex>,
+  /// Encoder delay and padding, from iTunSMPB or the edit list.
+  pub gapless: Option<Gapless>,
 }
 
 impl MPEG4AudioFormat {
@@ -178,11 +316,31 @@ impl MPEG4AudioFormat {
     f64::from(self.sr >> 16)
   }
   /// Legnth of the track
+  /// Exact if we have the sample tables.
   pub fn duration(&self) -> Duration {
+    if let Some(si) = &self.samples {
+      return si.duration();
+    }
     let mut ns = self.total_samples as f64 / self.sample_rate();
     ns *= BILLION as f64;
     Duration::from_nanos(ns as u64)
   }
+  /// Length of the audio a gapless player plays, without the encoder delay and padding.
+  pub fn playable_duration(&self) -> Duration {
+    match &self.gapless {
+      Some(g) => g.playable_duration(self.sample_rate() as u32),
+      None => self.duration(),
+    }
+  }
+  /// Average bits per second.
+  /// Worked out from the sample tables if we have them, the esds value
+  /// is often 0.
+  pub fn bitrate(&self) -> u32 {
+    match &self.samples {
+      Some(si) => si.avg_bitrate(),
+      None => self.avg_bitrate,
+    }
+  }
 }
 
 //
@@ -195,6 +353,7 @@ impl MPEG4AudioFormat {
 /// formally by the Track data structure.
 /// This also allows for a bit of evolution in the formats as they grow.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum FormatMetadata {
   /// Flac specific metadata
   Flac(FlacMetadata),
@@ -202,6 +361,10 @@ pub enum FormatMetadata {
   ID3(ID3Metadata),
   /// Mpeg4 specific metadata.
   MP4(MPEG4Metadata),
+  /// RIFF (WAV) chunk metadata.
+  Riff(RiffMetadata),
+  /// AIFF chunk metadata.
+  Aiff(AiffMetadata),
 }
 
 //
@@ -217,6 +380,17 @@ pub enum FormatMetadata {
 pub struct FlacMetadata {
   /// Flac metadata is stored as comments and key/value pairs.
   pub comments: HashMap<String, Vec<String>>,
+  /// MD5 signature of the unencoded audio from the STREAMINFO block,
+  /// as a lower case hex string. None if the encoder didn't compute one.
+  pub md5: Option<String>,
+  /// The CUESHEET block.
+  pub cue_sheet: Option<flac::CueSheet>,
+  /// The SEEKTABLE block.
+  pub seek_table: Option<flac::SeekTable>,
+  /// The APPLICATION blocks.
+  pub applications: Vec<flac::Application>,
+  /// Bytes of PADDING in all the padding blocks.
+  pub padding: u64,
 }
 
 impl FlacMetadata {
@@ -246,6 +420,216 @@ impl FlacMetadata {
     } else {
       write!(w, "No Comments.")?;
     }
+
+    if let Some(cs) = &self.cue_sheet {
+      println!("\nCue Sheet");
+      if !cs.catalog.is_empty() {
+        println!("Catalog: {}", cs.catalog);
+      }
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Track", "Offset", "Time", "ISRC", "Type", "Indexes"]);
+      for t in &cs.tracks {
+        let number = if t.is_lead_out() {
+          "Lead-out".to_string()
+        } else {
+          t.number.to_string()
+        };
+        let time = if cs.is_cd {
+          flac::cd_time(t.offset)
+        } else {
+          String::new()
+        };
+        let kind = match (t.audio, t.pre_emphasis) {
+          (false, _) => "Data",
+          (true, true) => "Audio (pre-emphasis)",
+          (true, false) => "Audio",
+        };
+        let indexes: Vec<String> = t
+          .indexes
+          .iter()
+          .map(|(n, o)| format!("{:02} {}", n, o))
+          .collect();
+        table.add_row(row![number, t.offset, time, t.isrc, kind, indexes.join(", ")]);
+      }
+      table.print(&mut w)?;
+    }
+
+    println!("\nBlocks");
+    let mut table = Table::new();
+    table.set_format(*FORMAT_CLEAN);
+    if let Some(st) = &self.seek_table {
+      table.add_row(row!["Seek Table", st]);
+    }
+    for a in &self.applications {
+      table.add_row(row!["Application", format!("{} {} bytes", a.id, a.size)]);
+    }
+    table.add_row(row!["Padding", format!("{} bytes", self.padding)]);
+    table.print(&mut w)?;
+    Ok(())
+  }
+}
+
+//
+// RIFF
+//
+
+/// RIFF Format Metadata
+/// The metadata chunks of a WAV file: LIST INFO text, the Broadcast Wave
+/// bext chunk and the iXML chunk.
+#[derive(Debug, Default)]
+pub struct RiffMetadata {
+  /// Every chunk in the file, in order.
+  pub chunks: Vec<riff::Chunk>,
+  /// INFO text by id, e.g. INAM, in the order found.
+  pub info: Vec<(String, String)>,
+  pub bext: Option<riff::Bext>,
+  pub ixml: Option<riff::IXml>,
+  /// When the first sample was recorded, from the bext chunk.
+  pub timestamp: Option<NaiveDateTime>,
+}
+
+impl RiffMetadata {
+  /// The INFO text for an id.
+  pub fn info(&self, id: &str) -> Option<&str> {
+    self
+      .info
+      .iter()
+      .find(|(i, _)| i == id)
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// Print the metadata, as key values in columns, to a writer.
+  pub fn print(&self, mut w: impl Write) -> Result<(), std::io::Error> {
+    println!("Metadata");
+
+    println!("\nINFO");
+    if !self.info.is_empty() {
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Name", "Value"]);
+      for (id, v) in &self.info {
+        table.add_row(row![id, riff::info_name(id), v]);
+      }
+      table.print(&mut w)?;
+    } else {
+      write!(w, "No INFO.")?;
+    }
+
+    if let Some(b) = &self.bext {
+      println!("\nBroadcast Wave (bext)");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Value"]);
+      table.add_row(row!["Description", b.description]);
+      table.add_row(row!["Originator", b.originator]);
+      table.add_row(row!["Originator Reference", b.originator_reference]);
+      table.add_row(row!["Origination Date", b.origination_date]);
+      table.add_row(row!["Origination Time", b.origination_time]);
+      table.add_row(row!["Time Reference", b.time_reference]);
+      table.add_row(row![
+        "Timestamp",
+        self
+          .timestamp
+          .map_or(EMPTY_SMALL.to_string(), |t| t.to_string())
+      ]);
+      table.add_row(row!["Version", b.version]);
+      if !b.umid.is_empty() {
+        table.add_row(row!["UMID", b.umid]);
+      }
+      table.add_row(row!["Coding History", b.coding_history]);
+      table.print(&mut w)?;
+    }
+
+    if let Some(x) = &self.ixml {
+      println!("\niXML");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Value"]);
+      for (k, v) in x.fields() {
+        table.add_row(row![k, v]);
+      }
+      table.add_row(row!["Size", format!("{} bytes", x.xml.len())]);
+      table.print(&mut w)?;
+    }
+
+    println!("\nChunks");
+    let mut table = Table::new();
+    table.set_format(*FORMAT_CLEAN);
+    table.add_row(row!["Id", "Size", "Offset"]);
+    for c in &self.chunks {
+      table.add_row(row![c.id, c.size, c.offset]);
+    }
+    table.print(&mut w)?;
+    Ok(())
+  }
+}
+
+//
+// AIFF
+//
+
+/// AIFF Format Metadata
+#[derive(Debug, Default)]
+pub struct AiffMetadata {
+  /// Every chunk in the file, in order.
+  pub chunks: Vec<riff::Chunk>,
+  /// The AIFF-C compression type and name, None for AIFF.
+  pub compression: Option<(String, String)>,
+  /// Text chunks by id, e.g. NAME, in the order found.
+  pub text: Vec<(String, String)>,
+  /// The tag in the ID3 chunk.
+  pub id3: Option<ID3Metadata>,
+}
+
+impl AiffMetadata {
+  /// The text of the first chunk with an id.
+  pub fn text(&self, id: &str) -> Option<&str> {
+    self
+      .text
+      .iter()
+      .find(|(i, _)| i == id)
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// Print the metadata, as key values in columns, to a writer.
+  /// The ID3 tag, if there is one, comes first.
+  pub fn print(&self, mut w: impl Write) -> Result<(), std::io::Error> {
+    match &self.id3 {
+      Some(md) => md.print(&mut w)?,
+      None => println!("Metadata"),
+    }
+
+    println!("\nAIFF Text");
+    if !self.text.is_empty() {
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Name", "Value"]);
+      for (id, v) in &self.text {
+        table.add_row(row![id, aiff::text_name(id), v]);
+      }
+      table.print(&mut w)?;
+    } else {
+      write!(w, "No text.")?;
+    }
+
+    if let Some((c, name)) = &self.compression {
+      println!("\nCompression");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Type", "Name"]);
+      table.add_row(row![c, name]);
+      table.print(&mut w)?;
+    }
+
+    println!("\nChunks");
+    let mut table = Table::new();
+    table.set_format(*FORMAT_CLEAN);
+    table.add_row(row!["Id", "Size", "Offset"]);
+    for c in &self.chunks {
+      table.add_row(row![c.id, c.size, c.offset]);
+    }
+    table.print(&mut w)?;
     Ok(())
   }
 }
@@ -261,14 +645,110 @@ pub struct ID3Metadata {
   pub text: HashMap<String, Vec<String>>,
   /// Comment metadata is stored by key with values: Languages, Description, Text.
   pub comments: HashMap<String, Vec<(String, String, String)>>,
+  /// The ID3v1 or ID3v1.1 tag at the end of the file.
+  pub v1: Option<Id3v1>,
+  /// The APEv2 tag at the end of the file.
+  pub ape: Option<ApeTag>,
+  /// User defined text (TXXX).
+  pub user_text: Vec<id3v2::UserText>,
+  /// URLs (W frames, including WXXX).
+  pub links: Vec<id3v2::Link>,
+  /// Unsynchronised lyrics (USLT).
+  pub lyrics: Vec<id3v2::Lyrics>,
+  /// Synchronised lyrics and text (SYLT).
+  pub synced_lyrics: Vec<id3v2::SyncedLyrics>,
+  /// Ratings and play counts by user (POPM).
+  pub ratings: Vec<id3v2::Popularimeter>,
+  /// Play count (PCNT).
+  pub play_count: Option<u64>,
+  /// Identifiers in databases such as MusicBrainz (UFID).
+  pub unique_ids: Vec<id3v2::UniqueId>,
+  /// Application data (PRIV).
+  pub private: Vec<id3v2::Private>,
+  /// Embedded files (GEOB).
+  pub objects: Vec<id3v2::Object>,
+  /// Chapters (CHAP).
+  pub chapters: Vec<id3v2::Chapter>,
+  /// Tables of contents of the chapters (CTOC).
+  pub tocs: Vec<id3v2::TableOfContents>,
+}
+
+/// A field whose value in an ID3v1 or APEv2 tag isn't the one in the ID3v2 tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagDifference {
+  /// e.g. Title.
+  pub field: &'static str,
+  /// The tag that differs, ID3v1 or APEv2.
+  pub tag: &'static str,
+  /// Value in the ID3v2 tag.
+  pub v2: String,
+  /// Value in the other tag.
+  pub value: String,
 }
 
+// Fields compared between the tags: name, ID3v2 frames and APEv2 key.
+const COMPARED_FIELDS: [(&str, &[&str], &str); 6] = [
+  ("Title", &["TIT2"], "Title"),
+  ("Artist", &["TPE1"], "Artist"),
+  ("Album", &["TALB"], "Album"),
+  ("Year", &["TDRC", "TYER"], "Year"),
+  ("Track", &["TRCK"], "Track"),
+  ("Genre", &["TCON"], "Genre"),
+];
+
 impl ID3Metadata {
+  /// Fields where the ID3v1 or APEv2 tags have a different value than the ID3v2 tag.
+  /// Fields missing from either tag aren't compared, nor is the part of
+  /// an ID3v2 value that was too long for the ID3v1 tag.
+  pub fn differences(&self) -> Vec<TagDifference> {
+    let mut diffs = Vec::new();
+    for (field, frames, key) in &COMPARED_FIELDS {
+      let v2 = match frames
+        .iter()
+        .find_map(|f| self.text.get(*f).and_then(|v| v.first()))
+      {
+        Some(v) => normalize_field(field, v),
+        None => continue,
+      };
+      if let Some(v1) = &self.v1 {
+        let value = match *field {
+          "Title" => v1.title.clone(),
+          "Artist" => v1.artist.clone(),
+          "Album" => v1.album.clone(),
+          "Year" => v1.year.clone(),
+          "Track" => v1.track.map(|t| t.to_string()).unwrap_or_default(),
+          _ => v1.genre_name().unwrap_or_default().to_string(),
+        };
+        let truncated = value.chars().count() == id3v1::FIELD_SIZE && v2.starts_with(&value);
+        if !value.is_empty() && value != v2 && !truncated {
+          diffs.push(TagDifference {
+            field,
+            tag: "ID3v1",
+            v2: v2.clone(),
+            value,
+          });
+        }
+      }
+      if let Some(value) = self.ape.as_ref().and_then(|a| a.text(key)) {
+        let value = normalize_field(field, value);
+        if !value.is_empty() && value != v2 {
+          diffs.push(TagDifference {
+            field,
+            tag: "APEv2",
+            v2: v2.clone(),
+            value,
+          });
+        }
+      }
+    }
+    diffs
+  }
+
   /// Print the metadata, as key values in columns, to a writer.
   pub fn print(&self, mut w: impl Write) -> Result<(), std::io::Error> {
     println!("Metadata");
 
-    println!("\nText");
+    println!("\nID3v2 Text");
     if !self.text.is_empty() {
       let mut table = Table::new();
       table.set_format(*FORMAT_CLEAN);
@@ -293,7 +773,7 @@ impl ID3Metadata {
       write!(w, "No text.")?;
     }
 
-    println!("\nComments");
+    println!("\nID3v2 Comments");
     if !self.comments.is_empty() {
       let mut table = Table::new();
       table.set_format(*FORMAT_CLEAN);
@@ -317,10 +797,185 @@ impl ID3Metadata {
     } else {
       write!(w, "No Comments.")?;
     }
+
+    if let Some(v1) = &self.v1 {
+      println!("\n{}", v1.version());
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Value"]);
+      table.add_row(row!["Title", v1.title]);
+      table.add_row(row!["Artist", v1.artist]);
+      table.add_row(row!["Album", v1.album]);
+      table.add_row(row!["Year", v1.year]);
+      table.add_row(row!["Comment", v1.comment]);
+      if let Some(t) = v1.track {
+        table.add_row(row!["Track", t]);
+      }
+      table.add_row(row![
+        "Genre",
+        format!("{} {}", v1.genre, v1.genre_name().unwrap_or_default())
+      ]);
+      table.print(&mut w)?;
+    }
+
+    if let Some(ape) = &self.ape {
+      println!("\nAPEv2");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Value"]);
+      for i in &ape.items {
+        table.add_row(row![i.key, i.value]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.user_text.is_empty() {
+      println!("\nUser Text (TXXX)");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Description", "Value"]);
+      for t in &self.user_text {
+        table.add_row(row![t.description, t.value]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.links.is_empty() {
+      println!("\nLinks");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Description", "URL"]);
+      for l in &self.links {
+        table.add_row(row![l.id, l.description, l.url]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.lyrics.is_empty() || !self.synced_lyrics.is_empty() {
+      println!("\nLyrics");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Lang", "Description", "Text"]);
+      for l in &self.lyrics {
+        table.add_row(row!["USLT", l.lang, l.description, l.text]);
+      }
+      for l in &self.synced_lyrics {
+        let unit = if l.milliseconds { "ms" } else { "frames" };
+        table.add_row(row![
+          "SYLT",
+          l.lang,
+          l.content_type,
+          format!("{} lines, times in {}", l.lines.len(), unit)
+        ]);
+        for (t, text) in &l.lines {
+          table.add_row(row!["", "", t, text]);
+        }
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.ratings.is_empty() || self.play_count.is_some() {
+      println!("\nRatings");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["User", "Rating", "Stars", "Play Count"]);
+      for r in &self.ratings {
+        table.add_row(row![r.email, r.rating, r.stars(), r.play_count]);
+      }
+      if let Some(n) = self.play_count {
+        table.add_row(row!["(PCNT)", "", "", n]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.unique_ids.is_empty() || !self.private.is_empty() {
+      println!("\nIdentifiers and Private Data");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Owner", "Value"]);
+      for u in &self.unique_ids {
+        table.add_row(row!["UFID", u.owner, u.id_string()]);
+      }
+      for p in &self.private {
+        table.add_row(row!["PRIV", p.owner, format!("{} bytes", p.data.len())]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.objects.is_empty() {
+      println!("\nObjects (GEOB)");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["MIME Type", "File Name", "Description", "Size"]);
+      for o in &self.objects {
+        table.add_row(row![o.mime_type, o.filename, o.description, o.data.len()]);
+      }
+      table.print(&mut w)?;
+    }
+
+    if !self.chapters.is_empty() || !self.tocs.is_empty() {
+      println!("\nChapters");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Key", "Element", "Start (ms)", "End (ms)", "Title"]);
+      for t in &self.tocs {
+        table.add_row(row![
+          "CTOC",
+          t,
+          "",
+          "",
+          t.title.as_deref().unwrap_or_default()
+        ]);
+      }
+      for c in &self.chapters {
+        table.add_row(row![
+          "CHAP",
+          c.element_id,
+          c.start,
+          c.end,
+          c.title.as_deref().unwrap_or_default()
+        ]);
+      }
+      table.print(&mut w)?;
+    }
+
+    let diffs = self.differences();
+    if !diffs.is_empty() {
+      println!("\nTag Differences");
+      let mut table = Table::new();
+      table.set_format(*FORMAT_CLEAN);
+      table.add_row(row!["Field", "Tag", "ID3v2", "Value"]);
+      for d in &diffs {
+        table.add_row(row![d.field, d.tag, d.v2, d.value]);
+      }
+      table.print(&mut w)?;
+    }
     Ok(())
   }
 }
 
+// A tag value in the form used to compare it between tags:
+// the year of a date, the number of a track without the total or leading zeros,
+// and the name of an ID3v2 genre given as a number, e.g. "(17)".
+fn normalize_field(field: &str, v: &str) -> String {
+  let v = v.trim();
+  match field {
+    "Year" => v.chars().take(4).collect(),
+    "Track" => {
+      let n = v.split('/').next().unwrap_or_default();
+      n.parse::<u32>().map_or(n.to_string(), |n| n.to_string())
+    }
+    "Genre" => {
+      let n = v.trim_start_matches('(').trim_end_matches(')');
+      match n.parse::<usize>().ok().and_then(|n| id3v1::GENRES.get(n)) {
+        Some(g) => g.to_string(),
+        None => v.to_string(),
+      }
+    }
+    _ => v.to_string(),
+  }
+}
+
 //
 // MPEG4
 //
@@ -372,6 +1027,24 @@ impl PartialEq for MetaEntry<u32> {
 
 impl Eq for MetaEntry<u32> {}
 
+/// An iTunes freeform (----) item, e.g. com.apple.iTunes:iTunSMPB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeformEntry {
+  /// Reverse DNS name of who defined the item, e.g. com.apple.iTunes.
+  pub mean: String,
+  pub name: String,
+  pub value: String,
+}
+
+impl FreeformEntry {
+  /// The mean:name key the item is stored under in the text metadata.
+  pub fn key(&self) -> String {
+    format!("{}:{}", self.mean, self.name)
+  }
+}
+
+const FREEFORM_DESCRIPTION: &str = "Freeform Item";
+
 /// MPeg 4 Format Metadata
 ///
 /// This is pulled from the ilst box found: /moov/udta/ilist.
@@ -392,7 +1065,11 @@ impl Eq for MetaEntry<u32> {}
 ///
 /// Type == 21, is stored a single byte, in the byte `HashMap`.
 ///
-/// Types: 0 (Implicit), 13(JPEG), 14(PMG) are not stored at the moment.
+/// Freeform (----) items are keyed by their mean and name as `mean:name`,
+/// e.g. `com.apple.iTunes:iTunSMPB`, and are also kept in order in `freeform`.
+///
+/// Types: 13(JPEG), 14(PMG) in a covr box become the track's artwork,
+/// 0 (Implicit) is not stored at the moment.
 ///
 #[derive(Debug)]
 pub struct MPEG4Metadata {
@@ -412,7 +1089,13 @@ pub struct MPEG4Metadata {
   /// This is the size of the actual media data, independent of
   /// the metadata. It could be expressed as a percentage of the file
   /// size.
-  pub media_size: u32,
+  pub media_size: u64,
+
+  /// Each of the tracks in the file, not just the audio one.
+  pub traks: Vec<mpeg4::trak::Trak>,
+
+  /// The freeform items, in the order they're found.
+  pub freeform: Vec<FreeformEntry>,
 }
 
 // TODO(jdr): Fix this. It only needs 1 HasTable.
@@ -425,11 +1108,40 @@ impl Default for MPEG4Metadata {
       creation: DateTime::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc),
       modification: DateTime::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc),
       media_size: 0,
+      traks: Vec::new(),
+      freeform: Vec::new(),
     }
   }
 }
 
 impl MPEG4Metadata {
+  /// Adds a freeform item, to the text metadata as well.
+  pub fn add_freeform(&mut self, mean: &str, name: &str, value: &str) {
+    let e = FreeformEntry {
+      mean: mean.to_string(),
+      name: name.to_string(),
+      value: value.to_string(),
+    };
+    self.text.insert(
+      e.key(),
+      MetaEntry {
+        description: FREEFORM_DESCRIPTION.to_string(),
+        value: e.value.clone(),
+      },
+    );
+    self.freeform.push(e);
+  }
+
+  /// The value of the freeform item with mean and name, e.g.
+  /// `freeform_value("com.apple.iTunes", "iTunSMPB")`.
+  pub fn freeform_value(&self, mean: &str, name: &str) -> Option<&str> {
+    self
+      .freeform
+      .iter()
+      .find(|e| e.mean == mean && e.name == name)
+      .map(|e| e.value.as_str())
+  }
+
   /// Print the metadata, as key values in columns, to a writer.
   pub fn print(&self, mut w: impl Write) -> Result<(), std::io::Error> {
     println!("Metadata");
@@ -463,6 +1175,15 @@ impl MPEG4Metadata {
   }
 }
 
+/// A chapter marker within a track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+  /// Time from the start of the track.
+  pub start: Duration,
+  pub duration: Duration,
+  pub title: String,
+}
+
 // Track Definition
 
 /// Captures general and codec specific metadata for a single audio track.
@@ -496,6 +1217,16 @@ pub struct Track {
   /// to capture metadata not otherwise provided by the Track and assocaited
   /// structs and enums.
   pub metadata: Option<FormatMetadata>,
+  /// Chapter markers, in order, for formats that have them.
+  pub chapters: Vec<Chapter>,
+  /// Images embedded in the tags, e.g. the cover.
+  pub artwork: Vec<Artwork>,
+  /// MusicBrainz recording id.
+  pub musicbrainz_track_id: Option<String>,
+  /// MusicBrainz release id.
+  pub musicbrainz_album_id: Option<String>,
+  /// Where the track is in its file, for a virtual track from a cue sheet.
+  pub span: Option<cue::Span>,
 }
 
 impl Default for Track {
@@ -513,6 +1244,11 @@ impl Default for Track {
       disk_total: None,
       format: None,
       metadata: None,
+      chapters: Vec::new(),
+      artwork: Vec::new(),
+      musicbrainz_track_id: None,
+      musicbrainz_album_id: None,
+      span: None,
       // comments: HashMap::new(),
     }
   }
@@ -536,7 +1272,44 @@ impl Track {
       },
     }
   }
+
+  /// Encoder delay and padding, if the track has them.
+  pub fn gapless(&self) -> Option<&Gapless> {
+    match &self.format {
+      Some(CodecFormat::MPEG3(f)) => f.gapless.as_ref(),
+      Some(CodecFormat::MPEG4(f)) => f.gapless.as_ref(),
+      _ => None,
+    }
+  }
+
+  /// Whether the track is in a lossy format that adds delay and padding,
+  /// MP3 and AAC, and so needs gapless information to play without a gap.
+  pub fn needs_gapless(&self) -> bool {
+    match &self.format {
+      Some(CodecFormat::MPEG3(_)) => true,
+      Some(CodecFormat::MPEG4(_)) => match &self.metadata {
+        Some(FormatMetadata::MP4(md)) => mpeg4::trak::audio_trak(&md.traks)
+          .and_then(|i| md.traks[i].sample_format.as_deref())
+          == Some(AAC_SAMPLE_FORMAT),
+        _ => false,
+      },
+      _ => false,
+    }
+  }
+
+  /// Length of the audio a gapless player plays, without the encoder delay and padding.
+  pub fn playable_duration(&self) -> Option<Duration> {
+    match &self.format {
+      Some(CodecFormat::PCM(f)) => Some(f.duration()),
+      Some(CodecFormat::MPEG3(f)) => Some(f.playable_duration()),
+      Some(CodecFormat::MPEG4(f)) => Some(f.playable_duration()),
+      None => None,
+    }
+  }
 }
+
+// Sample entry of AAC audio in an MPEG4 file.
+const AAC_SAMPLE_FORMAT: &str = "mp4a";
 /// Read track(s) and regular files from a file or directory.
 ///
 /// `PathBuf` provides the file or directory.
@@ -565,8 +1338,8 @@ pub fn files_from(
       // Directories are not traversed, just listed.
       files.push(p);
     } else {
-      match get_track(&p)? {
-        Some(mut tk) => {
+      match get_track(&p) {
+        Ok(Some(mut tk)) => {
           // Some format don't provide track titles.
           // Let's use the file name if they don't.
           if tk.title.is_none() {
@@ -579,7 +1352,12 @@ pub fn files_from(
           tk.path = p;
           tracks.push(tk);
         }
-        None => files.push(p),
+        Ok(None) => files.push(p),
+        // A bad file shouldn't stop us reading the rest.
+        Err(e) => {
+          eprintln!("Couldn't read {}: {}", p.display(), e);
+          files.push(p);
+        }
       }
     }
   }
@@ -609,6 +1387,7 @@ pub fn get_track(p: &path::PathBuf) -> Result<Option<Track>, Box<dyn Error>> {
       FileFormat::MPEG4(mut d) => return Ok(d.get_track(&file)?),
       FileFormat::MP4A(mut d) => return Ok(d.get_track(&file)?),
       FileFormat::WAV(mut d) => return Ok(d.get_track(&file)?),
+      FileFormat::AIFF(mut d) => return Ok(d.get_track(&file)?),
       FileFormat::MP3(mut d) => return Ok(d.get_track(&file)?),
     }
   }
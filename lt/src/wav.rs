@@ -1,12 +1,16 @@
 //! Interface to WAV file format reading.
 //!
-//! Wav files only provide basic format information, so really only
-//! track::SampleFormat.
-//! Thie means you need to set the title (and anything else for that matter
-//! on your own.
+//! The format comes from the fmt chunk, which may be WAVE_FORMAT_EXTENSIBLE
+//! with valid bits and a channel mask. Integer and float samples are read,
+//! in RIFF files and in RF64/BW64 files over 4 GiB. Any metadata comes
+//! from the LIST INFO, bext and iXML chunks, see `riff`.
+//!
+//! References:
+//!
+//! [https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible)
 use crate::file::{Decoder, FileFormat};
+use crate::riff;
 use crate::track;
-use hound;
 use std::error::Error;
 use std::io::{Read, Seek};
 
@@ -15,24 +19,107 @@ use std::io::{Read, Seek};
 /// Wav file format reader.
 ///
 /// Implements the `Deecoder` trait, so has `get_track`.
-pub struct Wav;
+pub struct Wav {
+    /// The id of the file's chunk: RIFF, RF64 or BW64.
+    pub form: String,
+    /// Size of the file less 8 bytes, from the ds64 chunk for RF64 and BW64.
+    pub riff_size: u64,
+}
 
-const RIFF_HEADER: &[u8] = b"RIFF";
 const WAVE_HEADER: &[u8] = b"WAVE";
 
-/// Read the first 12 bytes of the buffer and return A `Wav` strcut
+/// Id of the fmt chunk.
+pub const FMT: &str = "fmt ";
+/// Integer samples.
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+/// Float samples.
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+/// The format is the first two bytes of the sub format GUID.
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// Size of the fmt chunk, without the extension.
+const FMT_SIZE: usize = 16;
+// Size of the fmt chunk of WAVE_FORMAT_EXTENSIBLE.
+const FMT_EXTENSIBLE_SIZE: usize = 40;
+
+/// Read the first bytes of the buffer and return A `Wav` strcut
 /// if this buffer has the right identifier,
 /// return None otherwise.
 ///
-/// Will also return None if there are less than 12 bytes in the buffer.
+/// RF64 and BW64 files need the start of their ds64 chunk, 28 bytes in all,
+/// other files need 12 bytes.
 pub fn identify(b: &[u8]) -> Option<FileFormat> {
-    if b.len() >= 12 && &b[0..4] == RIFF_HEADER {
-        match &b[8..12] {
-            b if b == WAVE_HEADER => Some(FileFormat::WAV(Wav {})),
-            _ => None,
-        }
+    if b.len() < 12 || !riff::is_form(&b[0..4]) || &b[8..12] != WAVE_HEADER {
+        return None;
+    }
+    let form = String::from_utf8_lossy(&b[0..4]).to_string();
+    let riff_size = if form == riff::RIFF {
+        u64::from(u32::from_le_bytes([b[4], b[5], b[6], b[7]]))
+    } else if b.len() >= 28 && &b[12..16] == riff::DS64.as_bytes() {
+        let mut n = [0; 8];
+        n.copy_from_slice(&b[20..28]);
+        u64::from_le_bytes(n)
     } else {
-        None
+        return None;
+    };
+    Some(FileFormat::WAV(Wav { form, riff_size }))
+}
+
+/// The fields of a fmt chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WaveFormat {
+    /// The format tag, or the sub format's for WAVE_FORMAT_EXTENSIBLE.
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Bytes in a sample for all of the channels.
+    pub block_align: u16,
+    /// Bits each sample is stored in.
+    pub container_bits: u16,
+    /// Bits of each sample that are used, the same as the container bits
+    /// unless the format is WAVE_FORMAT_EXTENSIBLE.
+    pub valid_bits: u16,
+    pub channel_mask: Option<u32>,
+}
+
+impl WaveFormat {
+    /// Parse the data of a fmt chunk.
+    pub fn parse(b: &[u8]) -> Result<WaveFormat, Box<dyn Error>> {
+        if b.len() < FMT_SIZE {
+            return Err(format!("fmt chunk is too short: {} bytes.", b.len()).into());
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let mut f = WaveFormat {
+            format_tag: u16_at(0),
+            channels: u16_at(2),
+            sample_rate: u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+            block_align: u16_at(12),
+            container_bits: u16_at(14),
+            valid_bits: u16_at(14),
+            channel_mask: None,
+        };
+        if f.format_tag == WAVE_FORMAT_EXTENSIBLE {
+            if b.len() < FMT_EXTENSIBLE_SIZE {
+                return Err(
+                    format!("Extensible fmt chunk is too short: {} bytes.", b.len()).into(),
+                );
+            }
+            // Some writers leave the valid bits as 0.
+            if u16_at(18) != 0 {
+                f.valid_bits = u16_at(18);
+            }
+            f.channel_mask = Some(u32::from_le_bytes([b[20], b[21], b[22], b[23]]));
+            f.format_tag = u16_at(24);
+        }
+        Ok(f)
+    }
+
+    /// Integer or float samples, an error for compressed formats.
+    pub fn sample_format(&self) -> Result<track::SampleFormat, Box<dyn Error>> {
+        match self.format_tag {
+            WAVE_FORMAT_PCM => Ok(track::SampleFormat::Int),
+            WAVE_FORMAT_IEEE_FLOAT => Ok(track::SampleFormat::Float),
+            t => Err(format!("Unsupported WAV format: {:#06x}.", t).into()),
+        }
     }
 }
 
@@ -45,26 +132,147 @@ impl Decoder for Wav {
         FORMAT_NAME
     }
     /// Create a track with as much information as you have from the file.
-    /// Wav files only provide basic format information, so really only
-    /// track::SampleFormat.
-    /// Thie means you need to set the title (and anything else for that matter
-    /// on your own.
-    // TODO(jdr) fill out the rest of the wave spec (float etc).
-    fn get_track(&mut self, r: impl Read + Seek) -> Result<Option<track::Track>, Box<dyn Error>> {
-        let wr = hound::WavReader::new(r)?;
-        let spec = wr.spec();
+    /// The title, artist, album and track number come from the INFO chunk,
+    /// if the file has one.
+    fn get_track(
+        &mut self,
+        mut r: impl Read + Seek,
+    ) -> Result<Option<track::Track>, Box<dyn Error>> {
+        let mut md = read_metadata(&mut r)?;
+        let wf = match md.chunks.iter().find(|c| c.id == FMT) {
+            Some(c) => WaveFormat::parse(&riff::read_data(&mut r, c)?)?,
+            None => return Err("No fmt chunk.".into()),
+        };
+        let data_size = match md.chunks.iter().find(|c| c.id == riff::DATA) {
+            Some(c) => c.size,
+            None => return Err("No data chunk.".into()),
+        };
         let mut tk = track::Track {
             // path: self.path.clone(),
             file_format: Some(FORMAT_NAME.to_string()),
             ..Default::default()
         };
         let f = track::PCMFormat {
-            sample_rate: spec.sample_rate,
-            channels: spec.channels as u8,
-            bits_per_sample: spec.bits_per_sample,
-            total_samples: wr.duration() as u64,
+            sample_rate: wf.sample_rate,
+            channels: wf.channels as u8,
+            bits_per_sample: wf.valid_bits,
+            total_samples: data_size / u64::from(wf.block_align.max(1)),
+            sample_format: wf.sample_format()?,
+            container_bits: wf.container_bits,
+            channel_mask: wf.channel_mask,
         };
         tk.format = Some(track::CodecFormat::PCM(f));
+
+        md.timestamp = md.bext.as_ref().and_then(|b| b.timestamp(wf.sample_rate));
+        tk.title = md.info("INAM").map(|s| s.to_string());
+        tk.artist = md.info("IART").map(|s| s.to_string());
+        tk.album = md.info("IPRD").map(|s| s.to_string());
+        tk.track_number = md.info("ITRK").and_then(|s| s.parse().ok());
+        tk.metadata = Some(track::FormatMetadata::Riff(md));
         Ok(Some(tk))
     }
 }
+
+/// Read the chunks of a WAV file and the metadata in them.
+/// The timestamp is left for the caller, it needs the sample rate.
+pub fn read_metadata(mut r: impl Read + Seek) -> Result<track::RiffMetadata, Box<dyn Error>> {
+    let (_, chunks) = riff::read_chunks(&mut r)?;
+    let mut md = track::RiffMetadata {
+        ..Default::default()
+    };
+    for c in &chunks {
+        match c.id.as_str() {
+            riff::LIST => {
+                let b = riff::read_data(&mut r, c)?;
+                if riff::list_type(&b) == riff::INFO.as_bytes() {
+                    md.info.extend(riff::parse_info(&b));
+                }
+            }
+            riff::BEXT => md.bext = riff::Bext::parse(&riff::read_data(&mut r, c)?),
+            riff::IXML => {
+                let b = riff::read_data(&mut r, c)?;
+                md.ixml = Some(riff::IXml {
+                    xml: String::from_utf8_lossy(&b)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                });
+            }
+            _ => (),
+        }
+    }
+    md.chunks = chunks;
+    Ok(md)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify() {
+        let mut b = [&b"RIFF"[..], &100u32.to_le_bytes(), b"WAVE", b"fmt "].concat();
+        match identify(&b) {
+            Some(FileFormat::WAV(w)) => assert_eq!((w.form.as_str(), w.riff_size), ("RIFF", 100)),
+            _ => panic!("not a WAV file"),
+        }
+        b[0..4].copy_from_slice(b"RF64");
+        assert!(identify(&b).is_none());
+
+        let mut b = [&b"BW64"[..], &[0xff; 4], b"WAVE", b"ds64", &[28, 0, 0, 0]].concat();
+        b.extend_from_slice(&5_000_000_000u64.to_le_bytes());
+        match identify(&b) {
+            Some(FileFormat::WAV(w)) => {
+                assert_eq!((w.form.as_str(), w.riff_size), ("BW64", 5_000_000_000))
+            }
+            _ => panic!("not a WAV file"),
+        }
+        assert!(identify(b"RIFF\0\0\0\0AVI ").is_none());
+    }
+
+    #[test]
+    fn test_wave_format() {
+        // 24 bit samples in 32 bits, 5.1 channels.
+        let mut b = vec![0xfe, 0xff, 6, 0];
+        b.extend_from_slice(&48_000u32.to_le_bytes());
+        b.extend_from_slice(&(48_000u32 * 24).to_le_bytes());
+        b.extend_from_slice(&[24, 0, 32, 0, 22, 0, 24, 0]);
+        b.extend_from_slice(&0x3fu32.to_le_bytes());
+        b.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0x10, 0]);
+        b.extend_from_slice(&[0x80, 0, 0, 0xaa, 0, 0x38, 0x9b, 0x71]);
+        let f = WaveFormat::parse(&b).unwrap();
+        assert_eq!(f.format_tag, WAVE_FORMAT_PCM);
+        assert_eq!((f.channels, f.sample_rate, f.block_align), (6, 48_000, 24));
+        assert_eq!((f.container_bits, f.valid_bits), (32, 24));
+        assert_eq!(f.channel_mask, Some(0x3f));
+        assert_eq!(f.sample_format().unwrap(), track::SampleFormat::Int);
+
+        let pcm = track::PCMFormat {
+            channel_mask: f.channel_mask,
+            ..Default::default()
+        };
+        assert_eq!(
+            pcm.speakers(),
+            vec![
+                "Front Left",
+                "Front Right",
+                "Front Center",
+                "LFE",
+                "Back Left",
+                "Back Right"
+            ]
+        );
+
+        // Plain float.
+        b[0] = 3;
+        b[1] = 0;
+        let f = WaveFormat::parse(&b[..16]).unwrap();
+        assert_eq!(f.sample_format().unwrap(), track::SampleFormat::Float);
+        assert_eq!(f.channel_mask, None);
+
+        // A-law and a truncated extensible chunk.
+        b[0] = 6;
+        assert!(WaveFormat::parse(&b).unwrap().sample_format().is_err());
+        b[0..2].copy_from_slice(&[0xfe, 0xff]);
+        assert!(WaveFormat::parse(&b[..18]).is_err());
+    }
+}